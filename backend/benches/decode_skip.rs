@@ -0,0 +1,25 @@
+//! Decode time with and without `DecodeSkip::NonReference`: `cargo bench --bench decode_skip`.
+//! Needs the FFmpeg libraries; `DECODE_BENCH_VIDEO` picks the video (default
+//! `data/video.mp4`), ideally high-frame-rate footage.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use open_ai_video_understanding::services::{bench_api, DecodeSkip};
+use std::path::PathBuf;
+
+fn decode_skip(c: &mut Criterion) {
+    let path: PathBuf = std::env::var("DECODE_BENCH_VIDEO")
+        .unwrap_or_else(|_| "data/video.mp4".into())
+        .into();
+    let mut group = c.benchmark_group("decode_skip");
+    group.sample_size(10);
+    let modes = [("off", DecodeSkip::Off), ("non_reference", DecodeSkip::NonReference)];
+    for (name, skip) in modes {
+        group.bench_function(name, |bench| {
+            bench.iter(|| bench_api::decode_frames(&path, skip).expect("decode failed"))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, decode_skip);
+criterion_main!(benches);
@@ -0,0 +1,98 @@
+//! Allocations of the selection loop with and without the feature pool:
+//! `cargo bench --bench feature_pool`
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use open_ai_video_understanding::services::bench_api::{self, FeaturePool};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Counts allocations (and reallocations) so the pool's effect shows up as a number
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+const CANDIDATES: usize = 1000;
+// Every this many candidates one is novel enough to become the reference
+const NOVEL_EVERY: usize = 10;
+const WIDTH: usize = 640;
+const HEIGHT: usize = 360;
+
+// Threshold selection over `CANDIDATES` frames, taking vectors from the pool and handing
+// them back the way `process_video` does; motion-sampled references are pooled copies
+fn select_pooled(pool: &mut FeaturePool, plane: &[u8]) -> f32 {
+    let (mut reference, _) = pool.compute(plane, WIDTH, WIDTH, HEIGHT);
+    let mut total = 0.0;
+    for i in 0..CANDIDATES {
+        let (feat, l2) = pool.compute(plane, WIDTH, WIDTH, HEIGHT);
+        total += bench_api::dot_f32(&reference, &feat) / l2;
+        if i % NOVEL_EVERY == 0 {
+            let copy = pool.copy_of(&feat);
+            pool.recycle(std::mem::replace(&mut reference, copy));
+        }
+        pool.recycle(feat);
+    }
+    pool.recycle(reference);
+    total
+}
+
+// The same loop with a new vector per feature, as before the pool
+fn select_unpooled(plane: &[u8]) -> f32 {
+    let mut reference = Vec::new();
+    bench_api::downscale_luma(plane, WIDTH, WIDTH, HEIGHT, &mut reference);
+    let mut total = 0.0;
+    for i in 0..CANDIDATES {
+        let mut feat = Vec::new();
+        let l2 = bench_api::downscale_luma(plane, WIDTH, WIDTH, HEIGHT, &mut feat);
+        total += bench_api::dot_f32(&reference, &feat) / l2;
+        if i % NOVEL_EVERY == 0 {
+            reference = feat.clone();
+        }
+    }
+    total
+}
+
+fn allocations_during(run: impl FnOnce() -> f32) -> usize {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    black_box(run());
+    ALLOCATIONS.load(Ordering::Relaxed) - before
+}
+
+fn feature_pool(c: &mut Criterion) {
+    let plane: Vec<u8> = (0..WIDTH * HEIGHT).map(|i| (i * 31 % 251) as u8).collect();
+    let mut pool = FeaturePool::default();
+    // Warm the pool, as a run does after its first few frames
+    select_pooled(&mut pool, &plane);
+    let pooled = allocations_during(|| select_pooled(&mut pool, &plane));
+    let unpooled = allocations_during(|| select_unpooled(&plane));
+    println!(
+        "allocations per {} candidates: pooled {}, unpooled {}",
+        CANDIDATES, pooled, unpooled
+    );
+
+    let mut group = c.benchmark_group("selection_loop");
+    group.bench_function("pooled", |bench| bench.iter(|| select_pooled(&mut pool, &plane)));
+    group.bench_function("unpooled", |bench| bench.iter(|| select_unpooled(&plane)));
+    group.finish();
+}
+
+criterion_group!(benches, feature_pool);
+criterion_main!(benches);
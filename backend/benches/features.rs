@@ -0,0 +1,38 @@
+//! Selection kernels, SIMD against their scalar versions: `cargo bench --bench features`
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use open_ai_video_understanding::services::bench_api;
+
+// Length of a luma feature (64x64)
+const FEATURE_LEN: usize = 64 * 64;
+
+fn dot_product(c: &mut Criterion) {
+    let a: Vec<f32> = (0..FEATURE_LEN).map(|i| (i % 255) as f32 / 255.0).collect();
+    let b: Vec<f32> = (0..FEATURE_LEN).map(|i| (i * 7 % 255) as f32 / 255.0).collect();
+    let mut group = c.benchmark_group("dot_f32");
+    group.bench_function("simd", |bench| {
+        bench.iter(|| bench_api::dot_f32(black_box(&a), black_box(&b)))
+    });
+    group.bench_function("scalar", |bench| {
+        bench.iter(|| bench_api::dot_f32_scalar(black_box(&a), black_box(&b)))
+    });
+    group.finish();
+}
+
+// A 1080p luma plane, the common case for `compute_feature_from_y`
+fn downscale(c: &mut Criterion) {
+    let (w, h) = (1920, 1080);
+    let plane: Vec<u8> = (0..w * h).map(|i| (i * 31 % 251) as u8).collect();
+    let mut feat = Vec::new();
+    let mut group = c.benchmark_group("downscale_luma");
+    group.bench_function("simd", |bench| {
+        bench.iter(|| bench_api::downscale_luma(black_box(&plane), w, w, h, &mut feat))
+    });
+    group.bench_function("scalar", |bench| {
+        bench.iter(|| bench_api::downscale_luma_scalar(black_box(&plane), w, w, h, &mut feat))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, dot_product, downscale);
+criterion_main!(benches);
@@ -1,21 +1,76 @@
 use axum::{extract::Json, response::IntoResponse};
-use axum::extract::Multipart;
+use axum::extract::{Extension, Multipart};
+use axum::extract::rejection::JsonRejection;
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::extract::{Path, Query};
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::Response;
+use base64::{engine::general_purpose, Engine as _};
 use serde_json::json;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use crate::services;
+use std::collections::BTreeMap;
+use std::convert::Infallible;
 use std::path::PathBuf;
+use std::time::Instant;
 use tokio::fs;
 use tokio::io::AsyncWriteExt;
-use tracing::info;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::StreamExt;
+use tracing::{info, warn, Instrument};
 
-/// Health check handler - returns server status
+/// Health check handler - returns server status and current load
 pub async fn health_check() -> Json<serde_json::Value> {
+    let load = services::load_snapshot();
     Json(json!({
         "status": "ok",
-        "message": "Server is running"
+        "message": "Server is running",
+        "accepting": load.accepting,
+        "load": load
     }))
 }
 
+#[derive(Deserialize)]
+pub struct DeepHealthQuery {
+    /// Make a trivial Gemini call (default true); false only checks the configuration
+    pub ping: Option<bool>,
+}
+
+/// Readiness beyond `/health`: whether Gemini is configured and answering and whether
+/// `data/` is writable. 503 when either is not.
+pub async fn deep_health_check(Query(query): Query<DeepHealthQuery>) -> Response {
+    let (gemini, data_dir_writable) = tokio::join!(
+        services::check_gemini(query.ping.unwrap_or(true)),
+        services::data_dir_writable()
+    );
+    let healthy = gemini.is_ok() && data_dir_writable;
+    let mut body = json!({
+        "status": if healthy { "ok" } else { "error" },
+        "gemini": if gemini.is_ok() { "ok" } else { "unreachable" },
+        "data_dir_writable": data_dir_writable
+    });
+    if let Err(e) = gemini {
+        warn!("Deep health check: Gemini unreachable: {:#}", e);
+        body["gemini_error"] = json!(format!("{:#}", e));
+    }
+    let status = if healthy {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(body)).into_response()
+}
+
+/// Prometheus scrape endpoint
+pub async fn metrics() -> Response {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        services::render_metrics(),
+    )
+        .into_response()
+}
 
 pub async fn test() -> Json<serde_json::Value> {
     Json(json!({
@@ -25,29 +80,1932 @@ pub async fn test() -> Json<serde_json::Value> {
 }
 
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize)]
 pub struct ProcessVideoRequest {
     pub video_path: String,
+    /// Pass the filename-derived title to the summary prompt as context (default false)
+    pub title_context: Option<bool>,
+    /// Max transcript characters per summary call before map-reduce kicks in
+    pub summary_chunk_chars: Option<usize>,
+    /// Max frames per summary call before map-reducing, > 0 (default: characters only)
+    pub summary_chunk_frames: Option<usize>,
+    /// Add the first round's chunk summaries, with their time ranges, as
+    /// `chunk_summaries` (default false; empty when the summary took a single call)
+    pub chunk_summaries: Option<bool>,
+    /// "compare" (default) or "reset" selection when the stream changes resolution
+    pub on_resolution_change: Option<services::ResolutionChange>,
+    /// Add a human-readable `selection_summary` explaining which frames were picked
+    pub explain_selection: Option<bool>,
+    /// "detailed" (default) or "alt_text" for one-sentence screen-reader descriptions
+    pub description_mode: Option<services::DescriptionMode>,
+    /// "off" (default) or "non_reference" to skip decoding B-frames on high-fps footage
+    pub decode_skip: Option<services::DecodeSkip>,
+    /// "adaptive" (default) picks the more novel frame of each pair of sample points;
+    /// "fixed" describes a frame at every sample point
+    pub selection_mode: Option<services::SelectionMode>,
+    /// Describe only sample points whose frame changed from the previous one by more than
+    /// this mean absolute luma difference, in (0, 1]; replaces `selection_mode`
+    pub motion_threshold: Option<f32>,
+    /// Retry with a demuxer guessed from magic bytes/extension when opening fails (default true)
+    pub demuxer_fallback: Option<bool>,
+    /// Per-request Gemini endpoint (Vertex project/region, auth mode; an allowlisted base URL)
+    pub gemini_endpoint: Option<services::GeminiEndpoint>,
+    /// Always describe the final frame (default true)
+    pub include_last_frame: Option<bool>,
+    /// "luma" (default) or "rgb" to compare frames by color as well as brightness
+    pub feature_mode: Option<services::FeatureMode>,
+    /// Split the video at hard cuts and add per-scene summaries as `scenes` (default false)
+    pub chapters: Option<bool>,
+    /// Add the distinct objects and people seen across frames as `entities` (default false)
+    pub entities: Option<bool>,
+    /// Group frames into titled topic chapters by description similarity and add them as
+    /// `chapters` (default false)
+    pub detect_chapters: Option<bool>,
+    /// Description similarity below which consecutive frames start a new chapter, in (0, 1)
+    pub chapter_similarity: Option<f32>,
+    /// Similarity below which consecutive frames start a new scene, in (0, 1)
+    pub scene_cut_similarity: Option<f32>,
+    /// Regex replacements applied in order to each frame description
+    pub description_rewrites: Option<Vec<RewriteRule>>,
+    /// Longest side (px) of the image sent to the model; smaller is cheaper. Unset, the
+    /// model gets the stored JPEG
+    pub llm_max_dim: Option<u32>,
+    /// Longest side (px) of the stored frame JPEG, whatever `llm_max_dim` is; defaults to
+    /// full resolution
+    pub store_max_dim: Option<u32>,
+    /// Approximate timestamps (seconds) to guarantee a described frame near
+    pub moments: Option<Vec<f64>>,
+    /// Seconds either side of each moment to search (default 2)
+    pub moment_tolerance_secs: Option<f64>,
+    /// Add per-phase wall-clock `timings` to the response (default false); see
+    /// `services::PhaseTimings` for what each phase covers
+    pub include_timings: Option<bool>,
+    /// Cache the frame prompt with Gemini once per video instead of resending it (default false)
+    pub prompt_cache: Option<bool>,
+    /// Crop black letterbox/pillarbox bars before selection and description (default false)
+    pub auto_crop: Option<bool>,
+    /// Write `data/{video_id}_manifest.json` describing the run (default true)
+    pub manifest: Option<bool>,
+    /// Classify each frame into these labels instead of describing it
+    pub labels: Option<Vec<String>>,
+    /// Add a content `fingerprint` of the video to the response (default false)
+    pub fingerprint: Option<bool>,
+    /// Model to retry a frame with when the primary call times out or is rate limited
+    pub fallback_model: Option<String>,
+    /// Deadline per primary frame call (default 30 with `fallback_model`, else
+    /// `GEMINI_CALL_TIMEOUT_SECS`); a frame past it gets an `error` instead of stalling the run
+    pub frame_timeout_secs: Option<f64>,
+    /// Fail the whole run once it has taken this long, cancelling outstanding frame calls
+    pub job_timeout_secs: Option<f64>,
+    /// Temperature-0 greedy decoding for frame and summary calls, for reproducible output
+    /// as far as the provider allows (default false)
+    pub deterministic: Option<bool>,
+    /// Sampling temperature of frame and summary calls, 0..=2; not with `deterministic`
+    pub temperature: Option<f32>,
+    /// Nucleus sampling cutoff of frame and summary calls, in (0, 1]; not with `deterministic`
+    pub top_p: Option<f32>,
+    /// Cap on the tokens of each frame and summary reply
+    pub max_output_tokens: Option<u32>,
+    /// Seconds between frames considered for description (default 0.25)
+    pub sample_interval_secs: Option<f64>,
+    /// Don't describe frames whose cosine similarity to the last described one exceeds
+    /// `1 - min_dissimilarity`, in (0, 1]
+    pub min_dissimilarity: Option<f32>,
+    /// Custom prompt for per-frame descriptions, e.g. for OCR of on-screen text; ignored
+    /// with `labels` (default: the prompt of `description_mode`)
+    pub frame_prompt: Option<String>,
+    /// Add each frame's JPEG as base64 `image_base64` to the records, for clients that
+    /// can't reach `/data` (default false)
+    pub include_images: Option<bool>,
+    /// Transcribe the audio track, add it as `transcript` and interleave it with the frames
+    /// in the summary (default false)
+    pub transcribe: Option<bool>,
+    /// Downscale frames wider than this (keeping aspect ratio) before they are encoded,
+    /// bounding the stored and the model's JPEG alike
+    pub max_frame_width: Option<u32>,
+    /// JPEG quality of encoded frames, clamped to 1..=100 (default 85)
+    pub jpeg_quality: Option<u8>,
+    /// Deprecated alias of `feature_mode`, which takes "grayscale" for `luma`
+    pub selection_metric: Option<services::FeatureMode>,
+    /// Style and length of `summary` (default: a 3-5 sentence narrative)
+    pub summary_options: Option<SummaryFormat>,
+    /// Narrow the sampling interval so at least this many frames are described
+    pub min_frames: Option<u64>,
+    /// Widen the sampling interval, and stop queueing, so at most this many frames are described
+    pub max_frames: Option<u64>,
+    /// Select and write frames without calling Gemini, to preview and tune selection;
+    /// records have empty descriptions and there is no summary (default false)
+    pub dry_run: Option<bool>,
+    /// Frames described at once for this request, >= 1 (default `LLM_MAX_CONCURRENCY`)
+    pub max_concurrency: Option<usize>,
+    /// Decode only keyframes and describe each of them instead of running selection;
+    /// much faster on long videos (default false)
+    pub keyframes_only: Option<bool>,
+    /// Embed frame descriptions so `POST /videos/{video_id}/search` can find frames by
+    /// meaning (default false)
+    pub embed: Option<bool>,
+    /// Send the previous selected frame with each frame and ask what changed (default false)
+    pub with_context_frame: Option<bool>,
+    /// Reuse descriptions of near-identical frames already described instead of calling
+    /// the model again (default false)
+    pub reuse_descriptions: Option<bool>,
+    /// Share `reuse_descriptions` hashes with the tenant's other runs that pass the same
+    /// scope, instead of only with runs of this video
+    pub description_cache_scope: Option<String>,
+    /// Blur faces in every frame before it is stored or sent to the model; needs
+    /// `FACE_DETECTOR_URL` (default false)
+    pub redact_faces: Option<bool>,
+    /// Describe up to this many frames in one Gemini call, to make fewer requests (default
+    /// one call per frame)
+    pub describe_batch_size: Option<usize>,
+    /// BCP-47 tag such as "ja" or "pt-BR" for descriptions and summary (default English)
+    pub language: Option<String>,
 }
 
-pub async fn process_video(Json(req): Json<ProcessVideoRequest>) -> impl IntoResponse {
-    match services::process_video(req.video_path).await {
-        Ok(records) => {
-            // Add a concise summary of the records
-            let summary = match services::summarize_records(&records).await {
-                Ok(s) => s,
-                Err(e) => format!("Failed to summarize: {}", e),
+#[derive(Serialize, Deserialize)]
+pub struct SummaryFormat {
+    /// "narrative" (default), "bullets" or "timeline"
+    pub style: Option<services::SummaryStyle>,
+    /// Most sentences (narrative), bullets or timeline lines, 1..=50
+    pub max_sentences: Option<u32>,
+    /// Leave frames the model was less confident about than this (0..=1) out of the summary
+    pub min_confidence: Option<f32>,
+}
+
+impl SummaryFormat {
+    // Adds problems under `summary_options.*`
+    fn validate(&self, errors: &mut FieldErrors) {
+        if let Some(n) = self.max_sentences {
+            if !(1..=MAX_SUMMARY_SENTENCES).contains(&n) {
+                errors.insert(
+                    "summary_options.max_sentences".into(),
+                    format!("max_sentences must be in 1..={}", MAX_SUMMARY_SENTENCES),
+                );
+            }
+        }
+        if let Some(c) = self.min_confidence {
+            if !(0.0..=1.0).contains(&c) {
+                errors.insert(
+                    "summary_options.min_confidence".into(),
+                    "min_confidence must be in 0..=1".into(),
+                );
+            }
+        }
+    }
+}
+
+fn validate_generation(
+    generation: &services::GenerationParams,
+    deterministic: bool,
+    errors: &mut FieldErrors,
+) {
+    if let Some(t) = generation.temperature {
+        if !(0.0..=services::MAX_TEMPERATURE).contains(&t) {
+            errors.insert(
+                "temperature".into(),
+                format!("temperature must be in 0..={}", services::MAX_TEMPERATURE),
+            );
+        }
+    }
+    if let Some(p) = generation.top_p {
+        if !(p > 0.0 && p <= 1.0) {
+            errors.insert("top_p".into(), "top_p must be in (0, 1]".into());
+        }
+    }
+    if generation.max_output_tokens == Some(0) {
+        errors.insert("max_output_tokens".into(), "max_output_tokens must be > 0".into());
+    }
+    // Greedy decoding fixes both
+    if deterministic {
+        for (field, set) in [
+            ("temperature", generation.temperature.is_some()),
+            ("top_p", generation.top_p.is_some()),
+        ] {
+            if set {
+                let problem = format!("{} can't be combined with deterministic", field);
+                errors.insert(field.into(), problem);
+            }
+        }
+    }
+}
+
+fn validate_language(language: Option<&str>, errors: &mut FieldErrors) {
+    if let Some(language) = language {
+        if !services::is_language_tag(language) {
+            errors.insert(
+                "language".into(),
+                format!("'{}' is not a BCP-47 language tag such as \"ja\" or \"pt-BR\"", language),
+            );
+        }
+    }
+}
+
+/// Largest accepted `summary_options.max_sentences`.
+const MAX_SUMMARY_SENTENCES: u32 = 50;
+
+/// Longest accepted `frame_prompt`, in characters.
+const MAX_FRAME_PROMPT_CHARS: usize = 4000;
+
+#[derive(Serialize, Deserialize)]
+pub struct RewriteRule {
+    pub pattern: String,
+    #[serde(default)]
+    pub replacement: String,
+}
+
+/// Per-field validation problems, keyed by the JSON field name.
+pub type FieldErrors = BTreeMap<String, String>;
+
+impl ProcessVideoRequest {
+    fn generation(&self) -> services::GenerationParams {
+        services::GenerationParams {
+            temperature: self.temperature,
+            top_p: self.top_p,
+            max_output_tokens: self.max_output_tokens,
+        }
+    }
+
+    /// Check values serde can't express as types; collects every problem instead of stopping at the first
+    pub fn validate(&self) -> Result<(), FieldErrors> {
+        let mut errors = FieldErrors::new();
+        if self.video_path.trim().is_empty() {
+            errors.insert("video_path".into(), "video_path must not be empty".into());
+        }
+        if self.summary_chunk_chars == Some(0) {
+            errors.insert("summary_chunk_chars".into(), "summary_chunk_chars must be > 0".into());
+        }
+        if self.summary_chunk_frames == Some(0) {
+            errors.insert("summary_chunk_frames".into(), "summary_chunk_frames must be > 0".into());
+        }
+        if self.max_concurrency == Some(0) {
+            errors.insert("max_concurrency".into(), "max_concurrency must be >= 1".into());
+        }
+        for (field, value) in [("min_frames", self.min_frames), ("max_frames", self.max_frames)] {
+            if value == Some(0) {
+                errors.insert(field.into(), format!("{} must be > 0", field));
+            }
+        }
+        if let (Some(min), Some(max)) = (self.min_frames, self.max_frames) {
+            if min > max {
+                errors.insert("min_frames".into(), "min_frames must not exceed max_frames".into());
+            }
+        }
+        if let Some(format) = &self.summary_options {
+            format.validate(&mut errors);
+        }
+        for (i, rule) in self.description_rewrites.iter().flatten().enumerate() {
+            if let Err(e) = services::DescriptionRewrite::new(&rule.pattern, "") {
+                errors.insert(format!("description_rewrites[{}].pattern", i), e.to_string());
+            }
+        }
+        for (field, dim) in [
+            ("llm_max_dim", self.llm_max_dim),
+            ("store_max_dim", self.store_max_dim),
+            ("max_frame_width", self.max_frame_width),
+        ] {
+            if dim == Some(0) {
+                errors.insert(field.into(), format!("{} must be > 0", field));
+            }
+        }
+        validate_language(self.language.as_deref(), &mut errors);
+        validate_generation(&self.generation(), self.deterministic.unwrap_or(false), &mut errors);
+        if let Some(t) = self.motion_threshold {
+            if !(t.is_finite() && t > 0.0 && t <= 1.0) {
+                errors.insert("motion_threshold".into(), "motion_threshold must be in (0, 1]".into());
+            }
+        }
+        if self.moments.iter().flatten().any(|t| !(t.is_finite() && *t >= 0.0)) {
+            errors.insert("moments".into(), "moments must be non-negative seconds".into());
+        }
+        if let Some(t) = self.moment_tolerance_secs {
+            if !(t.is_finite() && t > 0.0) {
+                errors.insert("moment_tolerance_secs".into(), "moment_tolerance_secs must be > 0".into());
+            }
+        }
+        if let Some(labels) = &self.labels {
+            let mut seen = std::collections::HashSet::new();
+            for (i, label) in labels.iter().enumerate() {
+                let problem = if label.trim().is_empty() {
+                    Some("label must not be empty")
+                } else if label.contains([',', ';', '\n']) {
+                    Some("label must not contain ',', ';' or line breaks")
+                } else if !seen.insert(label.to_lowercase()) {
+                    Some("duplicate label")
+                } else {
+                    None
+                };
+                if let Some(problem) = problem {
+                    errors.insert(format!("labels[{}]", i), problem.into());
+                }
+            }
+        }
+        if self.selection_metric.is_some() && self.feature_mode.is_some() {
+            errors.insert(
+                "selection_metric".into(),
+                "selection_metric is a deprecated alias of feature_mode; send only feature_mode"
+                    .into(),
+            );
+        }
+        if let Some(prompt) = &self.frame_prompt {
+            if prompt.trim().is_empty() {
+                errors.insert("frame_prompt".into(), "frame_prompt must not be empty".into());
+            } else if prompt.chars().count() > MAX_FRAME_PROMPT_CHARS {
+                errors.insert(
+                    "frame_prompt".into(),
+                    format!("frame_prompt must be at most {} characters", MAX_FRAME_PROMPT_CHARS),
+                );
+            }
+        }
+        if let Some(name) = &self.fallback_model {
+            if services::parse_model(name).is_none() {
+                errors.insert("fallback_model".into(), format!("unknown model '{}'", name));
+            }
+        }
+        if let Some(t) = self.frame_timeout_secs {
+            if !(t.is_finite() && t > 0.0) {
+                errors.insert("frame_timeout_secs".into(), "frame_timeout_secs must be > 0".into());
+            }
+        }
+        if let Some(t) = self.job_timeout_secs {
+            if !(t.is_finite() && t > 0.0) {
+                errors.insert("job_timeout_secs".into(), "job_timeout_secs must be > 0".into());
+            }
+        }
+        if let Some(t) = self.sample_interval_secs {
+            if !(t.is_finite() && t > 0.0) {
+                errors.insert(
+                    "sample_interval_secs".into(),
+                    "sample_interval_secs must be a positive number of seconds".into(),
+                );
+            }
+        }
+        if let Some(d) = self.min_dissimilarity {
+            if !(d.is_finite() && d > 0.0 && d <= 1.0) {
+                errors.insert(
+                    "min_dissimilarity".into(),
+                    "min_dissimilarity must be in (0, 1]".into(),
+                );
+            }
+        }
+        if let Some(size) = self.describe_batch_size {
+            let conflict = [
+                ("labels", self.labels.as_ref().is_some_and(|labels| !labels.is_empty())),
+                ("with_context_frame", self.with_context_frame == Some(true)),
+                ("prompt_cache", self.prompt_cache == Some(true)),
+                // Batch calls don't retry with the fallback model
+                ("fallback_model", self.fallback_model.is_some()),
+            ]
+            .into_iter()
+            .find_map(|(field, set)| set.then_some(field));
+            if size == 0 || size > services::MAX_DESCRIBE_BATCH_SIZE {
+                errors.insert(
+                    "describe_batch_size".into(),
+                    format!(
+                        "describe_batch_size must be in 1..={}",
+                        services::MAX_DESCRIBE_BATCH_SIZE
+                    ),
+                );
+            } else if let Some(field) = conflict.filter(|_| size > 1) {
+                errors.insert(
+                    "describe_batch_size".into(),
+                    format!("describe_batch_size can't be combined with {}", field),
+                );
+            }
+        }
+        if let Some(scope) = &self.description_cache_scope {
+            if self.reuse_descriptions != Some(true) {
+                errors.insert(
+                    "description_cache_scope".into(),
+                    "description_cache_scope needs reuse_descriptions".into(),
+                );
+            } else if scope.trim().is_empty() {
+                errors.insert(
+                    "description_cache_scope".into(),
+                    "description_cache_scope must not be empty".into(),
+                );
+            }
+        }
+        if self.redact_faces == Some(true) {
+            match services::HttpFaceDetector::from_env() {
+                Ok(Some(_)) => {}
+                Ok(None) => {
+                    errors.insert(
+                        "redact_faces".into(),
+                        "redact_faces needs a face detector; set FACE_DETECTOR_URL".into(),
+                    );
+                }
+                Err(e) => {
+                    errors.insert("redact_faces".into(), format!("{:#}", e));
+                }
+            }
+        }
+        for (field, similarity) in [
+            ("scene_cut_similarity", self.scene_cut_similarity),
+            ("chapter_similarity", self.chapter_similarity),
+        ] {
+            if let Some(s) = similarity {
+                if !(s.is_finite() && s > 0.0 && s < 1.0) {
+                    errors.insert(
+                        field.into(),
+                        format!("{} must be between 0 and 1 (exclusive)", field),
+                    );
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// 400 response in the usual `{"status":"error"}` shape plus a per-field `errors` map
+fn validation_error(errors: FieldErrors) -> Response {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(json!({
+            "status": "error",
+            "message": "Invalid request",
+            "errors": errors
+        })),
+    )
+        .into_response()
+}
+
+/// Turn axum's JSON rejection into field errors; serde reports the failing path for data errors
+fn rejection_errors(rejection: &JsonRejection) -> FieldErrors {
+    let mut errors = FieldErrors::new();
+    let JsonRejection::JsonDataError(err) = rejection else {
+        errors.insert("body".into(), rejection.body_text());
+        return errors;
+    };
+
+    // Walk the source chain to reach the serde_path_to_error wrapper axum uses internally
+    let mut source = std::error::Error::source(err);
+    while let Some(e) = source {
+        if let Some(path_err) = e.downcast_ref::<serde_path_to_error::Error<serde_json::Error>>() {
+            let message = path_err.inner().to_string();
+            let path = path_err.path().to_string();
+            // Missing/unknown fields are reported against the parent, the name is in backticks
+            let field = if path == "." {
+                message
+                    .split('`')
+                    .nth(1)
+                    .map(str::to_string)
+                    .unwrap_or_else(|| "body".to_string())
+            } else {
+                path
             };
-            Json(json!({
+            errors.insert(field, message);
+            return errors;
+        }
+        source = e.source();
+    }
+
+    errors.insert("body".into(), rejection.body_text());
+    errors
+}
+
+/// Tenant of a request, from its API key (see `routes::ApiKey`), for fair LLM scheduling
+/// and scoped description reuse. Without `API_KEYS` every request shares the default
+/// tenant, so no client can pick its own scheduling slot or another tenant's cache.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tenant(pub String);
+
+fn tenant(auth: &Option<Extension<Tenant>>) -> Option<String> {
+    auth.as_ref().map(|Extension(Tenant(tenant))| tenant.clone())
+}
+
+// Parse and validate a `ProcessVideoRequest` into pipeline options, or every field problem
+fn prepare_run(
+    tenant: Option<String>,
+    payload: Result<Json<ProcessVideoRequest>, JsonRejection>,
+) -> Result<(ProcessVideoRequest, services::ProcessOptions, services::SummaryOptions), FieldErrors> {
+    let req = match payload {
+        Ok(Json(req)) => req,
+        Err(rejection) => return Err(rejection_errors(&rejection)),
+    };
+    req.validate()?;
+    if req.selection_metric.is_some() {
+        warn!("selection_metric is deprecated; use feature_mode");
+    }
+    if req.description_cache_scope.is_some() && tenant.is_none() {
+        let mut errors = FieldErrors::new();
+        errors.insert(
+            "description_cache_scope".into(),
+            "needs an API key, whose tenant the shared descriptions are kept to".into(),
+        );
+        return Err(errors);
+    }
+
+    let gemini = match req.gemini_endpoint.as_ref().map(services::GeminiConfig::with_override) {
+        Some(Ok(gemini)) => Some(gemini),
+        Some(Err(e)) => {
+            let mut errors = FieldErrors::new();
+            errors.insert("gemini_endpoint".into(), format!("{:#}", e));
+            return Err(errors);
+        }
+        None => None,
+    };
+
+    let summary_options = services::SummaryOptions {
+        title: if req.title_context.unwrap_or(false) {
+            services::title_from_video_id(&services::video_id_from_path(req.video_path.as_ref()))
+        } else {
+            None
+        },
+        chunk_chars: req.summary_chunk_chars,
+        chunk_frames: req.summary_chunk_frames,
+        gemini: gemini.clone(),
+        deterministic: req.deterministic.unwrap_or(false),
+        generation: req.generation(),
+        // Filled in from the analysis once the audio is transcribed
+        transcript: Vec::new(),
+        style: req
+            .summary_options
+            .as_ref()
+            .and_then(|f| f.style)
+            .unwrap_or_default(),
+        max_sentences: req.summary_options.as_ref().and_then(|f| f.max_sentences),
+        summarizer: None,
+        language: req.language.clone(),
+        min_confidence: req.summary_options.as_ref().and_then(|f| f.min_confidence),
+        tenant: tenant.clone(),
+    };
+
+    let options = services::ProcessOptions {
+        resolution_change: req.on_resolution_change.unwrap_or_default(),
+        description_mode: req.description_mode.unwrap_or_default(),
+        decode_skip: req.decode_skip.unwrap_or_default(),
+        selection_mode: req.selection_mode.unwrap_or_default(),
+        motion_threshold: req.motion_threshold,
+        demuxer_fallback: req.demuxer_fallback.unwrap_or(true),
+        gemini,
+        include_last_frame: req.include_last_frame.unwrap_or(true),
+        prompt_cache: req.prompt_cache.unwrap_or(false),
+        auto_crop: req.auto_crop.unwrap_or(false),
+        fallback_model: req.fallback_model.clone(),
+        frame_timeout_secs: req.frame_timeout_secs,
+        job_timeout_secs: req.job_timeout_secs,
+        deterministic: req.deterministic.unwrap_or(false),
+        generation: req.generation(),
+        min_dissimilarity: req.min_dissimilarity,
+        frame_prompt: req.frame_prompt.clone(),
+        keep_images: req.include_images.unwrap_or(false),
+        transcribe: req.transcribe.unwrap_or(false),
+        jpeg_quality: req
+            .jpeg_quality
+            .unwrap_or(services::DEFAULT_JPEG_QUALITY)
+            .clamp(1, 100),
+        sample_interval_secs: req
+            .sample_interval_secs
+            .unwrap_or(services::DEFAULT_SAMPLE_INTERVAL_SECS),
+        labels: req
+            .labels
+            .iter()
+            .flatten()
+            .map(|label| label.trim().to_string())
+            .collect(),
+        feature_mode: req.feature_mode.or(req.selection_metric).unwrap_or_default(),
+        // Patterns were checked by `validate`
+        description_rewrites: req
+            .description_rewrites
+            .iter()
+            .flatten()
+            .filter_map(|rule| services::DescriptionRewrite::new(&rule.pattern, &rule.replacement).ok())
+            .collect(),
+        llm_max_dim: req.llm_max_dim,
+        store_max_dim: req.store_max_dim,
+        max_frame_width: req.max_frame_width,
+        moments: req.moments.clone().unwrap_or_default(),
+        moment_tolerance_secs: req
+            .moment_tolerance_secs
+            .unwrap_or(services::DEFAULT_MOMENT_TOLERANCE_SECS),
+        tenant,
+        control: None,
+        min_frames: req.min_frames,
+        max_frames: req.max_frames,
+        dry_run: req.dry_run.unwrap_or(false),
+        describer: None,
+        max_concurrency: req.max_concurrency,
+        keyframes_only: req.keyframes_only.unwrap_or(false),
+        embed_descriptions: req.embed.unwrap_or(false),
+        with_context_frame: req.with_context_frame.unwrap_or(false),
+        reuse_descriptions: req.reuse_descriptions.unwrap_or(false),
+        description_cache_scope: req.description_cache_scope.clone(),
+        redact_faces: req.redact_faces.unwrap_or(false),
+        face_detector: None,
+        describe_batch_size: req.describe_batch_size,
+        language: req.language.clone(),
+    };
+    Ok((req, options, summary_options))
+}
+
+// Status and message for a local `video_path` the API won't process, `None` if it's fine
+async fn video_path_problem(video_path: &str) -> Option<(StatusCode, String)> {
+    if services::is_remote_video(video_path) {
+        return None;
+    }
+    let e = services::check_video_path(video_path).await.err()?;
+    let status = match e {
+        services::VideoPathError::NotFound => StatusCode::NOT_FOUND,
+        services::VideoPathError::OutsideDataDir => StatusCode::FORBIDDEN,
+        services::VideoPathError::NotAVideo => StatusCode::UNPROCESSABLE_ENTITY,
+        services::VideoPathError::Unreadable(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    Some((status, format!("Cannot process video '{}': {}", video_path, e)))
+}
+
+async fn check_video_path(video_path: &str) -> Result<(), Response> {
+    match video_path_problem(video_path).await {
+        None => Ok(()),
+        Some((status, message)) => {
+            Err((status, Json(json!({ "status": "error", "message": message }))).into_response())
+        }
+    }
+}
+
+pub async fn process_video(
+    auth: Option<Extension<Tenant>>,
+    payload: Result<Json<ProcessVideoRequest>, JsonRejection>,
+) -> Response {
+    let (req, options, mut summary_options) = match prepare_run(tenant(&auth), payload) {
+        Ok(run) => run,
+        Err(errors) => return validation_error(errors),
+    };
+    if let Err(response) = check_video_path(&req.video_path).await {
+        return response;
+    }
+
+    let body = match services::process_video(req.video_path.clone(), &options, None).await {
+        Ok(analysis) if options.dry_run => Json(json!({
+            "status": "ok",
+            "dry_run": true,
+            "records": analysis.records,
+            "selection_summary": analysis.stats.explain(),
+            "metrics": analysis.metrics,
+            "video_info": analysis.video_info
+        })),
+        Ok(mut analysis) => {
+            let summary_started = Instant::now();
+            summary_options.transcript = analysis.transcript.clone();
+            // Add a concise summary of the records
+            let (summary, chunks) =
+                match services::summarize_records_chunked(&analysis.records, &summary_options).await {
+                    Ok(s) => (s.summary, s.chunks),
+                    Err(e) => (format!("Failed to summarize: {}", e), Vec::new()),
+                };
+            let mut body = json!({
                 "status": "ok",
-                "records": records,
-                "summary": summary
-            }))
+                "records": analysis.records,
+                "summary": summary,
+                "video_info": analysis.video_info
+            });
+            if req.chunk_summaries.unwrap_or(false) {
+                body["chunk_summaries"] = json!(chunks);
+            }
+            if req.transcribe.unwrap_or(false) {
+                body["transcript"] = json!(analysis.transcript);
+            }
+            if req.include_images.unwrap_or(false) {
+                for (value, record) in body["records"]
+                    .as_array_mut()
+                    .into_iter()
+                    .flatten()
+                    .zip(&mut analysis.records)
+                {
+                    // Free each image once it is encoded
+                    if let Some(bytes) = record.jpeg_bytes.take() {
+                        value["image_base64"] = json!(general_purpose::STANDARD.encode(bytes));
+                    }
+                }
+            }
+            if req.min_dissimilarity.is_some() {
+                body["near_duplicates_skipped"] = json!(analysis.stats.near_duplicates_skipped);
+            }
+            if req.explain_selection.unwrap_or(false) {
+                body["selection_summary"] = json!(analysis.stats.explain());
+            }
+            if req.chapters.unwrap_or(false) {
+                let cut = req
+                    .scene_cut_similarity
+                    .unwrap_or(services::DEFAULT_SCENE_CUT_SIMILARITY);
+                body["scenes"] = match services::summarize_scenes(&analysis.records, cut, &summary_options).await {
+                    Ok(scenes) => json!(scenes),
+                    Err(e) => {
+                        warn!("Scene summaries failed: {}", e);
+                        json!([])
+                    }
+                };
+            }
+            if req.detect_chapters.unwrap_or(false) {
+                let similarity = req
+                    .chapter_similarity
+                    .unwrap_or(services::DEFAULT_CHAPTER_SIMILARITY);
+                body["chapters"] = match services::detect_chapters(&analysis.records, similarity, &summary_options).await {
+                    Ok(chapters) => json!(chapters),
+                    Err(e) => {
+                        warn!("Chapter detection failed: {:#}", e);
+                        json!([])
+                    }
+                };
+            }
+            if req.entities.unwrap_or(false) {
+                body["entities"] = match services::extract_entities(&analysis.records, &summary_options).await {
+                    Ok(entities) => json!(entities),
+                    Err(e) => {
+                        warn!("Entity extraction failed: {:#}", e);
+                        json!([])
+                    }
+                };
+            }
+            analysis.timings.summary_ms = summary_started.elapsed().as_millis() as u64;
+            if req.include_timings.unwrap_or(false) {
+                body["timings"] = json!(analysis.timings);
+            }
+            body["metrics"] = json!(analysis.metrics);
+            if req.fingerprint.unwrap_or(false) {
+                body["fingerprint"] = json!(analysis.fingerprint);
+            }
+            if let Err(e) = services::store::save_analysis(
+                &analysis.video_id,
+                options.tenant.as_deref(),
+                &req.video_path,
+                &summary,
+                &analysis.records,
+            )
+            .await
+            {
+                warn!("Failed to store analysis of {}: {:#}", analysis.video_id, e);
+            }
+            if req.manifest.unwrap_or(true) {
+                let video_id = &analysis.video_id;
+                let manifest = run_manifest(video_id, &req, &analysis, &body).await;
+                let written = match manifest {
+                    Ok(manifest) => services::write_manifest(video_id, &manifest).await,
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = written {
+                    warn!("Failed to write manifest for {}: {:#}", video_id, e);
+                }
+            }
+            Json(body)
         },
         Err(e) => Json(json!({
             "status": "error",
             "message": format!("Failed to process video: {}", e)
         }))
+    };
+    body.into_response()
+}
+
+// Forwards each completed record to an SSE stream; unbounded so the pipeline never waits
+// on a slow client
+struct ChannelSink(mpsc::UnboundedSender<services::FrameRecord>);
+
+impl services::FrameSink for ChannelSink {
+    fn on_frame(&self, record: &services::FrameRecord) {
+        let record = services::FrameRecord {
+            jpeg_bytes: None,
+            ..record.clone()
+        };
+        // A closed channel only means the client went away
+        let _ = self.0.send(record);
+    }
+}
+
+/// `/process-video` as Server-Sent Events: a `frame` event per record as soon as it is
+/// described (completion order), then a final `summary` event, or an `error` event.
+pub async fn process_video_stream(
+    auth: Option<Extension<Tenant>>,
+    payload: Result<Json<ProcessVideoRequest>, JsonRejection>,
+) -> Response {
+    let (req, mut options, mut summary_options) = match prepare_run(tenant(&auth), payload) {
+        Ok(run) => run,
+        Err(errors) => return validation_error(errors),
+    };
+    if let Err(response) = check_video_path(&req.video_path).await {
+        return response;
+    }
+
+    // A client that disconnects stops the run, so frames nobody receives aren't described
+    let control = services::JobControl::new();
+    options.control = Some(control.clone());
+    let (record_tx, record_rx) = mpsc::unbounded_channel();
+    let (event_tx, event_rx) = mpsc::channel::<Event>(1);
+    tokio::spawn(async move {
+        let sink = ChannelSink(record_tx);
+        let result = {
+            let run = services::process_video(req.video_path, &options, Some(&sink));
+            tokio::pin!(run);
+            tokio::select! {
+                result = &mut run => result,
+                _ = sink.0.closed() => {
+                    info!("Client disconnected; stopping after the frame calls in flight");
+                    control.stop();
+                    run.await
+                }
+            }
+        };
+        // Nobody is left to receive a summary
+        if sink.0.is_closed() {
+            return;
+        }
+        let event = match result {
+            Ok(analysis) => {
+                summary_options.transcript = analysis.transcript;
+                let summary = if options.dry_run {
+                    String::new()
+                } else {
+                    match services::summarize_records(&analysis.records, &summary_options).await {
+                        Ok(s) => s,
+                        Err(e) => format!("Failed to summarize: {}", e),
+                    }
+                };
+                let mut data = json!({ "status": "ok", "summary": summary });
+                if !summary_options.transcript.is_empty() {
+                    data["transcript"] = json!(summary_options.transcript);
+                }
+                Event::default().event("summary").data(data.to_string())
+            }
+            Err(e) => {
+                warn!("Streamed processing failed: {}", e);
+                Event::default().event("error").data(
+                    json!({ "status": "error", "message": format!("Failed to process video: {}", e) })
+                        .to_string(),
+                )
+            }
+        };
+        // Close the frame stream first so the final event comes after every frame
+        drop(sink);
+        let _ = event_tx.send(event).await;
+    }
+    .in_current_span());
+
+    let frames = tokio_stream::wrappers::UnboundedReceiverStream::new(record_rx).map(|record| {
+        Event::default()
+            .event("frame")
+            .json_data(&record)
+            .unwrap_or_else(|e| Event::default().event("error").data(e.to_string()))
+    });
+    let stream = frames.chain(ReceiverStream::new(event_rx)).map(Ok::<_, Infallible>);
+
+    Sse::new(stream).keep_alive(KeepAlive::default()).into_response()
+}
+
+/// `/process-video` over a WebSocket. The first text message is the JSON request; then
+/// each record arrives as `{"type":"frame","record":...}` in completion order, and the
+/// client may send `{"action":"pause"|"resume"|"cancel"}` at any time. The socket closes
+/// after a final `{"type":"summary",...}` or `{"type":"error",...}` message.
+pub async fn process_video_ws(
+    auth: Option<Extension<Tenant>>,
+    ws: WebSocketUpgrade,
+) -> Response {
+    // The socket outlives the upgrade request, so its span is carried over by hand
+    let span = tracing::Span::current();
+    let tenant = tenant(&auth);
+    ws.on_upgrade(move |socket| run_ws(socket, tenant).instrument(span))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum WsAction {
+    Pause,
+    Resume,
+    Cancel,
+}
+
+#[derive(Deserialize)]
+struct WsControl {
+    action: WsAction,
+}
+
+async fn run_ws(mut socket: WebSocket, tenant: Option<String>) {
+    let text = loop {
+        match socket.recv().await {
+            Some(Ok(Message::Text(text))) => break text,
+            Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+            Some(Ok(_)) => continue,
+        }
+    };
+    let (req, mut options, mut summary_options) = match prepare_run(tenant, Json::from_bytes(text.as_bytes())) {
+        Ok(run) => run,
+        Err(errors) => {
+            let message = json!({
+                "type": "error",
+                "status": "error",
+                "message": "Invalid request",
+                "errors": errors
+            });
+            let _ = socket.send(Message::Text(message.to_string())).await;
+            let _ = socket.close().await;
+            return;
+        }
+    };
+    if let Some((_, problem)) = video_path_problem(&req.video_path).await {
+        let message = json!({ "type": "error", "status": "error", "message": problem });
+        let _ = socket.send(Message::Text(message.to_string())).await;
+        let _ = socket.close().await;
+        return;
+    }
+    let control = services::JobControl::new();
+    options.control = Some(control.clone());
+
+    let (record_tx, mut record_rx) = mpsc::unbounded_channel();
+    let mut job = tokio::spawn(async move {
+        let sink = ChannelSink(record_tx);
+        match services::process_video(req.video_path, &options, Some(&sink)).await {
+            Ok(analysis) => {
+                summary_options.transcript = analysis.transcript;
+                let summary = if options.dry_run {
+                    String::new()
+                } else {
+                    match services::summarize_records(&analysis.records, &summary_options).await {
+                        Ok(s) => s,
+                        Err(e) => format!("Failed to summarize: {}", e),
+                    }
+                };
+                let mut message = json!({ "type": "summary", "status": "ok", "summary": summary });
+                if !summary_options.transcript.is_empty() {
+                    message["transcript"] = json!(summary_options.transcript);
+                }
+                message
+            }
+            Err(e) => {
+                warn!("WebSocket processing failed: {}", e);
+                json!({
+                    "type": "error",
+                    "status": "error",
+                    "message": format!("Failed to process video: {}", e)
+                })
+            }
+        }
+    }
+    .in_current_span());
+
+    let frame = |record: services::FrameRecord| {
+        Message::Text(json!({ "type": "frame", "record": record }).to_string())
+    };
+    let last = loop {
+        tokio::select! {
+            Some(record) = record_rx.recv() => {
+                if socket.send(frame(record)).await.is_err() {
+                    break None;
+                }
+            }
+            incoming = socket.recv() => match incoming {
+                Some(Ok(Message::Text(text))) => {
+                    let reply = match serde_json::from_str::<WsControl>(&text) {
+                        Ok(WsControl { action: WsAction::Pause }) => {
+                            control.pause();
+                            json!({ "type": "control", "status": "ok", "action": "pause" })
+                        }
+                        Ok(WsControl { action: WsAction::Resume }) => {
+                            control.resume();
+                            json!({ "type": "control", "status": "ok", "action": "resume" })
+                        }
+                        Ok(WsControl { action: WsAction::Cancel }) => {
+                            // Resume too, so held frames don't keep the run from noticing
+                            control.resume();
+                            control.cancel();
+                            json!({ "type": "control", "status": "ok", "action": "cancel" })
+                        }
+                        Err(e) => json!({
+                            "type": "control",
+                            "status": "error",
+                            "message": format!("Invalid control message: {}", e)
+                        }),
+                    };
+                    if socket.send(Message::Text(reply.to_string())).await.is_err() {
+                        break None;
+                    }
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break None,
+                Some(Ok(_)) => {}
+            },
+            finished = &mut job => break Some(finished),
+        }
+    };
+
+    let Some(finished) = last else {
+        // The client went away; stop spending calls on it
+        control.cancel();
+        return;
+    };
+    // The sink is gone with the task, so this drains every record sent before the end
+    while let Ok(record) = record_rx.try_recv() {
+        if socket.send(frame(record)).await.is_err() {
+            return;
+        }
+    }
+    let message = finished.unwrap_or_else(|e| {
+        json!({
+            "type": "error",
+            "status": "error",
+            "message": format!("Failed to process video: {}", e)
+        })
+    });
+    let _ = socket.send(Message::Text(message.to_string())).await;
+    let _ = socket.close().await;
+}
+
+/// Most videos one `/process-batch` request may list.
+const MAX_BATCH_VIDEOS: usize = 100;
+
+/// Videos of one batch decoded at the same time; their frame calls share the global LLM limit.
+const BATCH_VIDEOS_IN_FLIGHT: usize = 4;
+
+#[derive(Deserialize)]
+pub struct BatchRequest {
+    pub video_paths: Vec<String>,
+}
+
+/// Process several videos with default options, returning each result (or error) keyed
+/// by its path. One video failing doesn't stop the others.
+pub async fn process_batch(
+    auth: Option<Extension<Tenant>>,
+    payload: Result<Json<BatchRequest>, JsonRejection>,
+) -> Response {
+    let req = match payload {
+        Ok(Json(req)) => req,
+        Err(rejection) => return validation_error(rejection_errors(&rejection)),
+    };
+    let mut errors = FieldErrors::new();
+    if req.video_paths.is_empty() {
+        errors.insert("video_paths".into(), "video_paths must not be empty".into());
+    } else if req.video_paths.len() > MAX_BATCH_VIDEOS {
+        errors.insert(
+            "video_paths".into(),
+            format!("video_paths must list at most {} videos", MAX_BATCH_VIDEOS),
+        );
+    } else if req.video_paths.iter().any(|path| path.trim().is_empty()) {
+        errors.insert("video_paths".into(), "video paths must not be empty".into());
+    }
+    if !errors.is_empty() {
+        return validation_error(errors);
+    }
+
+    let options = services::ProcessOptions {
+        tenant: tenant(&auth),
+        ..Default::default()
+    };
+    let slots = std::sync::Arc::new(tokio::sync::Semaphore::new(BATCH_VIDEOS_IN_FLIGHT));
+    let mut runs = tokio::task::JoinSet::new();
+    // A path listed twice is only processed once
+    let paths: std::collections::BTreeSet<String> = req.video_paths.into_iter().collect();
+    for path in paths {
+        let (options, slots) = (options.clone(), slots.clone());
+        runs.spawn(async move {
+            let _slot = slots.acquire_owned().await;
+            let result = batch_video(&path, &options).await;
+            (path, result)
+        }
+        .in_current_span());
+    }
+
+    let mut results = serde_json::Map::new();
+    while let Some(joined) = runs.join_next().await {
+        let (path, result) = match joined {
+            Ok(run) => run,
+            Err(e) => {
+                warn!("Batch video task failed: {}", e);
+                continue;
+            }
+        };
+        let value = match result {
+            Ok(value) => value,
+            Err(e) => {
+                warn!("Batch processing of {} failed: {:#}", path, e);
+                json!({ "status": "error", "message": format!("Failed to process video: {}", e) })
+            }
+        };
+        results.insert(path, value);
+    }
+    Json(json!({ "status": "ok", "results": results })).into_response()
+}
+
+// One `/process-batch` entry: records and summary, also saved to the store
+async fn batch_video(path: &str, options: &services::ProcessOptions) -> anyhow::Result<serde_json::Value> {
+    if let Some((_, problem)) = video_path_problem(path).await {
+        anyhow::bail!(problem);
+    }
+    let analysis = services::process_video(path, options, None).await?;
+    Ok(summarized_result(analysis, path, options.tenant.clone()).await)
+}
+
+// Records and default summary of a finished run, which is saved to the store
+async fn summarized_result(
+    analysis: services::VideoAnalysis,
+    source_path: &str,
+    tenant: Option<String>,
+) -> serde_json::Value {
+    let summary_options = services::SummaryOptions {
+        transcript: analysis.transcript.clone(),
+        tenant,
+        ..Default::default()
+    };
+    let summary = match services::summarize_records(&analysis.records, &summary_options).await {
+        Ok(s) => s,
+        Err(e) => format!("Failed to summarize: {}", e),
+    };
+    let saved = services::store::save_analysis(
+        &analysis.video_id,
+        summary_options.tenant.as_deref(),
+        source_path,
+        &summary,
+        &analysis.records,
+    );
+    if let Err(e) = saved.await {
+        warn!("Failed to store analysis of {}: {:#}", analysis.video_id, e);
+    }
+    json!({
+        "status": "ok",
+        "video_id": analysis.video_id,
+        "records": analysis.records,
+        "summary": summary
+    })
+}
+
+/// Process a video sent as the raw request body (`Content-Type: video/*`), e.g. piped from
+/// stdin with `curl --data-binary @-`. Default options, like `/process-batch`; the response
+/// is one of its result entries.
+pub async fn process_stream(
+    auth: Option<Extension<Tenant>>,
+    headers: HeaderMap,
+    body: axum::body::Body,
+) -> Response {
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("");
+    let Some(extension) = services::extension_for_content_type(content_type) else {
+        return (
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Json(json!({
+                "status": "error",
+                "message": "Content-Type must be video/*"
+            })),
+        )
+            .into_response();
+    };
+    // Look at the first bytes before anything is written: an empty body is the caller's
+    // mistake, not a failed run
+    let mut chunks = body.into_data_stream();
+    let first = loop {
+        match chunks.next().await {
+            Some(Ok(chunk)) if chunk.is_empty() => continue,
+            Some(Ok(chunk)) => break chunk,
+            Some(Err(e)) => {
+                return Json(json!({
+                    "status": "error",
+                    "message": format!("Failed to read request body: {}", e)
+                }))
+                .into_response();
+            }
+            None => {
+                let mut errors = FieldErrors::new();
+                errors.insert("body".into(), "request body must contain the video".into());
+                return validation_error(errors);
+            }
+        }
+    };
+
+    let options = services::ProcessOptions {
+        tenant: tenant(&auth),
+        ..Default::default()
+    };
+    let chunks = tokio_stream::once(Ok(first)).chain(chunks);
+    match services::process_video_chunks(chunks, extension, &options, None).await {
+        Ok(analysis) => {
+            let result = summarized_result(analysis, "request body", options.tenant.clone()).await;
+            Json(result).into_response()
+        }
+        Err(e) => Json(json!({
+            "status": "error",
+            "message": format!("Failed to process video: {}", e)
+        }))
+        .into_response(),
+    }
+}
+
+// One-stop description of a finished run; `body` is the response already built for it
+async fn run_manifest(
+    video_id: &str,
+    req: &ProcessVideoRequest,
+    analysis: &services::VideoAnalysis,
+    body: &serde_json::Value,
+) -> anyhow::Result<serde_json::Value> {
+    let mut records = Vec::with_capacity(analysis.records.len());
+    for record in &analysis.records {
+        let mut value = json!(record);
+        value["thumbnail_url"] = json!(services::thumbnail_url(record).await?);
+        records.push(value);
+    }
+    // The container's duration, fps, resolution and codec; `selection` has the decoded span
+    let mut video = json!(analysis.video_info);
+    video["frames_decoded"] = json!(analysis.stats.frames_decoded);
+    Ok(json!({
+        "video_id": video_id,
+        "video_path": req.video_path,
+        "created_at": chrono::Utc::now().to_rfc3339(),
+        "options": req,
+        "video": video,
+        "selection": analysis.stats,
+        "fingerprint": analysis.fingerprint,
+        "records": records,
+        "summary": body["summary"],
+        "scenes": body.get("scenes"),
+        "usage": {
+            "frames_described": analysis.records.len(),
+            "timings": analysis.timings
+        }
+    }))
+}
+
+/// Run manifest written by `/process-video`
+pub async fn manifest(Path(video_id): Path<String>) -> Response {
+    match services::load_manifest(&video_id).await {
+        Ok(Some(manifest)) => Json(manifest).into_response(),
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Json(json!({
+                "status": "error",
+                "message": format!("No manifest for video '{}'", video_id)
+            })),
+        )
+            .into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({
+                "status": "error",
+                "message": format!("Failed to load manifest: {}", e)
+            })),
+        )
+            .into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CancelRequest {
+    pub video_id: String,
+}
+
+/// Cancel a running `/process-video` (or stream) job of the caller's tenant; it then
+/// responds with an error
+pub async fn cancel_process_video(
+    auth: Option<Extension<Tenant>>,
+    payload: Result<Json<CancelRequest>, JsonRejection>,
+) -> Response {
+    let req = match payload {
+        Ok(Json(req)) => req,
+        Err(rejection) => return validation_error(rejection_errors(&rejection)),
+    };
+    if services::cancel_job(&req.video_id, tenant(&auth).as_deref()) {
+        Json(json!({ "status": "ok", "video_id": req.video_id })).into_response()
+    } else {
+        (
+            StatusCode::NOT_FOUND,
+            Json(json!({
+                "status": "error",
+                "message": format!("No running job for video '{}'", req.video_id)
+            })),
+        )
+            .into_response()
+    }
+}
+
+/// Counters of a running `/process-video` job of the caller's tenant, for progress bars
+pub async fn process_video_progress(
+    auth: Option<Extension<Tenant>>,
+    Path(video_id): Path<String>,
+) -> Response {
+    match services::job_progress(&video_id, tenant(&auth).as_deref()) {
+        Some(progress) => Json(json!(progress)).into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(json!({
+                "status": "error",
+                "message": format!("No running job for video '{}'", video_id)
+            })),
+        )
+            .into_response(),
+    }
+}
+
+/// Most videos `GET /videos` returns per page.
+const MAX_LIST_LIMIT: u32 = 500;
+
+#[derive(Deserialize)]
+pub struct ListQuery {
+    /// Page size (default 50, at most 500)
+    pub limit: Option<u32>,
+    pub offset: Option<u32>,
+}
+
+/// Stored analyses newest first, paginated with `?limit=` and `?offset=`
+pub async fn list_videos(Query(query): Query<ListQuery>) -> Response {
+    let limit = query.limit.unwrap_or(50);
+    if limit == 0 || limit > MAX_LIST_LIMIT {
+        let mut errors = FieldErrors::new();
+        errors.insert("limit".into(), format!("limit must be in 1..={}", MAX_LIST_LIMIT));
+        return validation_error(errors);
+    }
+    match services::store::list_videos(limit, query.offset.unwrap_or(0)).await {
+        Ok(videos) => Json(json!(videos)).into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({
+                "status": "error",
+                "message": format!("Failed to list videos: {}", e)
+            })),
+        )
+            .into_response(),
+    }
+}
+
+/// Stored summary and frames of a `/process-video` run
+pub async fn stored_video(
+    auth: Option<Extension<Tenant>>,
+    Path(video_id): Path<String>,
+) -> Response {
+    match services::store::load_video(&video_id).await {
+        // `progress` is set while a `STORE_PROGRESS` run is still adding frames
+        Ok(Some(video)) => Json(json!({
+            "status": "ok",
+            "video": video,
+            "progress": services::job_progress(&video_id, tenant(&auth).as_deref()),
+        }))
+        .into_response(),
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Json(json!({
+                "status": "error",
+                "message": format!("No stored analysis for video '{}'", video_id)
+            })),
+        )
+            .into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({
+                "status": "error",
+                "message": format!("Failed to load video: {}", e)
+            })),
+        )
+            .into_response(),
+    }
+}
+
+/// Validate `ProcessVideoRequest` options and estimate the work a run would do, without
+/// decoding frames or calling the model.
+pub async fn estimate(
+    auth: Option<Extension<Tenant>>,
+    payload: Result<Json<ProcessVideoRequest>, JsonRejection>,
+) -> Response {
+    let (req, options, _) = match prepare_run(tenant(&auth), payload) {
+        Ok(run) => run,
+        Err(errors) => return validation_error(errors),
+    };
+    if let Err(response) = check_video_path(&req.video_path).await {
+        return response;
+    }
+
+    let body = match services::estimate_video(req.video_path, &options).await {
+        Ok(estimate) => {
+            let mut body = json!(estimate);
+            body["status"] = json!("ok");
+            Json(body)
+        }
+        Err(e) => Json(json!({
+            "status": "error",
+            "message": format!("Failed to estimate video: {}", e)
+        }))
+    };
+    body.into_response()
+}
+
+#[derive(Deserialize)]
+pub struct MonitorQuery {
+    pub url: String,
+    pub novelty_threshold: Option<f32>,
+}
+
+impl MonitorQuery {
+    pub fn validate(&self) -> Result<(), FieldErrors> {
+        let mut errors = FieldErrors::new();
+        if !(self.url.starts_with("rtsp://") || self.url.starts_with("rtsps://")) {
+            errors.insert("url".into(), "url must be an rtsp:// or rtsps:// address".into());
+        }
+        if let Some(t) = self.novelty_threshold {
+            if !(t.is_finite() && t > 0.0 && t < 1.0) {
+                errors.insert(
+                    "novelty_threshold".into(),
+                    "novelty_threshold must be between 0 and 1 (exclusive)".into(),
+                );
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// Live monitor handler - streams a described frame over SSE every time the scene changes.
+/// Runs until the source ends or the client disconnects.
+pub async fn monitor_live(Query(query): Query<MonitorQuery>) -> Response {
+    if let Err(errors) = query.validate() {
+        return validation_error(errors);
+    }
+    let threshold = query
+        .novelty_threshold
+        .unwrap_or(services::DEFAULT_NOVELTY_THRESHOLD);
+
+    let (record_tx, record_rx) = mpsc::channel(16);
+    let (event_tx, event_rx) = mpsc::channel::<Event>(1);
+    tokio::spawn(async move {
+        if let Err(e) = services::monitor_live(query.url, threshold, record_tx).await {
+            warn!("Live monitor failed: {}", e);
+            let event = Event::default()
+                .event("error")
+                .data(json!({ "status": "error", "message": e.to_string() }).to_string());
+            let _ = event_tx.send(event).await;
+        }
+    }
+    .in_current_span());
+
+    let frames = ReceiverStream::new(record_rx).map(|record| {
+        Event::default()
+            .event("frame")
+            .json_data(&record)
+            .unwrap_or_else(|e| Event::default().event("error").data(e.to_string()))
+    });
+    let stream = frames.chain(ReceiverStream::new(event_rx)).map(Ok::<_, Infallible>);
+
+    Sse::new(stream).keep_alive(KeepAlive::default()).into_response()
+}
+
+/// Frame records of a processed video as CSV, for spreadsheets
+pub async fn records_csv(Path(video_id): Path<String>) -> Response {
+    let csv = match services::load_records(&video_id).await {
+        Ok(Some(records)) => services::records_to_csv(&records).await.map(Some),
+        other => other.map(|_| None),
+    };
+    match csv {
+        Ok(Some(csv)) => (
+            [
+                (header::CONTENT_TYPE, "text/csv; charset=utf-8".to_string()),
+                (
+                    header::CONTENT_DISPOSITION,
+                    format!("attachment; filename=\"{}_records.csv\"", video_id),
+                ),
+            ],
+            csv,
+        )
+            .into_response(),
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Json(json!({
+                "status": "error",
+                "message": format!("No records for video '{}'", video_id)
+            })),
+        )
+            .into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({
+                "status": "error",
+                "message": format!("Failed to load records: {}", e)
+            })),
+        )
+            .into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ContactSheetQuery {
+    /// Tiles per row (default 6, at most 32)
+    pub columns: Option<u32>,
+}
+
+const MAX_CONTACT_SHEET_COLUMNS: u32 = 32;
+
+/// Frame images of a processed video tiled into one JPEG, e.g. for a scrubbable timeline
+pub async fn contact_sheet(
+    Path(video_id): Path<String>,
+    Query(query): Query<ContactSheetQuery>,
+) -> Response {
+    let columns = query.columns.unwrap_or(services::DEFAULT_CONTACT_SHEET_COLUMNS);
+    if columns == 0 || columns > MAX_CONTACT_SHEET_COLUMNS {
+        let mut errors = FieldErrors::new();
+        errors.insert(
+            "columns".into(),
+            format!("columns must be in 1..={}", MAX_CONTACT_SHEET_COLUMNS),
+        );
+        return validation_error(errors);
+    }
+    let records = match services::load_records(&video_id).await {
+        Ok(Some(records)) if !records.is_empty() => records,
+        Ok(_) => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(json!({
+                    "status": "error",
+                    "message": format!("No records for video '{}'", video_id)
+                })),
+            )
+                .into_response()
+        }
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({
+                    "status": "error",
+                    "message": format!("Failed to load records: {}", e)
+                })),
+            )
+                .into_response()
+        }
+    };
+    let images = services::load_frame_images(&records).await;
+    let sheet =
+        tokio::task::spawn_blocking(move || services::build_contact_sheet(&images, columns))
+            .await
+            .map_err(anyhow::Error::from)
+            .and_then(|sheet| sheet);
+    match sheet {
+        Ok(jpeg) => ([(header::CONTENT_TYPE, "image/jpeg")], jpeg).into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({
+                "status": "error",
+                "message": format!("Failed to build contact sheet: {}", e)
+            })),
+        )
+            .into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct SearchRequest {
+    pub query: String,
+    /// Frames to return, 1..=100 (default 5)
+    pub top_k: Option<usize>,
+}
+
+const DEFAULT_SEARCH_TOP_K: usize = 5;
+const MAX_SEARCH_TOP_K: usize = 100;
+
+/// Stored frames of a video ranked by how well their descriptions match `query`
+pub async fn search_video(
+    Path(video_id): Path<String>,
+    payload: Result<Json<SearchRequest>, JsonRejection>,
+) -> Response {
+    let req = match payload {
+        Ok(Json(req)) => req,
+        Err(rejection) => return validation_error(rejection_errors(&rejection)),
+    };
+    let mut errors = FieldErrors::new();
+    if req.query.trim().is_empty() {
+        errors.insert("query".into(), "query must not be empty".into());
+    }
+    let top_k = req.top_k.unwrap_or(DEFAULT_SEARCH_TOP_K);
+    if !(1..=MAX_SEARCH_TOP_K).contains(&top_k) {
+        errors.insert("top_k".into(), format!("top_k must be between 1 and {}", MAX_SEARCH_TOP_K));
+    }
+    if !errors.is_empty() {
+        return validation_error(errors);
+    }
+
+    match services::search_frames(&video_id, req.query.trim(), top_k).await {
+        Ok(Some(results)) => Json(json!({
+            "status": "ok",
+            "video_id": video_id,
+            "results": results
+        }))
+        .into_response(),
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Json(json!({
+                "status": "error",
+                "message": format!(
+                    "No embeddings stored for video '{}'; process it with \"embed\": true",
+                    video_id
+                )
+            })),
+        )
+            .into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({
+                "status": "error",
+                "message": format!("Search failed: {:#}", e)
+            })),
+        )
+            .into_response(),
+    }
+}
+
+/// Body of `POST /videos/{video_id}/resummarize`; the summary fields of `ProcessVideoRequest`.
+#[derive(Deserialize)]
+pub struct ResummarizeRequest {
+    /// Style, length and confidence cutoff of the new summary
+    pub summary_options: Option<SummaryFormat>,
+    pub language: Option<String>,
+    pub summary_chunk_chars: Option<usize>,
+    pub summary_chunk_frames: Option<usize>,
+    pub title_context: Option<bool>,
+    pub deterministic: Option<bool>,
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    pub max_output_tokens: Option<u32>,
+}
+
+/// Summarize a stored video's frame descriptions again, without reprocessing the video
+pub async fn resummarize_video(
+    Path(video_id): Path<String>,
+    auth: Option<Extension<Tenant>>,
+    payload: Result<Json<ResummarizeRequest>, JsonRejection>,
+) -> Response {
+    let req = match payload {
+        Ok(Json(req)) => req,
+        Err(rejection) => return validation_error(rejection_errors(&rejection)),
+    };
+    let mut errors = FieldErrors::new();
+    if let Some(format) = &req.summary_options {
+        format.validate(&mut errors);
+    }
+    validate_language(req.language.as_deref(), &mut errors);
+    let generation = services::GenerationParams {
+        temperature: req.temperature,
+        top_p: req.top_p,
+        max_output_tokens: req.max_output_tokens,
+    };
+    validate_generation(&generation, req.deterministic.unwrap_or(false), &mut errors);
+    for (field, value) in [
+        ("summary_chunk_chars", req.summary_chunk_chars),
+        ("summary_chunk_frames", req.summary_chunk_frames),
+    ] {
+        if value == Some(0) {
+            errors.insert(field.into(), format!("{} must be > 0", field));
+        }
+    }
+    if !errors.is_empty() {
+        return validation_error(errors);
+    }
+
+    let format = req.summary_options.as_ref();
+    let options = services::SummaryOptions {
+        title: if req.title_context.unwrap_or(false) {
+            services::title_from_video_id(&video_id)
+        } else {
+            None
+        },
+        chunk_chars: req.summary_chunk_chars,
+        chunk_frames: req.summary_chunk_frames,
+        deterministic: req.deterministic.unwrap_or(false),
+        generation,
+        style: format.and_then(|f| f.style).unwrap_or_default(),
+        max_sentences: format.and_then(|f| f.max_sentences),
+        language: req.language.clone(),
+        min_confidence: format.and_then(|f| f.min_confidence),
+        tenant: tenant(&auth),
+        ..Default::default()
+    };
+    match services::resummarize_video(&video_id, &options).await {
+        Ok(Some(summary)) => Json(json!({
+            "status": "ok",
+            "video_id": video_id,
+            "summary": summary
+        }))
+        .into_response(),
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Json(json!({
+                "status": "error",
+                "message": format!("No stored analysis for video '{}'", video_id)
+            })),
+        )
+            .into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({
+                "status": "error",
+                "message": format!("Failed to summarize: {:#}", e)
+            })),
+        )
+            .into_response(),
+    }
+}
+
+/// Delete a video's files in `data/` and its stored analysis
+pub async fn delete_video(
+    auth: Option<Extension<Tenant>>,
+    Path(video_id): Path<String>,
+) -> Response {
+    if services::job_progress(&video_id, tenant(&auth).as_deref()).is_some() {
+        return (
+            StatusCode::CONFLICT,
+            Json(json!({
+                "status": "error",
+                "message": format!("Video '{}' is being processed; cancel it first", video_id)
+            })),
+        )
+            .into_response();
+    }
+    match services::delete_video(&video_id, tenant(&auth).as_deref()).await {
+        Ok(true) => Json(json!({ "status": "ok", "video_id": video_id })).into_response(),
+        Ok(false) => (
+            StatusCode::NOT_FOUND,
+            Json(json!({
+                "status": "error",
+                "message": format!("No files for video '{}'", video_id)
+            })),
+        )
+            .into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({
+                "status": "error",
+                "message": format!("Failed to delete video: {:#}", e)
+            })),
+        )
+            .into_response(),
+    }
+}
+
+/// Frame descriptions of a processed video as a WebVTT subtitle track
+pub async fn captions_vtt(Path(video_id): Path<String>) -> Response {
+    captions(&video_id, "text/vtt; charset=utf-8", services::to_webvtt).await
+}
+
+/// Frame descriptions of a processed video as SRT subtitles
+pub async fn captions_srt(Path(video_id): Path<String>) -> Response {
+    captions(&video_id, "application/x-subrip; charset=utf-8", services::to_srt).await
+}
+
+async fn captions(
+    video_id: &str,
+    content_type: &'static str,
+    render: fn(&[services::FrameRecord]) -> String,
+) -> Response {
+    match services::load_records(video_id).await {
+        Ok(Some(records)) => ([(header::CONTENT_TYPE, content_type)], render(&records)).into_response(),
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Json(json!({
+                "status": "error",
+                "message": format!("No records for video '{}'", video_id)
+            })),
+        )
+            .into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({
+                "status": "error",
+                "message": format!("Failed to load records: {}", e)
+            })),
+        )
+            .into_response(),
+    }
+}
+
+/// Most images one `/process-image` request may describe
+const MAX_IMAGES_PER_REQUEST: usize = 32;
+
+/// Describe uploaded images (PNG, JPEG, ...) sent as `image` multipart fields, with an
+/// optional `frame_prompt` text field. One image is answered with its description; several
+/// with `images`, one description or error per image in upload order. The request fails
+/// with a 500 when no image could be described.
+pub async fn process_image(auth: Option<Extension<Tenant>>, mut multipart: Multipart) -> Response {
+    let bad_request = |message: String| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "status": "error", "message": message })),
+        )
+            .into_response()
+    };
+    let mut images = Vec::new();
+    let mut frame_prompt = None;
+    loop {
+        let field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(e) => return bad_request(format!("Failed to parse multipart data: {}", e)),
+        };
+        let is_prompt = match field.name() {
+            Some("frame_prompt") => true,
+            Some("image") => false,
+            name => {
+                return bad_request(format!(
+                    "Unexpected field '{}'; send images as 'image' fields",
+                    name.unwrap_or("")
+                ))
+            }
+        };
+        let data = match field.bytes().await {
+            Ok(data) => data,
+            Err(e) => return bad_request(format!("Failed to read file data: {}", e)),
+        };
+        if is_prompt {
+            let prompt = String::from_utf8_lossy(&data).trim().to_string();
+            if prompt.chars().count() > MAX_FRAME_PROMPT_CHARS {
+                return bad_request(format!(
+                    "frame_prompt must be at most {} characters",
+                    MAX_FRAME_PROMPT_CHARS
+                ));
+            }
+            frame_prompt = Some(prompt).filter(|p| !p.is_empty());
+        } else if images.len() == MAX_IMAGES_PER_REQUEST {
+            return bad_request(format!("At most {} images per request", MAX_IMAGES_PER_REQUEST));
+        } else {
+            images.push(data.to_vec());
+        }
+    }
+    if images.is_empty() {
+        return bad_request("No image provided".to_string());
+    }
+
+    let options = services::ProcessOptions {
+        frame_prompt,
+        tenant: tenant(&auth),
+        ..Default::default()
+    };
+    let image_body = |result: anyhow::Result<services::FrameDescription>| match result {
+        Ok(described) => {
+            let mut body = json!(described);
+            body["status"] = json!("ok");
+            body
+        }
+        Err(e) => json!({
+            "status": "error",
+            "message": format!("Failed to describe image: {:#}", e)
+        }),
+    };
+    if images.len() == 1 {
+        let image = images.pop().unwrap_or_default();
+        let result = services::describe_image(image, &options).await;
+        let status = match result {
+            Ok(_) => StatusCode::OK,
+            Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        return (status, Json(image_body(result))).into_response();
+    }
+
+    // Described concurrently, within the shared LLM concurrency like video frames
+    let count = images.len();
+    let mut tasks = tokio::task::JoinSet::new();
+    for (index, image) in images.into_iter().enumerate() {
+        let options = options.clone();
+        tasks.spawn(
+            async move { (index, services::describe_image(image, &options).await) }
+                .in_current_span(),
+        );
+    }
+    let mut described: Vec<serde_json::Value> = vec![serde_json::Value::Null; count];
+    while let Some(joined) = tasks.join_next().await {
+        match joined {
+            Ok((index, result)) => described[index] = image_body(result),
+            Err(e) => warn!("Image description task failed: {}", e),
+        }
+    }
+    for entry in described.iter_mut().filter(|entry| entry.is_null()) {
+        *entry = image_body(Err(anyhow::anyhow!("description task failed")));
+    }
+    if described.iter().all(|entry| entry["status"] == "error") {
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({
+                "status": "error",
+                "message": "Failed to describe any image",
+                "images": described
+            })),
+        )
+            .into_response();
+    }
+    Json(json!({ "status": "ok", "images": described })).into_response()
+}
+
+/// Final path component of a client-supplied filename, with everything but ASCII
+/// letters, digits, '-', '_' and '.' replaced by '_'. The name must end in a video
+/// extension, since the upload is only useful to `/process-video`.
+fn sanitize_upload_filename(raw: &str) -> Result<String, String> {
+    // Clients on Windows may send either separator
+    let base = raw.rsplit(['/', '\\']).next().unwrap_or("");
+    let cleaned: String = base
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') { c } else { '_' })
+        .collect();
+    // No hidden files, and no "." or ".." left over
+    let cleaned = cleaned.trim_start_matches('.');
+    match cleaned.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() && services::is_video_extension(ext) => {
+            Ok(cleaned.to_string())
+        }
+        _ => Err(format!("'{}' is not a supported video file name", raw)),
     }
 }
 
@@ -64,70 +2022,442 @@ pub async fn upload_video(mut multipart: Multipart) -> impl IntoResponse {
         }));
     }
 
+    let mut field = match multipart.next_field().await {
+        Ok(Some(field)) => field,
+        Ok(None) => {
+            return Json(json!({
+                "status": "error",
+                "message": "No file provided"
+            }));
+        }
+        Err(e) => {
+            return Json(json!({
+                "status": "error",
+                "message": format!("Failed to parse multipart data: {}", e)
+            }));
+        }
+    };
+
+    let name = field.name().unwrap_or("").to_string();
+    let filename = match sanitize_upload_filename(field.file_name().unwrap_or("video.mp4")) {
+        Ok(filename) => filename,
+        Err(message) => {
+            return Json(json!({
+                "status": "error",
+                "message": message
+            }));
+        }
+    };
+    
+    info!("Processing field: {}, filename: {}", name, filename);
+    
+    // Generate unique filename with timestamp
+    let unique_filename = format!("{}_{}", 
+        chrono::Utc::now().timestamp_millis(), 
+        filename
+    );
+    let file_path = data_dir.join(&unique_filename);
+    
+    // Write chunks as they arrive so a large upload never sits in memory
+    let mut file = match fs::File::create(&file_path).await {
+        Ok(file) => file,
+        Err(e) => {
+            return Json(json!({
+                "status": "error",
+                "message": format!("Failed to create file: {}", e)
+            }));
+        }
+    };
     loop {
-        let field = match multipart.next_field().await {
-            Ok(Some(field)) => field,
+        let failure = match field.chunk().await {
+            Ok(Some(chunk)) => match file.write_all(&chunk).await {
+                Ok(()) => continue,
+                Err(e) => format!("Failed to write file: {}", e),
+            },
             Ok(None) => break,
-            Err(e) => {
-                return Json(json!({
-                    "status": "error",
-                    "message": format!("Failed to parse multipart data: {}", e)
-                }));
-            }
+            Err(e) => format!("Failed to read file data: {}", e),
         };
+        // Don't leave a truncated video behind
+        drop(file);
+        let _ = fs::remove_file(&file_path).await;
+        return Json(json!({
+            "status": "error",
+            "message": failure
+        }));
+    }
+    if let Err(e) = file.flush().await {
+        return Json(json!({
+            "status": "error",
+            "message": format!("Failed to write file: {}", e)
+        }));
+    }
+    info!("Saved video to: {:?}", file_path);
+    
+    // Return the path for processing
+    Json(json!({
+        "status": "ok",
+        "message": "File uploaded successfully",
+        "video_path": file_path.to_string_lossy().to_string()
+    }))
+}
 
-        let name = field.name().unwrap_or("").to_string();
-        let filename = field.file_name().unwrap_or("video.mp4").to_string();
-        
-        info!("Processing field: {}, filename: {}", name, filename);
-        
-        // Generate unique filename with timestamp
-        let unique_filename = format!("{}_{}", 
-            chrono::Utc::now().timestamp_millis(), 
-            filename
-        );
-        let file_path = data_dir.join(&unique_filename);
-        
-        // Read the file data
-        let data = match field.bytes().await {
-            Ok(bytes) => bytes,
-            Err(e) => {
-                return Json(json!({
-                    "status": "error",
-                    "message": format!("Failed to read file data: {}", e)
-                }));
-            }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(body: serde_json::Value) -> Result<Json<ProcessVideoRequest>, JsonRejection> {
+        Ok(Json(serde_json::from_value(body).unwrap()))
+    }
+
+    // Status and JSON body of one request through `app`
+    async fn call(
+        app: axum::Router,
+        request: axum::http::Request<axum::body::Body>,
+    ) -> (StatusCode, serde_json::Value) {
+        use tower::ServiceExt;
+
+        let response = app.oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), 1 << 20).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    fn post(
+        uri: &str,
+        content_type: &str,
+        body: impl Into<axum::body::Body>,
+    ) -> axum::http::Request<axum::body::Body> {
+        axum::http::Request::post(uri)
+            .header(header::CONTENT_TYPE, content_type)
+            .body(body.into())
+            .unwrap()
+    }
+
+    #[test]
+    fn selection_metric_is_an_alias_of_feature_mode() {
+        let mode = |body: serde_json::Value| {
+            let Ok((_, options, _)) = prepare_run(None, request(body)) else {
+                panic!("a single selector is accepted");
+            };
+            options.feature_mode
         };
-        
-        // Write to file
-        match fs::File::create(&file_path).await {
-            Ok(mut file) => {
-                if let Err(e) = file.write_all(&data).await {
-                    return Json(json!({
-                        "status": "error",
-                        "message": format!("Failed to write file: {}", e)
-                    }));
-                }
-                info!("Saved video to: {:?}", file_path);
-                
-                // Return the path for processing
-                return Json(json!({
-                    "status": "ok",
-                    "message": "File uploaded successfully",
-                    "video_path": file_path.to_string_lossy().to_string()
-                }));
-            },
-            Err(e) => {
-                return Json(json!({
-                    "status": "error",
-                    "message": format!("Failed to create file: {}", e)
-                }));
+        let path = "data/video.mp4";
+        let histogram = json!({ "video_path": path, "selection_metric": "histogram" });
+        assert_eq!(mode(histogram), services::FeatureMode::Histogram);
+        let grayscale = json!({ "video_path": path, "selection_metric": "grayscale" });
+        assert_eq!(mode(grayscale), services::FeatureMode::Luma);
+        let rgb = json!({ "video_path": path, "feature_mode": "rgb" });
+        assert_eq!(mode(rgb), services::FeatureMode::Rgb);
+
+        let both =
+            json!({ "video_path": path, "selection_metric": "histogram", "feature_mode": "rgb" });
+        let errors = prepare_run(None, request(both)).err().unwrap();
+        assert!(errors.contains_key("selection_metric"));
+        let unknown = json!({ "video_path": path, "selection_metric": "edges" });
+        assert!(serde_json::from_value::<ProcessVideoRequest>(unknown).is_err());
+    }
+
+    #[test]
+    fn max_frame_width_stays_apart_from_the_dimension_bounds() {
+        let path = "data/video.mp4";
+        let body = json!({ "video_path": path, "max_frame_width": 640, "llm_max_dim": 512 });
+        let Ok((_, options, _)) = prepare_run(None, request(body)) else {
+            panic!("max_frame_width is accepted with llm_max_dim");
+        };
+        assert_eq!(options.max_frame_width, Some(640));
+        assert_eq!((options.llm_max_dim, options.store_max_dim), (Some(512), None));
+    }
+
+    #[test]
+    fn describe_batch_size_rejects_fallback_model() {
+        let body = json!({
+            "video_path": "data/video.mp4",
+            "describe_batch_size": 4,
+            "fallback_model": "gemini-2.5-flash-lite"
+        });
+        let errors = prepare_run(None, request(body)).err().unwrap();
+        assert!(errors["describe_batch_size"].contains("fallback_model"));
+    }
+
+    #[test]
+    fn description_cache_scope_needs_an_authenticated_tenant() {
+        let body = json!({
+            "video_path": "data/video.mp4",
+            "reuse_descriptions": true,
+            "description_cache_scope": "slides"
+        });
+        let errors = prepare_run(None, request(body.clone())).err().unwrap();
+        assert!(errors.contains_key("description_cache_scope"));
+
+        let Ok((_, options, summary_options)) = prepare_run(Some("acme".to_string()), request(body))
+        else {
+            panic!("a tenant's cache scope is accepted");
+        };
+        assert_eq!(options.tenant.as_deref(), Some("acme"));
+        assert_eq!(summary_options.tenant.as_deref(), Some("acme"));
+    }
+
+    // The endpoints under test, without the API key and rate-limit layers
+    fn app() -> axum::Router {
+        use axum::routing::{delete, post};
+
+        axum::Router::new()
+            .route("/videos/:id", delete(delete_video))
+            .route("/process-video", post(process_video))
+            .route("/process-video-stream", post(process_video_stream))
+            .route("/process-batch", post(process_batch))
+            .route("/process-stream", post(process_stream))
+            .route("/process-image", post(process_image))
+            .route("/estimate", post(estimate))
+    }
+
+    fn post_json(uri: &str, body: serde_json::Value) -> axum::http::Request<axum::body::Body> {
+        post(uri, "application/json", body.to_string())
+    }
+
+    #[test]
+    fn validate_reports_every_field_problem() {
+        let path = "data/video.mp4";
+        let cases = [
+            (json!({ "video_path": " " }), "video_path", "must not be empty"),
+            (json!({ "video_path": path, "max_concurrency": 0 }), "max_concurrency", ">= 1"),
+            (json!({ "video_path": path, "max_frames": 0 }), "max_frames", "> 0"),
+            (
+                json!({ "video_path": path, "min_frames": 9, "max_frames": 3 }),
+                "min_frames",
+                "must not exceed max_frames",
+            ),
+            (
+                json!({ "video_path": path, "summary_options": { "max_sentences": 51 } }),
+                "summary_options.max_sentences",
+                "1..=50",
+            ),
+            (
+                json!({ "video_path": path, "description_rewrites": [{ "pattern": "(" }] }),
+                "description_rewrites[0].pattern",
+                "",
+            ),
+            (json!({ "video_path": path, "llm_max_dim": 0 }), "llm_max_dim", "> 0"),
+            (json!({ "video_path": path, "language": "not a tag" }), "language", "BCP-47"),
+            (json!({ "video_path": path, "temperature": 3.0 }), "temperature", "0..=2"),
+            (
+                json!({ "video_path": path, "top_p": 0.5, "deterministic": true }),
+                "top_p",
+                "deterministic",
+            ),
+            (json!({ "video_path": path, "motion_threshold": 1.5 }), "motion_threshold", "(0, 1]"),
+            (json!({ "video_path": path, "moments": [-1.0] }), "moments", "non-negative"),
+            (
+                json!({ "video_path": path, "labels": ["cat", "Cat"] }),
+                "labels[1]",
+                "duplicate label",
+            ),
+            (json!({ "video_path": path, "labels": ["a,b"] }), "labels[0]", "must not contain"),
+            (json!({ "video_path": path, "frame_prompt": "" }), "frame_prompt", "not be empty"),
+            (
+                json!({ "video_path": path, "fallback_model": "no-such-model" }),
+                "fallback_model",
+                "unknown model",
+            ),
+            (
+                json!({ "video_path": path, "sample_interval_secs": 0.0 }),
+                "sample_interval_secs",
+                "positive",
+            ),
+            (
+                json!({ "video_path": path, "describe_batch_size": 17 }),
+                "describe_batch_size",
+                "1..=16",
+            ),
+            (
+                json!({ "video_path": path, "describe_batch_size": 4, "labels": ["cat"] }),
+                "describe_batch_size",
+                "labels",
+            ),
+            (
+                json!({ "video_path": path, "description_cache_scope": "slides" }),
+                "description_cache_scope",
+                "needs reuse_descriptions",
+            ),
+            (
+                json!({ "video_path": path, "scene_cut_similarity": 1.0 }),
+                "scene_cut_similarity",
+                "exclusive",
+            ),
+        ];
+        for (body, field, problem) in cases {
+            let req: ProcessVideoRequest = serde_json::from_value(body.clone()).unwrap();
+            let errors = req.validate().err().unwrap_or_else(|| panic!("{} is rejected", body));
+            let message = errors.get(field).unwrap_or_else(|| panic!("{} names {}", body, field));
+            assert!(message.contains(problem), "{}: {}", field, message);
+        }
+
+        // Every problem is reported, not just the first
+        let req: ProcessVideoRequest = serde_json::from_value(
+            json!({ "video_path": "", "max_frames": 0, "top_p": 2.0 }),
+        )
+        .unwrap();
+        let fields: Vec<_> = req.validate().err().unwrap().into_keys().collect();
+        assert_eq!(fields, ["max_frames", "top_p", "video_path"]);
+        let req: ProcessVideoRequest =
+            serde_json::from_value(json!({ "video_path": path })).unwrap();
+        assert!(req.validate().is_ok());
+    }
+
+    #[tokio::test]
+    async fn json_rejections_name_the_failing_field() {
+        let cases = [
+            (post("/process-video", "application/json", "{"), "body"),
+            (post("/process-video", "text/plain", "{}"), "body"),
+            (post_json("/process-video", json!({})), "video_path"),
+            (post_json("/process-video", json!({ "video_path": 5 })), "video_path"),
+            (
+                post_json(
+                    "/process-video",
+                    json!({ "video_path": "data/v.mp4", "summary_options": { "style": "haiku" } }),
+                ),
+                "summary_options.style",
+            ),
+            (post_json("/process-batch", json!({ "video_paths": "data/v.mp4" })), "video_paths"),
+        ];
+        for (request, field) in cases {
+            let (status, body) = call(app(), request).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            assert_eq!(body["status"], "error");
+            assert!(body["errors"][field].is_string(), "{} in {}", field, body);
+        }
+    }
+
+    #[test]
+    fn upload_filenames_are_reduced_to_a_safe_base_name() {
+        let cases = [
+            ("clip.mp4", Ok("clip.mp4")),
+            ("../../etc/cron.d/x.mp4", Ok("x.mp4")),
+            ("C:\\Users\\me\\My Clip.MOV", Ok("My_Clip.MOV")),
+            ("..hidden.mkv", Ok("hidden.mkv")),
+            ("caf\u{e9} <1>.webm", Ok("caf___1_.webm")),
+            ("notes.txt", Err(())),
+            (".mp4", Err(())),
+            ("..", Err(())),
+            ("videos/", Err(())),
+        ];
+        for (raw, expected) in cases {
+            let cleaned = sanitize_upload_filename(raw);
+            assert_eq!(cleaned.as_deref().map_err(|_| ()), expected, "{}", raw);
+        }
+    }
+
+    #[tokio::test]
+    async fn video_paths_stay_inside_data() {
+        let cases = [
+            ("../Cargo.toml", StatusCode::FORBIDDEN),
+            ("/etc/hostname", StatusCode::FORBIDDEN),
+            ("data/../src/main.rs", StatusCode::FORBIDDEN),
+            ("data/missing.mp4", StatusCode::NOT_FOUND),
+            ("data", StatusCode::UNPROCESSABLE_ENTITY),
+        ];
+        for uri in ["/process-video", "/process-video-stream", "/estimate"] {
+            for (path, expected) in cases {
+                let request = post_json(uri, json!({ "video_path": path }));
+                let (status, body) = call(app(), request).await;
+                assert_eq!(status, expected, "{} {}", uri, path);
+                assert!(body["message"].as_str().unwrap().starts_with("Cannot process video"));
             }
         }
     }
-    
-    Json(json!({
-        "status": "error",
-        "message": "No file provided"
-    }))
+
+    #[tokio::test]
+    async fn estimate_and_stream_validate_like_process_video() {
+        for uri in ["/estimate", "/process-video-stream"] {
+            let request = post_json(uri, json!({ "video_path": "data/v.mp4", "max_frames": 0 }));
+            let (status, body) = call(app(), request).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            assert_eq!(body["errors"]["max_frames"], "max_frames must be > 0");
+        }
+    }
+
+    #[tokio::test]
+    async fn process_batch_checks_its_list_and_each_path() {
+        let too_many: Vec<_> = (0..=MAX_BATCH_VIDEOS).map(|i| format!("data/{}.mp4", i)).collect();
+        let lists = [json!([]), json!(too_many), json!(["data/v.mp4", " "])];
+        for list in lists {
+            let request = post_json("/process-batch", json!({ "video_paths": list }));
+            let (status, body) = call(app(), request).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            assert!(body["errors"]["video_paths"].is_string());
+        }
+
+        // A bad entry fails alone, and a path listed twice appears once
+        let paths = json!(["../Cargo.toml", "data/missing.mp4", "data/missing.mp4"]);
+        let request = post_json("/process-batch", json!({ "video_paths": paths }));
+        let (status, body) = call(app(), request).await;
+        assert_eq!(status, StatusCode::OK);
+        let results = body["results"].as_object().unwrap();
+        assert_eq!(results.len(), 2);
+        for result in results.values() {
+            assert_eq!(result["status"], "error");
+            let message = result["message"].as_str().unwrap();
+            assert!(message.starts_with("Failed to process video: Cannot process video"));
+        }
+    }
+
+    // `/process-image` request with one multipart field per (name, data)
+    fn multipart(fields: &[(&str, &str)]) -> axum::http::Request<axum::body::Body> {
+        let mut body = String::new();
+        for (name, data) in fields {
+            body.push_str(&format!(
+                "--boundary\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n",
+                name, data
+            ));
+        }
+        body.push_str("--boundary--\r\n");
+        post("/process-image", "multipart/form-data; boundary=boundary", body)
+    }
+
+    #[tokio::test]
+    async fn process_image_fails_when_no_image_is_described() {
+        let (status, body) = call(app(), multipart(&[("image", "not an image")])).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(body["status"], "error");
+
+        let fields = [("image", "not an image"), ("image", "nor this")];
+        let (status, body) = call(app(), multipart(&fields)).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(body["status"], "error");
+        assert_eq!(body["images"].as_array().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn process_image_rejects_unknown_fields() {
+        let (status, body) = call(app(), multipart(&[("file", "not an image")])).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body["message"].as_str().unwrap().contains("'file'"));
+    }
+
+    #[tokio::test]
+    async fn delete_video_ignores_ids_outside_data() {
+        // `data/..%2Fdelete_probe` would name this file next to `data/`
+        let probe = "delete_probe_manifest.json";
+        std::fs::write(probe, "{}").unwrap();
+        let request = axum::http::Request::delete("/videos/..%2Fdelete_probe")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        let (status, _) = call(app(), request).await;
+        let kept = std::path::Path::new(probe).exists();
+        let _ = std::fs::remove_file(probe);
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert!(kept);
+    }
+
+    #[tokio::test]
+    async fn process_stream_needs_a_video_body() {
+        let (status, body) = call(app(), post("/process-stream", "application/json", "{}")).await;
+        assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(body["message"], "Content-Type must be video/*");
+
+        let empty = post("/process-stream", "video/mp4", axum::body::Body::empty());
+        let (status, body) = call(app(), empty).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["errors"]["body"], "request body must contain the video");
+    }
 }
@@ -0,0 +1,8 @@
+//! Video understanding backend: frame selection, Gemini descriptions and summaries.
+//!
+//! The HTTP server in `main.rs` is a thin wrapper around this library; embedders can
+//! call `services` directly (e.g. `services::analyze_video` with a `FrameSink`).
+
+pub mod handlers;
+pub mod routes;
+pub mod services;
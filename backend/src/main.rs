@@ -1,6 +1,4 @@
-mod handlers;
-mod routes;
-mod services;
+use open_ai_video_understanding::{routes, services};
 
 /// Main entry point for the backend server
 #[tokio::main]
@@ -8,14 +6,46 @@ async fn main() {
     // Load environment variables from .env file
     dotenv::dotenv().ok();
     
-    // Initialize tracing for logging
-    tracing_subscriber::fmt::init();
+    // JSON log lines, each carrying its request and video spans; LOG_FORMAT=text for a
+    // terminal
+    if std::env::var("LOG_FORMAT").is_ok_and(|format| format == "text") {
+        tracing_subscriber::fmt::init();
+    } else {
+        tracing_subscriber::fmt().json().init();
+    }
+
+    if let Err(e) = services::install_metrics() {
+        tracing::warn!("Prometheus metrics unavailable: {:#}", e);
+    }
+
+    // Fail fast on a bad Gemini endpoint/auth setup instead of on the first video
+    match services::GeminiConfig::from_env() {
+        Ok(gemini) => tracing::info!("Gemini endpoint: {}", gemini.describe()),
+        Err(e) => {
+            tracing::error!("Invalid Gemini configuration: {:#}", e);
+            std::process::exit(1);
+        }
+    }
+
+    // Same for STORAGE_BACKEND, e.g. S3 credentials missing
+    match services::storage::init().await {
+        Ok(storage) => tracing::info!("Storage: {}", storage.describe()),
+        Err(e) => {
+            tracing::error!("Invalid storage configuration: {:#}", e);
+            std::process::exit(1);
+        }
+    }
+
+    // Deletes old frames and videos when DATA_RETENTION_HOURS is set
+    tokio::spawn(services::run_data_retention());
 
     // Create and run the server on port 4000 (Next.js uses 3000)
     let app = routes::create_router();
     let addr: std::net::SocketAddr = "0.0.0.0:4000".parse().unwrap();
 
-    println!("Server running on http://{}", addr);
+    tracing::info!("Server running on http://{}", addr);
+    // Connect info gives the rate limit the client IP
+    let app = app.into_make_service_with_connect_info::<std::net::SocketAddr>();
     axum::serve(tokio::net::TcpListener::bind(addr).await.unwrap(), app)
         .await
         .unwrap();
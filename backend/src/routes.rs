@@ -1,28 +1,476 @@
-use axum::{routing::{get, post}, Router, extract::{Json, DefaultBodyLimit}};
-use crate::handlers;
-use tower_http::cors::{CorsLayer, Any};
+use axum::{routing::{get, post}, Router, extract::{ConnectInfo, DefaultBodyLimit, Request, State}};
+use axum::http::{header, HeaderMap, HeaderName, HeaderValue, Method, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use crate::{handlers, services};
+use serde_json::json;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tower_http::cors::{AllowOrigin, CorsLayer, Any};
 use tower_http::services::ServeDir;
+use tracing::{warn, Instrument};
 
-/// Creates and returns the main router with all routes
+/// Optional endpoints a deployment exposes. Everything is on by default.
+#[derive(Debug, Clone)]
+pub struct RouterConfig {
+    /// `POST /upload`; disable (`NO_UPLOAD=true`) for read-only deployments
+    pub upload: bool,
+    /// Frame images under `/data`; disable (`STATIC_SERVING=false`) when a proxy or CDN serves them
+    pub static_serving: bool,
+    /// Bearer tokens accepted on every route but `/health` and the tenants they authenticate
+    /// (`API_KEYS`, comma-separated `tenant:key` pairs); empty leaves the server open, for
+    /// local development
+    pub api_keys: Vec<ApiKey>,
+    /// Origins browsers may call from (`ALLOWED_ORIGINS`, comma-separated, e.g.
+    /// `https://app.example.com`); `None` allows any origin, for local development
+    pub allowed_origins: Option<Vec<String>>,
+    /// Per-client limit on the routes that call Gemini (`RATE_LIMIT_PER_MINUTE`,
+    /// `RATE_LIMIT_BURST`); `None` leaves them unlimited
+    pub rate_limit: Option<RateLimit>,
+}
+
+/// A bearer token and the tenant it authenticates, for fair LLM scheduling and scoped
+/// description reuse. A key listed without a tenant is tenant `key-{n}`, its position.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiKey {
+    pub tenant: String,
+    pub key: String,
+}
+
+/// Token bucket per client: `burst` requests at once, refilled at `per_minute`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    pub per_minute: u32,
+    pub burst: u32,
+}
+
+impl Default for RouterConfig {
+    fn default() -> Self {
+        Self {
+            upload: true,
+            static_serving: true,
+            api_keys: Vec::new(),
+            allowed_origins: None,
+            rate_limit: None,
+        }
+    }
+}
+
+impl RouterConfig {
+    /// Read `NO_UPLOAD`, `STATIC_SERVING`, `API_KEYS`, `ALLOWED_ORIGINS`, `RATE_LIMIT_PER_MINUTE`
+    /// and `RATE_LIMIT_BURST`; unset or invalid values keep the defaults.
+    pub fn from_env() -> Self {
+        let api_keys = parse_api_keys(&std::env::var("API_KEYS").unwrap_or_default());
+        if api_keys.is_empty() {
+            warn!("API_KEYS is not set; all routes are unauthenticated");
+        }
+        let allowed_origins = std::env::var("ALLOWED_ORIGINS")
+            .ok()
+            .filter(|origins| !origins.trim().is_empty())
+            .map(|origins| {
+                origins
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|origin| !origin.is_empty())
+                    .map(str::to_string)
+                    .collect::<Vec<_>>()
+            });
+        if allowed_origins.is_none() {
+            warn!("ALLOWED_ORIGINS is not set; CORS allows requests from any origin");
+        }
+        // Burst defaults to a minute's worth
+        let rate_limit = env_count("RATE_LIMIT_PER_MINUTE").map(|per_minute| RateLimit {
+            per_minute,
+            burst: env_count("RATE_LIMIT_BURST").unwrap_or(per_minute),
+        });
+        Self {
+            upload: !env_flag("NO_UPLOAD", false),
+            static_serving: env_flag("STATIC_SERVING", true),
+            api_keys,
+            allowed_origins,
+            rate_limit,
+        }
+    }
+}
+
+// `tenant:key` pairs, or bare keys named by their position
+fn parse_api_keys(raw: &str) -> Vec<ApiKey> {
+    raw.split(',')
+        .map(str::trim)
+        .filter(|key| !key.is_empty())
+        .enumerate()
+        .map(|(i, entry)| match entry.split_once(':') {
+            Some((tenant, key)) if !tenant.trim().is_empty() && !key.trim().is_empty() => {
+                ApiKey { tenant: tenant.trim().to_string(), key: key.trim().to_string() }
+            }
+            _ => ApiKey { tenant: format!("key-{}", i + 1), key: entry.to_string() },
+        })
+        .collect()
+}
+
+// The tenant of `token`. Every key is compared in constant time, so how long the check
+// takes doesn't tell a guess how much of a key it got right.
+fn key_tenant(keys: &[ApiKey], token: &str) -> Option<String> {
+    use subtle::ConstantTimeEq;
+
+    let mut tenant = None;
+    for key in keys {
+        if bool::from(key.key.as_bytes().ct_eq(token.as_bytes())) {
+            tenant = Some(key.tenant.clone());
+        }
+    }
+    tenant
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(str::trim)
+}
+
+// Rejects requests without an `Authorization: Bearer` token from `keys`, and tells the
+// handlers the tenant of the ones with
+async fn require_api_key(
+    State(keys): State<Arc<Vec<ApiKey>>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let tenant = bearer_token(request.headers()).and_then(|token| key_tenant(&keys, token));
+    if let Some(tenant) = tenant {
+        request.extensions_mut().insert(handlers::Tenant(tenant));
+        return next.run(request).await;
+    }
+    (
+        StatusCode::UNAUTHORIZED,
+        [(header::WWW_AUTHENTICATE, "Bearer")],
+        Json(json!({
+            "status": "error",
+            "message": "Missing or invalid API key"
+        })),
+    )
+        .into_response()
+}
+
+// Past this many clients, buckets that have refilled are dropped (a full bucket is the
+// same as none), then the longest unseen ones until a tenth of the room is free again
+const MAX_RATE_LIMIT_BUCKETS: usize = 10_000;
+
+struct RateLimiter {
+    limit: RateLimit,
+    // Only checked tokens tell clients apart; otherwise any made-up token would get its
+    // own bucket
+    by_api_key: bool,
+    buckets: Mutex<HashMap<String, (f64, Instant)>>,
+}
+
+impl RateLimiter {
+    // Take a token from `client`'s bucket, or the seconds until one is available
+    fn take(&self, client: String) -> Result<(), u64> {
+        let rate = self.limit.per_minute as f64 / 60.0;
+        let burst = self.limit.burst as f64;
+        let now = Instant::now();
+        let refilled = |(tokens, updated): (f64, Instant)| {
+            (tokens + now.duration_since(updated).as_secs_f64() * rate).min(burst)
+        };
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_RATE_LIMIT_BUCKETS && !buckets.contains_key(&client) {
+            buckets.retain(|_, bucket| refilled(*bucket) < burst);
+            // Every client active: forget the ones seen longest ago, whose buckets are
+            // the most refilled anyway
+            let keep = MAX_RATE_LIMIT_BUCKETS - MAX_RATE_LIMIT_BUCKETS / 10;
+            if buckets.len() > keep {
+                let mut seen: Vec<_> =
+                    buckets.iter().map(|(c, &(_, at))| (at, c.clone())).collect();
+                let (oldest, _, _) = seen.select_nth_unstable(buckets.len() - keep);
+                for (_, client) in oldest.iter() {
+                    buckets.remove(client);
+                }
+            }
+        }
+        let bucket = buckets.entry(client).or_insert((burst, now));
+        let tokens = refilled(*bucket);
+        if tokens >= 1.0 {
+            *bucket = (tokens - 1.0, now);
+            Ok(())
+        } else {
+            *bucket = (tokens, now);
+            Err((((1.0 - tokens) / rate).ceil() as u64).max(1))
+        }
+    }
+}
+
+// Answers 429 once a client, by API key or else by IP, has used up its bucket
+async fn rate_limit(
+    State(limiter): State<Arc<RateLimiter>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let client = match bearer_token(request.headers()).filter(|_| limiter.by_api_key) {
+        Some(token) => format!("key:{}", token),
+        // Without connect info (a server not started by `main`) clients share one bucket
+        None => match request.extensions().get::<ConnectInfo<SocketAddr>>() {
+            Some(ConnectInfo(addr)) => format!("ip:{}", addr.ip()),
+            None => "ip:unknown".to_string(),
+        },
+    };
+    match limiter.take(client) {
+        Ok(()) => next.run(request).await,
+        Err(retry_after) => {
+            metrics::counter!("rate_limited_total").increment(1);
+            (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(header::RETRY_AFTER, retry_after.to_string())],
+                Json(json!({
+                    "status": "error",
+                    "message": format!("Rate limit exceeded; retry in {} s", retry_after)
+                })),
+            )
+                .into_response()
+        }
+    }
+}
+
+const REQUEST_ID_HEADER: &str = "x-request-id";
+
+// Runs the request inside a span carrying its request id, so every log line it causes
+// (frame tasks included) can be told apart from concurrent jobs. A client-sent
+// `x-request-id` is reused; either way the id is echoed in the response.
+async fn request_span(request: Request, next: Next) -> Response {
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .filter(|id| !id.is_empty() && id.len() <= 128)
+        .map(str::to_string)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    let span = tracing::info_span!(
+        "request",
+        request_id = %request_id,
+        method = %request.method(),
+        path = %request.uri().path()
+    );
+    let mut response = next.run(request).instrument(span).await;
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    response
+}
+
+// Only the methods and headers the API uses. Origins come from `allowed_origins`; invalid
+// ones are skipped rather than widening the list to any origin.
+fn cors_layer(allowed_origins: Option<&[String]>) -> CorsLayer {
+    let origin = match allowed_origins {
+        None => AllowOrigin::from(Any),
+        Some(origins) => AllowOrigin::list(origins.iter().filter_map(|origin| {
+            match HeaderValue::from_str(origin) {
+                Ok(value) => Some(value),
+                Err(_) => {
+                    warn!("Ignoring invalid ALLOWED_ORIGINS entry '{}'", origin);
+                    None
+                }
+            }
+        })),
+    };
+    let request_id = HeaderName::from_static(REQUEST_ID_HEADER);
+    CorsLayer::new()
+        .allow_origin(origin)
+        .allow_methods([Method::GET, Method::POST, Method::DELETE])
+        .allow_headers([header::AUTHORIZATION, header::CONTENT_TYPE, request_id.clone()])
+        .expose_headers([request_id])
+}
+
+// Positive integer, `None` if unset, zero or invalid
+fn env_count(name: &str) -> Option<u32> {
+    let value = std::env::var(name).ok().filter(|v| !v.is_empty())?;
+    match value.parse() {
+        Ok(0) => None,
+        Ok(count) => Some(count),
+        Err(_) => {
+            warn!("Invalid {} value '{}'; ignoring it", name, value);
+            None
+        }
+    }
+}
+
+fn env_flag(name: &str, default: bool) -> bool {
+    match std::env::var(name).ok().as_deref() {
+        None | Some("") => default,
+        Some("1" | "true" | "yes") => true,
+        Some("0" | "false" | "no") => false,
+        Some(other) => {
+            warn!("Invalid {} value '{}'; using {}", name, other, default);
+            default
+        }
+    }
+}
+
+/// Creates and returns the main router with all routes enabled per `RouterConfig::from_env`
 pub fn create_router() -> Router {
+    create_router_with(RouterConfig::from_env())
+}
+
+/// Creates the router with only the optional routes enabled in `config`
+pub fn create_router_with(config: RouterConfig) -> Router {
     // Configure CORS to allow frontend calls
-    let cors = CorsLayer::new()
-        .allow_origin(Any)
-        .allow_methods(Any)
-        .allow_headers(Any);
+    let cors = cors_layer(config.allowed_origins.as_deref());
 
-    Router::new()
-        .route("/health", get(handlers::health_check))
+    // Routes that call Gemini, behind the rate limit
+    let mut jobs = Router::new()
+        .route("/process-video", post(handlers::process_video))
+        .route("/process-video/stream", post(handlers::process_video_stream))
+        .route("/process-batch", post(handlers::process_batch))
+        .route("/process-stream", post(handlers::process_stream))
+        .route("/process-image", post(handlers::process_image))
+        .route("/ws/process", get(handlers::process_video_ws))
+        .route("/videos/:id/resummarize", post(handlers::resummarize_video))
+        .route("/videos/:id/search", post(handlers::search_video))
+        .route("/monitor", get(handlers::monitor_live))
+        .route("/health/deep", get(handlers::deep_health_check));
+    if let Some(limit) = config.rate_limit {
+        let limiter = Arc::new(RateLimiter {
+            limit,
+            by_api_key: !config.api_keys.is_empty(),
+            buckets: Mutex::new(HashMap::new()),
+        });
+        jobs = jobs.route_layer(middleware::from_fn_with_state(limiter, rate_limit));
+    }
+
+    let mut router = Router::new()
+        .merge(jobs)
         .route("/test", get(handlers::test))
-        .route("/upload", post(handlers::upload_video))
-        .route(
-            "/process-video",
-            post(|Json(req): Json<handlers::ProcessVideoRequest>| async move {
-                handlers::process_video(Json(req)).await
-            }),
-        )
-        // Serve frame images from the local data directory for thumbnails
-        .nest_service("/data", ServeDir::new("data"))
-        .layer(DefaultBodyLimit::max(500 * 1024 * 1024)) // 500 MB limit
+        .route("/metrics", get(handlers::metrics))
+        .route("/process-video/cancel", post(handlers::cancel_process_video))
+        .route("/process-video/progress/:id", get(handlers::process_video_progress))
+        .route("/estimate", post(handlers::estimate))
+        .route("/videos", get(handlers::list_videos))
+        .route("/videos/:id", get(handlers::stored_video).delete(handlers::delete_video))
+        .route("/videos/:id/records.csv", get(handlers::records_csv))
+        .route("/videos/:id/contact-sheet.jpg", get(handlers::contact_sheet))
+        .route("/videos/:id/captions.vtt", get(handlers::captions_vtt))
+        .route("/videos/:id/captions.srt", get(handlers::captions_srt))
+        .route("/videos/:id/manifest", get(handlers::manifest));
+    if config.upload {
+        router = router.route("/upload", post(handlers::upload_video));
+    }
+    if config.static_serving {
+        // Serve frame images from the local data directory for thumbnails; with API keys
+        // these need the bearer token like every other route
+        router = router.nest_service("/data", ServeDir::new("data"));
+    }
+    if !config.api_keys.is_empty() {
+        let keys = Arc::new(config.api_keys);
+        router = router.layer(middleware::from_fn_with_state(keys, require_api_key));
+    }
+    // Public even with API keys, for load balancer health checks
+    router = router.route("/health", get(handlers::health_check));
+    router
+        .layer(DefaultBodyLimit::max(services::MAX_VIDEO_BYTES as usize))
         .layer(cors)
+        .layer(middleware::from_fn(request_span))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_must_match_a_key_exactly() {
+        let keys = parse_api_keys("acme:secret,beta:other");
+        assert_eq!(key_tenant(&keys, "secret").as_deref(), Some("acme"));
+        assert_eq!(key_tenant(&keys, "other").as_deref(), Some("beta"));
+        for token in ["", "secre", "secret2", "Secret", "acme:secret"] {
+            assert_eq!(key_tenant(&keys, token), None, "{}", token);
+        }
+    }
+
+    #[test]
+    fn api_keys_name_their_tenants() {
+        let keys = parse_api_keys(" acme:k1 ,k2,, :k3,beta: ");
+        let pairs: Vec<_> = keys.iter().map(|k| (k.tenant.as_str(), k.key.as_str())).collect();
+        assert_eq!(pairs, [("acme", "k1"), ("key-2", "k2"), ("key-3", ":k3"), ("key-4", "beta:")]);
+    }
+
+    #[tokio::test]
+    async fn the_tenant_comes_from_the_api_key_not_a_header() {
+        use tower::ServiceExt;
+
+        async fn echo_tenant(
+            axum::Extension(handlers::Tenant(tenant)): axum::Extension<handlers::Tenant>,
+        ) -> String {
+            tenant
+        }
+        let keys = Arc::new(parse_api_keys("acme:k1,k2"));
+        let app = Router::new()
+            .route("/", get(echo_tenant))
+            .layer(middleware::from_fn_with_state(keys, require_api_key));
+        // Sending another tenant's id, or a fresh one each time, changes nothing
+        let requests = [("k1", "beta", "acme"), ("k1", "gamma", "acme"), ("k2", "acme", "key-2")];
+        for (token, claimed, expected) in requests {
+            let request = Request::builder()
+                .uri("/")
+                .header(header::AUTHORIZATION, format!("Bearer {}", token))
+                .header("x-tenant-id", claimed)
+                .body(axum::body::Body::empty())
+                .unwrap();
+            let response = app.clone().oneshot(request).await.unwrap();
+            let body = axum::body::to_bytes(response.into_body(), 64).await.unwrap();
+            assert_eq!(body, expected);
+        }
+    }
+
+    #[test]
+    fn busy_clients_make_room_for_new_ones() {
+        let limiter = RateLimiter {
+            limit: RateLimit { per_minute: 1, burst: 1 },
+            by_api_key: false,
+            buckets: Mutex::new(HashMap::new()),
+        };
+        // Each has just spent its one token, so no bucket is full
+        for i in 0..MAX_RATE_LIMIT_BUCKETS {
+            limiter.take(format!("ip:{}", i)).unwrap();
+        }
+        limiter.take("ip:new".to_string()).unwrap();
+        let buckets = limiter.buckets.lock().unwrap();
+        assert!(buckets.len() < MAX_RATE_LIMIT_BUCKETS);
+        assert!(buckets.contains_key("ip:new"));
+        assert!(buckets.contains_key(&format!("ip:{}", MAX_RATE_LIMIT_BUCKETS - 1)));
+        assert!(!buckets.contains_key("ip:0"));
+    }
+
+    #[tokio::test]
+    async fn model_routes_answer_429_with_retry_after_past_the_burst() {
+        let config = RouterConfig {
+            rate_limit: Some(RateLimit { per_minute: 1, burst: 1 }),
+            ..Default::default()
+        };
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = create_router_with(config).into_make_service_with_connect_info::<SocketAddr>();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        // A malformed body is rejected before the handler calls the model
+        let search = || {
+            reqwest::Client::new()
+                .post(format!("http://{}/videos/video/search", addr))
+                .header(header::CONTENT_TYPE, "application/json")
+                .body("{")
+                .send()
+        };
+        let first = search().await.unwrap();
+        assert_ne!(first.status().as_u16(), 429);
+
+        let limited = search().await.unwrap();
+        assert_eq!(limited.status().as_u16(), 429);
+        let retry_after = limited.headers()[header::RETRY_AFTER].to_str().unwrap();
+        assert!(retry_after.parse::<u64>().unwrap() >= 1);
+
+        // Routes that don't call the model stay unlimited
+        let health = reqwest::get(format!("http://{}/health", addr)).await.unwrap();
+        assert_eq!(health.status().as_u16(), 200);
+    }
 }
@@ -1,23 +1,31 @@
 use anyhow::{Context, Result};
 use base64::{engine::general_purpose, Engine as _};
-use gemini_rust::{Gemini, Model};
+use gemini_rust::{Gemini, GeminiBuilder, Model, TaskType};
 use image::codecs::jpeg::JpegEncoder;
 use image::ImageEncoder;
 use image::{ImageBuffer, Rgb};
 use ffmpeg_next as ffmpeg;
-use ffmpeg::format::{input as ff_input, Pixel as FfmpegPixel};
+use ffmpeg::format::{context::Input as FfmpegInput, input as ff_input, Pixel as FfmpegPixel};
 use ffmpeg::media::Type as FfmpegMediaType;
 use ffmpeg::software::scaling::{context::Context as FfmpegScaler, flag::Flags as FfmpegScaleFlags};
+use ffmpeg::util::frame::audio::Audio as FfmpegAudio;
 use ffmpeg::util::frame::video::Video as FfmpegVideo;
 use serde::{Deserialize, Serialize};
 use std::cmp::Ordering;
+use std::collections::{HashMap, VecDeque};
 use std::env;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Instant;
 use tokio::fs;
-use tokio::sync::Semaphore;
+use tokio::sync::{oneshot, Semaphore};
 use tokio::task::JoinSet;
-use tracing::{info, warn};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn, Instrument};
+use wide::f32x8;
+
+pub mod storage;
+pub mod store;
 // video_rs decoder removed for Y-plane path
 
 // ==========================
@@ -25,463 +33,6063 @@ use tracing::{info, warn};
 // ==========================
 
 // Record returned by `process_video` for each selected/sent frame.
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct FrameRecord {
     pub frame_id: u64,
     pub timestamp: f64,
     pub description: String,
     pub path: String, // using file path; can switch to base64 if you prefer
+    /// Single-sentence screen-reader text, only set in `DescriptionMode::AltText`
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub alt_text: Option<String>,
+    /// Labels picked from `ProcessOptions::labels`; empty when classification is off
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub labels: Vec<String>,
+    /// Model that produced `description` (the fallback model after a timeout or rate limit)
+    #[serde(default)]
+    pub model: String,
+    /// Why this frame couldn't be described; `description` is empty then and summaries
+    /// skip the frame
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub error: Option<String>,
+    /// Cosine similarity to the previously described frame, as measured during selection
+    /// (`None` for the first frame and resolution resets). Drives scene detection.
+    #[serde(skip)]
+    pub similarity_to_prev: Option<f32>,
+    /// The same similarity, serialized for tuning thresholds: the cosine of the frame's
+    /// features vs the reference when it was queued (lower is more novel). In `fixed` and
+    /// motion sampling it is measured but picks nothing.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub selection_score: Option<f32>,
+    /// Presentation time of the decoded frame in whole milliseconds. Unlike `frame_id`,
+    /// the index of the sample point (or `MOMENT_FRAME_ID_BASE` and up for frames picked
+    /// for `ProcessOptions::moments`), it doesn't depend on `sample_interval_secs`, so it
+    /// identifies the frame across runs (two sample points that got the same decoded
+    /// frame share it).
+    #[serde(default)]
+    pub pts_ms: u64,
+    /// Encoded JPEG, never serialized. Frames are written to `path` as soon as they are
+    /// encoded, so this is `None` unless `ProcessOptions::keep_images` is set.
+    #[serde(skip)]
+    pub jpeg_bytes: Option<Vec<u8>>,
+    /// Embedding of `description`, set with `ProcessOptions::embed_descriptions` and kept
+    /// in the store for `search_frames`; never serialized
     #[serde(skip)]
-    pub jpeg_bytes: Option<Vec<u8>>, // Hold in memory during processing, skip serialization
+    pub embedding: Option<Vec<f32>>,
+    /// Description reused from an earlier frame with the same perceptual hash instead of
+    /// a model call, see `ProcessOptions::reuse_descriptions`
+    #[serde(skip_serializing_if = "std::ops::Not::not", default)]
+    pub cache_hit: bool,
+    /// The model's own confidence in `description`, 0..=1; `None` when its reply wasn't
+    /// the requested JSON (or for labels, failed and dry-run frames)
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub confidence: Option<f32>,
 }
 
-// Compact per-sample feature used for cosine similarity (64x64 grayscale -> 4096D)
-#[derive(Clone)]
-struct SampleFeature {
-    vec: Vec<f32>, // length 4096
-    l2: f32,
+/// What `process_video` does when decoded frames change size mid-stream.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResolutionChange {
+    /// Keep comparing against the current reference (features are always 64x64)
+    #[default]
+    Compare,
+    /// Describe the first frame at the new size and use it as the new reference
+    Reset,
 }
 
-/// Runtime context for queuing frame-description jobs with bounded concurrency.
-#[derive(Clone)]
-struct FrameJobContext {
-    api_key: Arc<String>,
-    model: Model,
-    semaphore: Arc<Semaphore>,
-    video_id: Arc<String>, // Unique identifier for this video
+/// Which frames the decoder may drop before selection sees them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DecodeSkip {
+    /// Decode every frame
+    #[default]
+    Off,
+    /// Let the codec skip non-reference frames (typically B-frames). On 120/240fps footage the
+    /// remaining frames are still far denser than the default 0.25s sampling grid, so selection is
+    /// unchanged in practice while decode work drops substantially.
+    NonReference,
 }
 
-impl FrameJobContext {
-    fn new(api_key: String, model: Model, max_concurrency: usize, video_id: String) -> Self {
-        Self {
-            api_key: Arc::new(api_key),
-            model,
-            semaphore: Arc::new(Semaphore::new(max_concurrency.max(1))),
-            video_id: Arc::new(video_id),
+impl DecodeSkip {
+    // What the decoder is told to discard; `None` leaves the codec's default
+    fn discard(self) -> Option<ffmpeg::codec::Discard> {
+        match self {
+            DecodeSkip::Off => None,
+            DecodeSkip::NonReference => Some(ffmpeg::codec::Discard::NonReference),
         }
     }
+}
 
-    fn queue(
-        &self,
-        tasks: &mut JoinSet<Result<FrameRecord>>,
-        frame_id: u64,
-        timestamp: f64,
-        image: ImageBuffer<Rgb<u8>, Vec<u8>>,
-    ) {
-        let ctx = self.clone();
-        tasks.spawn(async move {
-            let _permit = ctx
-                .semaphore
-                .acquire_owned()
-                .await
-                .context("failed to acquire concurrency permit")?;
-
-            let jpeg_bytes = tokio::task::spawn_blocking(move || encode_jpeg(image))
-                .await
-                .context("JPEG encode task panicked")??;
+/// How frames are picked from the sample points. Both always describe the first frame.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SelectionMode {
+    /// Of each pair of sample points, describe the one less similar to the last described
+    /// frame
+    #[default]
+    Adaptive,
+    /// Describe the frame at every sample point, so timestamps are multiples of
+    /// `sample_interval_secs` and line up with external annotations
+    Fixed,
+}
 
-            // Use video_id to create unique frame paths per video
-            let path = format!("data/{}_frame_{:03}.jpg", ctx.video_id, frame_id);
-            
-            // Skip disk write during processing - keep in memory
-            // Disk writes will happen after all LLM calls complete
-            let description =
-                describe_jpeg_bytes(ctx.api_key.as_ref(), ctx.model, jpeg_bytes.clone()).await?;
+/// Feature vector used for the similarity comparisons that drive frame selection.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FeatureMode {
+    /// 64x64 luma read straight from the decoded Y plane; no conversion, cheapest
+    #[default]
+    #[serde(alias = "grayscale")]
+    Luma,
+    /// 32x32 RGB grid, for content where color is what changes (grading, painting).
+    /// Costs a color conversion of every sampled candidate, not just the selected ones.
+    Rgb,
+    /// Normalized 8x8x8-bin joint color histogram: catches color and lighting changes
+    /// (flashes, lights switching on) regardless of where in the frame they happen. Has no
+    /// spatial layout, so runs in this mode get no `fingerprint`.
+    Histogram,
+}
 
-            Ok(FrameRecord {
-                frame_id,
-                timestamp,
-                description,
-                path,
-                jpeg_bytes: Some(jpeg_bytes), // Keep bytes in memory
-            })
-        });
-    }
+/// Regex replacement applied to every frame description before it is stored,
+/// e.g. stripping a leading "In this frame, I can see".
+#[derive(Debug, Clone)]
+pub struct DescriptionRewrite {
+    pattern: regex::Regex,
+    replacement: String,
 }
 
-// (removed) FrameSelection; streaming selection uses direct enqueuing.
+impl DescriptionRewrite {
+    /// `replacement` may use `$1`/`$name` capture references.
+    pub fn new(pattern: &str, replacement: impl Into<String>) -> Result<Self, regex::Error> {
+        Ok(Self {
+            pattern: regex::Regex::new(pattern)?,
+            replacement: replacement.into(),
+        })
+    }
 
-fn load_llm_max_concurrency() -> usize {
-    const DEFAULT: usize = 100;
-    match env::var("LLM_MAX_CONCURRENCY") {
-        Ok(raw) => match raw.parse::<usize>() {
-            Ok(value) if value > 0 => value,
-            _ => {
-                warn!(
-                    "Invalid LLM_MAX_CONCURRENCY value '{}'; using {}",
-                    raw, DEFAULT
-                );
-                DEFAULT
-            }
-        },
-        Err(_) => DEFAULT,
+    fn apply(&self, text: &str) -> String {
+        self.pattern.replace_all(text, self.replacement.as_str()).into_owned()
     }
 }
 
-fn resolve_model(model_name: Option<&str>) -> Model {
-    match model_name {
-        Some("Gemini25Flash") => Model::Gemini25Flash,
-        Some("Gemini25Pro") => Model::Gemini25Pro,
-        Some("TextEmbedding004") => Model::TextEmbedding004,
-        Some("Gemini25FlashLite") | None => Model::Gemini25FlashLite,
-        Some(other) => {
-            warn!(
-                "Unknown GEMINI_MODEL '{}'; defaulting to Gemini25FlashLite",
-                other
-            );
-            Model::Gemini25FlashLite
-        }
-    }
+/// Which preset prompt frames are described with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DescriptionMode {
+    /// Long, speculative descriptions (the original prompt)
+    #[default]
+    Detailed,
+    /// One short sentence with spatial layout, suitable for screen readers and caption tracks.
+    /// The text is stored in `FrameRecord::alt_text` (and mirrored into `description`).
+    AltText,
 }
 
-fn encode_jpeg(image: ImageBuffer<Rgb<u8>, Vec<u8>>) -> Result<Vec<u8>> {
-    let width = image.width();
-    let height = image.height();
-    let raw = image.into_raw();
-    let mut buf = Vec::new();
-    let encoder = JpegEncoder::new_with_quality(&mut buf, 85);
-    encoder.write_image(&raw, width, height, image::ColorType::Rgb8.into())?;
-    Ok(buf)
+/// Half-width of the window searched around each moment of interest.
+pub const DEFAULT_MOMENT_TOLERANCE_SECS: f64 = 2.0;
+
+/// Tenant used when a request doesn't identify one.
+pub const DEFAULT_TENANT: &str = "default";
+
+/// Per-run knobs for `process_video`. `Default` matches the original hardcoded selection
+/// behavior, with the demuxer fallback and last-frame coverage enabled.
+#[derive(Debug, Clone)]
+pub struct ProcessOptions {
+    pub resolution_change: ResolutionChange,
+    pub description_mode: DescriptionMode,
+    pub decode_skip: DecodeSkip,
+    /// `min_dissimilarity` only applies to `Adaptive`
+    pub selection_mode: SelectionMode,
+    /// Motion sampling for mostly static footage: at each sample point, describe the frame
+    /// only if the mean absolute difference of its features from the previous sample
+    /// point's exceeds this (0..=1, luma units), so quiet stretches describe nothing, not
+    /// even the first or last frame. Replaces `selection_mode`.
+    pub motion_threshold: Option<f32>,
+    /// When opening the file or its decoder fails, retry with a demuxer guessed from the
+    /// file's magic bytes or extension before giving up
+    pub demuxer_fallback: bool,
+    /// Endpoint for description calls; `None` uses `GeminiConfig::from_env`
+    pub gemini: Option<GeminiConfig>,
+    /// Always describe the last decoded frame so the ending reaches the summary
+    pub include_last_frame: bool,
+    pub feature_mode: FeatureMode,
+    /// Applied in order to each description (before alt-text shortening)
+    pub description_rewrites: Vec<DescriptionRewrite>,
+    /// Tenant whose share of the global LLM concurrency this run uses (`DEFAULT_TENANT` if unset)
+    pub tenant: Option<String>,
+    /// Longest side of the JPEG sent to the model; `None` sends the stored JPEG
+    pub llm_max_dim: Option<u32>,
+    /// Longest side of the JPEG written to `data/`; `None` keeps the decoded resolution
+    pub store_max_dim: Option<u32>,
+    /// Downscale frames wider than this (keeping aspect ratio) before any JPEG is encoded,
+    /// so neither the stored nor the model's JPEG is wider; applied before the two bounds
+    pub max_frame_width: Option<u32>,
+    /// Approximate timestamps (seconds) of interesting moments; the most novel frame within
+    /// `moment_tolerance_secs` of each is always described, with a `frame_id` from
+    /// `MOMENT_FRAME_ID_BASE` up so the sample points keep theirs
+    pub moments: Vec<f64>,
+    pub moment_tolerance_secs: f64,
+    /// Send the frame prompt once as Gemini cached content and reference it from each frame
+    /// call; falls back to inline prompts when the cache can't be created
+    pub prompt_cache: bool,
+    /// Detect black letterbox/pillarbox bars and crop them from described frames and luma
+    /// features (RGB features always see the full frame)
+    pub auto_crop: bool,
+    /// Classify each frame into these labels instead of describing it; the record's
+    /// description becomes the comma-separated labels. Takes precedence over `description_mode`.
+    pub labels: Vec<String>,
+    /// Model (as accepted by `GEMINI_MODEL`) to retry a frame with when the primary call
+    /// times out or is rate limited
+    pub fallback_model: Option<String>,
+    /// Deadline per primary frame call; defaults to `DEFAULT_FRAME_TIMEOUT_SECS` when
+    /// `fallback_model` is set, otherwise `GEMINI_CALL_TIMEOUT_SECS`
+    pub frame_timeout_secs: Option<f64>,
+    /// Cancel the whole run, outstanding frame calls included, once it has taken this long
+    pub job_timeout_secs: Option<f64>,
+    /// Greedy decoding (temperature 0, top-k 1) for frame calls; see `with_sampling`
+    pub deterministic: bool,
+    /// Temperature, top-p and output limit of frame calls
+    pub generation: GenerationParams,
+    /// Seconds between sample points considered by selection
+    pub sample_interval_secs: f64,
+    /// Skip a pair's winner entirely when its cosine similarity to the last described frame
+    /// exceeds `1.0 - min_dissimilarity`, so static scenes cost no calls
+    pub min_dissimilarity: Option<f32>,
+    /// Replaces the description prompt of `description_mode`, e.g. to ask for on-screen text
+    pub frame_prompt: Option<String>,
+    /// BCP-47 tag (e.g. "ja", "pt-BR") of the language descriptions are written in; `None`
+    /// keeps English. Classification answers with `labels` stay in the labels' language.
+    pub language: Option<String>,
+    /// Keep each frame's JPEG in `FrameRecord::jpeg_bytes` after writing it to disk
+    pub keep_images: bool,
+    /// Transcribe the audio track into `VideoAnalysis::transcript`
+    pub transcribe: bool,
+    /// JPEG quality of encoded frames, 1..=100
+    pub jpeg_quality: u8,
+    /// Lets the caller pause, resume or cancel this run while it is in flight
+    pub control: Option<JobControl>,
+    /// Narrow the sample interval when it would describe fewer frames (first frame
+    /// included); best effort, as near-duplicate skipping can still leave fewer
+    pub min_frames: Option<u64>,
+    /// Widen the sample interval when it would describe more frames, and never queue more
+    pub max_frames: Option<u64>,
+    /// Select and write frames but make no Gemini calls: records keep an empty
+    /// `description` and `model`, and prompt caching and transcription are skipped
+    pub dry_run: bool,
+    /// Vision model for frame descriptions instead of Gemini. `fallback_model` and
+    /// `transcribe` still go to Gemini; `prompt_cache` is ignored.
+    pub describer: Option<Arc<dyn VisionDescriber>>,
+    /// Frames of this run described at once, instead of `LLM_MAX_CONCURRENCY`; all runs
+    /// together stay within the global `LLM_MAX_CONCURRENCY` limit
+    pub max_concurrency: Option<usize>,
+    /// Send only keyframe packets to the decoder and queue every keyframe, skipping
+    /// pairwise selection; `moments`, `min_frames` and `resolution_change` don't apply
+    pub keyframes_only: bool,
+    /// Embed each description with `text-embedding-004` into `FrameRecord::embedding`,
+    /// for `search_frames` once the run is stored
+    pub embed_descriptions: bool,
+    /// Send the previously queued frame along with each frame (the first goes alone) and
+    /// ask what changed, so descriptions can speak of motion and continuity. Not used for
+    /// `labels` or with `prompt_cache`.
+    pub with_context_frame: bool,
+    /// Reuse the description of an already described, near-identical frame (by perceptual
+    /// hash of its luma or RGB feature) instead of calling the model again, e.g. for a
+    /// slide a screen recording keeps returning to. Hashes are shared by the runs of one
+    /// tenant and `description_cache_scope` with the same model, prompt and rewrites. Not
+    /// used with `with_context_frame` or `Histogram` features.
+    pub reuse_descriptions: bool,
+    /// Which runs share `reuse_descriptions` hashes; `None` keeps them to runs of the same
+    /// video, a name shares them across the tenant's videos that pass it
+    pub description_cache_scope: Option<String>,
+    /// Blur the faces `face_detector` finds in each frame before it is written or sent to
+    /// the model. A frame whose detection fails is left undescribed with an `error`.
+    pub redact_faces: bool,
+    /// Finds faces for `redact_faces`; `None` uses the service at `FACE_DETECTOR_URL`
+    pub face_detector: Option<Arc<dyn FaceDetector>>,
+    /// Describe up to this many frames (at most `MAX_DESCRIBE_BATCH_SIZE`) in one Gemini
+    /// call. `max_concurrency` still bounds the frames in flight, so a batch holds at most
+    /// that many. Frames the reply leaves out are described alone. Only for the built-in
+    /// describer, and not with `labels`, `with_context_frame`, `prompt_cache`,
+    /// `fallback_model` or `dry_run`.
+    pub describe_batch_size: Option<usize>,
 }
 
-async fn describe_jpeg_bytes(api_key: &str, model: Model, jpeg_bytes: Vec<u8>) -> Result<String> {
-    let b64 = tokio::task::spawn_blocking(move || general_purpose::STANDARD.encode(jpeg_bytes))
-        .await
-        .context("base64 encode task panicked")?;
+/// JPEG quality of encoded frames unless `ProcessOptions::jpeg_quality` says otherwise.
+pub const DEFAULT_JPEG_QUALITY: u8 = 85;
 
-    let client = Gemini::with_model(api_key.to_string(), model)?;
+/// Default spacing of selection sample points.
+pub const DEFAULT_SAMPLE_INTERVAL_SECS: f64 = 0.25;
 
-    let response = client
-        .generate_content()
-        .with_user_message("Please describe what you see in this video frame with extremely detailed description try to understand the context of the frames. Make speculative guesses about what might be happening based on the frame!")
-        .with_inline_data(b64, "image/jpeg")
-        .execute()
-        .await?;
+/// Per-frame deadline used with `fallback_model` when none is given.
+pub const DEFAULT_FRAME_TIMEOUT_SECS: f64 = 30.0;
 
-    Ok(response.text())
+impl Default for ProcessOptions {
+    fn default() -> Self {
+        Self {
+            resolution_change: ResolutionChange::default(),
+            description_mode: DescriptionMode::default(),
+            decode_skip: DecodeSkip::default(),
+            selection_mode: SelectionMode::default(),
+            motion_threshold: None,
+            demuxer_fallback: true,
+            gemini: None,
+            include_last_frame: true,
+            feature_mode: FeatureMode::default(),
+            description_rewrites: Vec::new(),
+            tenant: None,
+            llm_max_dim: None,
+            store_max_dim: None,
+            max_frame_width: None,
+            moments: Vec::new(),
+            moment_tolerance_secs: DEFAULT_MOMENT_TOLERANCE_SECS,
+            prompt_cache: false,
+            auto_crop: false,
+            labels: Vec::new(),
+            fallback_model: None,
+            frame_timeout_secs: None,
+            job_timeout_secs: None,
+            deterministic: false,
+            generation: GenerationParams::default(),
+            sample_interval_secs: DEFAULT_SAMPLE_INTERVAL_SECS,
+            min_dissimilarity: None,
+            frame_prompt: None,
+            keep_images: false,
+            transcribe: false,
+            jpeg_quality: DEFAULT_JPEG_QUALITY,
+            control: None,
+            min_frames: None,
+            max_frames: None,
+            dry_run: false,
+            describer: None,
+            max_concurrency: None,
+            keyframes_only: false,
+            embed_descriptions: false,
+            with_context_frame: false,
+            reuse_descriptions: false,
+            description_cache_scope: None,
+            redact_faces: false,
+            face_detector: None,
+            describe_batch_size: None,
+            language: None,
+        }
+    }
 }
 
-/// Summarize what happens in the video based on the per-frame descriptions.
-/// Keeps it simple: sends a compact text transcript to Gemini and asks for
-/// a short summary. No images are attached here to keep calls light.
-pub async fn summarize_records(records: &[FrameRecord]) -> Result<String> {
-    if records.is_empty() {
-        return Ok("No frames processed; nothing to summarize.".to_string());
-    }
+/// Counters from the selection loop, used to explain why frames were (not) picked.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct SelectionStats {
+    /// Every frame the decoder produced
+    pub frames_decoded: u64,
+    /// Sample points considered for description (including the always-queued first frame)
+    pub candidates: u64,
+    /// Frames queued for description
+    pub selected: u64,
+    /// Candidates dropped as too similar to their neighbour or the reference
+    pub skipped: u64,
+    /// Timestamp of the last decoded frame
+    pub duration_secs: f64,
+    pub sample_interval_secs: f64,
+    /// Wall-clock time spent decoding and selecting, for comparing `DecodeSkip` settings
+    pub decode_secs: f64,
+    /// Pair winners not described because of `ProcessOptions::min_dissimilarity`
+    pub near_duplicates_skipped: u64,
+}
 
-    // Build a compact transcript
-    let mut transcript = String::with_capacity(1024);
-    transcript.push_str("Summarize the video in detail description, should be 3-5 sentences.\n\nFrames:\n. Based on all the frmaes, try to keep a story line and explain what happened in the video. Describe the story not the specific details.");
-    for r in records {
-        // Keep to one line per frame
-        use std::fmt::Write as _;
-        let _ = writeln!(transcript, "- [{:.1}s] {}", r.timestamp, r.description);
+impl SelectionStats {
+    /// One-sentence, non-technical explanation of the selection outcome.
+    pub fn explain(&self) -> String {
+        format!(
+            "Selected {} of {} candidate frames from a {} video ({} decoded frames) using {}s sampling; {} candidates were skipped as too similar to the frames around them.",
+            self.selected,
+            self.candidates,
+            format_duration(self.duration_secs),
+            self.frames_decoded,
+            self.sample_interval_secs,
+            self.skipped
+        )
     }
+}
 
-    let api_key = env::var("GOOGLE_API_KEY")?;
-    let client = Gemini::with_model(api_key, Model::Gemini25FlashLite)?;
+/// Everything `process_video` produces for one video.
+#[derive(Debug, Clone)]
+pub struct VideoAnalysis {
+    /// Described frames, sorted by timestamp
+    pub records: Vec<FrameRecord>,
+    pub stats: SelectionStats,
+    pub timings: PhaseTimings,
+    /// Content fingerprint as 64 hex chars (see `fingerprint_distance`); `None` if no
+    /// frame was selected
+    pub fingerprint: Option<String>,
+    /// Names this run's files in `data/`; for remote videos it comes from the downloaded copy
+    pub video_id: String,
+    /// Speech in timestamp order; empty unless `ProcessOptions::transcribe` is set
+    pub transcript: Vec<TranscriptSegment>,
+    pub metrics: RunMetrics,
+    pub video_info: VideoInfo,
+}
 
-    let response = client
-        .generate_content()
-        .with_user_message(transcript)
-        .execute()
-        .await?;
+/// Properties of the processed video stream, as reported by the container and decoder.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct VideoInfo {
+    /// `None` when neither the container nor the stream reports a duration
+    pub duration_secs: Option<f64>,
+    /// Average frame rate, falling back to the stream's base rate; `None` when neither is known
+    pub fps: Option<f64>,
+    pub width: u32,
+    pub height: u32,
+    /// FFmpeg codec name, e.g. "h264"
+    pub codec: String,
+}
 
-    Ok(response.text())
+/// Where a run's time and money went.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct RunMetrics {
+    pub decode_ms: u64,
+    /// Summed duration of every frame call; calls overlap, so this exceeds wall time
+    pub llm_call_ms: u64,
+    /// Frames with a description (failed, dry-run and reused frames aren't billed)
+    pub frames_described: u64,
+    /// Frames whose description was reused, see `ProcessOptions::reuse_descriptions`
+    pub description_cache_hits: u64,
+    /// From `approx_usd_per_image` of each frame's model; `None` when a model has no
+    /// known price
+    pub estimated_cost_usd: Option<f64>,
 }
 
-/// Process a whole video at `video_path`, scheduling frame analysis on a bounded
-/// async worker pool so LLM calls and encoding happen concurrently.
-pub async fn process_video(video_path: impl Into<PathBuf>) -> Result<Vec<FrameRecord>> {
-    ffmpeg::init().map_err(|e| anyhow::anyhow!("ffmpeg init failed: {e}"))?;
+/// Rough price of describing one frame with `model`: ~258 input tokens for the image plus
+/// the prompt, and ~200 output tokens, at list prices. `None` for models without a price.
+pub fn approx_usd_per_image(model: &Model) -> Option<f64> {
+    let (input_per_m, output_per_m) = match model {
+        Model::Gemini25FlashLite => (0.10, 0.40),
+        Model::Gemini25Flash => (0.30, 2.50),
+        Model::Gemini25Pro => (1.25, 10.00),
+        Model::TextEmbedding004 | Model::Custom(_) => return None,
+    };
+    Some((300.0 * input_per_m + 200.0 * output_per_m) / 1e6)
+}
 
-    let file_path = video_path.into();
-    fs::create_dir_all("data")
-        .await
-        .context("failed to ensure data directory exists")?;
+// Failed, dry-run and reused frames made no successful call
+fn billed(record: &FrameRecord) -> bool {
+    record.error.is_none() && !record.model.is_empty() && !record.cache_hit
+}
 
-    // Extract video ID from the filename (e.g., "1761542252139_crashDemo.mp4" -> "1761542252139_crashDemo")
-    let video_id = file_path
-        .file_stem()
-        .and_then(|s| s.to_str())
-        .unwrap_or("unknown")
-        .to_string();
-    
-    info!("Processing video with ID: {}", video_id);
+// Cost of the described `records`, by the model recorded on each
+fn estimated_cost(records: &[FrameRecord]) -> Option<f64> {
+    const KNOWN: [Model; 4] = [
+        Model::Gemini25Flash,
+        Model::Gemini25FlashLite,
+        Model::Gemini25Pro,
+        Model::TextEmbedding004,
+    ];
+    records
+        .iter()
+        .filter(|r| billed(r))
+        .map(|r| {
+            let model = KNOWN
+                .into_iter()
+                .find(|m| m.as_str() == r.model)
+                .unwrap_or_else(|| Model::Custom(r.model.clone()));
+            approx_usd_per_image(&model)
+        })
+        .sum()
+}
 
-    let api_key = env::var("GOOGLE_API_KEY")?;
-    let model_name = env::var("GEMINI_MODEL").ok();
-    let model = resolve_model(model_name.as_deref());
-    let max_concurrency = load_llm_max_concurrency();
-    let job_ctx = FrameJobContext::new(api_key, model, max_concurrency, video_id);
-    let mut tasks: JoinSet<Result<FrameRecord>> = JoinSet::new();
+/// Wall-clock time per pipeline phase, in milliseconds. Frames are encoded, written and
+/// described while decoding goes on, so the phases after `decode_ms` only cover what was
+/// left once decoding finished.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct PhaseTimings {
+    pub decode_ms: u64,
+    /// Wait for the frames still outstanding after decoding: their JPEG encoding, the
+    /// frame writes to `data/` (or storage) and the model calls
+    pub llm_ms: u64,
+    /// Writing the records JSON; frame JPEGs are written with their frame, in `llm_ms`
+    pub write_ms: u64,
+    /// Filled in by callers that summarize; `process_video` leaves it at 0
+    pub summary_ms: u64,
+}
 
-    // Run decode + selection in an isolated scope so ffmpeg types are dropped before awaits
-    let frames_enqueued = {
-        // Open input and prepare decoder
-        let mut ictx = ff_input(&file_path)
-            .with_context(|| format!("failed to open video file: {:?}", file_path))?;
-        let input_stream = ictx
-            .streams()
-            .best(FfmpegMediaType::Video)
-            .ok_or_else(|| anyhow::anyhow!("No video stream found"))?;
-        let stream_index = input_stream.index();
-        let time_base = input_stream.time_base();
-        let context_decoder = ffmpeg::codec::context::Context::from_parameters(input_stream.parameters())?;
-        let mut decoder = context_decoder.decoder().video()?;
+// Compact per-sample feature used for cosine similarity (64x64 grayscale -> 4096D)
+#[derive(Clone)]
+struct SampleFeature {
+    vec: Vec<f32>, // length 4096
+    l2: f32,
+}
 
-        // Helper scaler (lazy init) for winners -> RGB24 -> JPEG
-        let mut scaler: Option<FfmpegScaler> = None;
-        fn ensure_scaler_impl(
-            scaler: &mut Option<FfmpegScaler>,
-            src_format: FfmpegPixel,
-            w: u32,
-            h: u32,
-        ) -> Result<()> {
-            let need_new = match scaler {
-                Some(s) => {
-                    let inp = s.input();
-                    let out = s.output();
-                    inp.format != src_format
-                        || inp.width != w
-                        || inp.height != h
-                        || out.format != FfmpegPixel::RGB24
-                        || out.width != w
-                        || out.height != h
-                }
-                None => true,
-            };
-            if need_new {
-                *scaler = Some(
-                    FfmpegScaler::get(
-                        src_format,
-                        w,
-                        h,
-                        FfmpegPixel::RGB24,
-                        w,
-                        h,
-                        FfmpegScaleFlags::BILINEAR,
-                    )
-                    .map_err(anyhow::Error::from)?,
-                );
-            }
-            Ok(())
+// Computes comparison features for a `FeatureMode`, reusing vectors handed back with
+// `recycle` so the selection loop doesn't allocate one per candidate
+struct FeatureExtractor {
+    mode: FeatureMode,
+    scaler: Option<FfmpegScaler>,
+    /// Converts frames whose luma can't be read directly (10-bit, packed, RGB) to GRAY8
+    gray_scaler: Option<FfmpegScaler>,
+    free: Vec<Vec<f32>>,
+    /// Region luma features are computed from; `None` is the whole frame
+    crop: Option<CropRect>,
+}
+
+impl FeatureExtractor {
+    // The selection window holds at most ref + pending + current, plus a moment-of-interest probe
+    const MAX_FREE: usize = 4;
+
+    fn new(mode: FeatureMode) -> Self {
+        Self {
+            mode,
+            scaler: None,
+            gray_scaler: None,
+            free: Vec::new(),
+            crop: None,
         }
+    }
 
-        // Utility to convert a frame to RGB ImageBuffer for JPEG/LLM
-        let mut to_rgb_image = |frame: &FfmpegVideo| -> Result<ImageBuffer<Rgb<u8>, Vec<u8>>> {
-            ensure_scaler_impl(&mut scaler, frame.format(), frame.width(), frame.height())?;
-            let mut rgb = FfmpegVideo::empty();
-            scaler.as_mut().unwrap().run(frame, &mut rgb)?;
-            let w = rgb.width();
-            let h = rgb.height();
-            let stride = rgb.stride(0);
-            let src = rgb.data(0);
-            let row_len = (w as usize) * 3;
-            let mut out = vec![0u8; row_len * (h as usize)];
-            for y in 0..(h as usize) {
-                let src_off = y * stride;
-                let dst_off = y * row_len;
-                out[dst_off..dst_off + row_len].copy_from_slice(&src[src_off..src_off + row_len]);
+    fn compute(&mut self, frame: &FfmpegVideo) -> Result<(Vec<f32>, f32)> {
+        let mut feat = self.take();
+        let l2 = match self.mode {
+            FeatureMode::Luma if has_8bit_luma_plane(frame.format()) => {
+                compute_feature_from_y(frame, self.crop, &mut feat)?
+            }
+            FeatureMode::Luma => {
+                let gray = frame_to_gray(&mut self.gray_scaler, frame)?;
+                compute_feature_from_y(&gray, self.crop, &mut feat)?
             }
-            ImageBuffer::from_raw(w, h, out)
-                .ok_or_else(|| anyhow::anyhow!("failed to build RGB image"))
+            FeatureMode::Rgb => compute_feature_rgb(&mut self.scaler, frame, &mut feat)?,
+            FeatureMode::Histogram => compute_histogram_feature(&mut self.scaler, frame, &mut feat)?,
         };
+        Ok((feat, l2))
+    }
 
-        // Y-plane feature reference
-        let mut frames_enqueued = 0usize;
-        let first_frame_id = 0_u64;
-        info!("Decoding first frame and dispatching to LLM queue...");
-        let mut first_done = false;
+    // A recycled vector, or a new one while the pool is empty
+    fn take(&mut self) -> Vec<f32> {
+        self.free.pop().unwrap_or_default()
+    }
 
-        // Streaming pairwise selection state
-        let mut next_sample = 0.25_f64;
-        let mut next_id = 1_u64;
-        let mut frames_seen: u64 = 0;
-        let mut last_ts: f64 = 0.0;
-        let mut pending: Option<(u64, f64, FfmpegVideo, SampleFeature)> = None; // (id, ts, frame, feat)
-        let mut ref_vec: Vec<f32> = Vec::new();
-        let mut ref_l2: f32 = 0.0;
+    // Pooled copy, for a feature shared by several sample points
+    fn copy_of(&mut self, feat: &[f32]) -> Vec<f32> {
+        let mut copy = self.take();
+        copy.clear();
+        copy.extend_from_slice(feat);
+        copy
+    }
 
-        let mut receive_and_process = |decoder: &mut ffmpeg::decoder::Video,
-                                       packet_ts: Option<i64>|
-         -> Result<()> {
-            let mut decoded = FfmpegVideo::empty();
-            while decoder.receive_frame(&mut decoded).is_ok() {
-                // Timestamp in seconds
-                let ts_units = decoded.timestamp().or(packet_ts).unwrap_or(0);
-                let ts = (ts_units as f64)
-                    * (time_base.numerator() as f64 / time_base.denominator() as f64);
-                frames_seen += 1;
-                last_ts = ts;
+    fn recycle(&mut self, feat: Vec<f32>) {
+        if feat.capacity() > 0 && self.free.len() < Self::MAX_FREE {
+            self.free.push(feat);
+        }
+    }
+}
 
-                if !first_done {
-                    // Initialize reference from Y plane
-                    let (v, l2) = compute_feature_from_y(&decoded)?;
-                    ref_vec = v;
-                    ref_l2 = l2;
+/// Shares the process-wide LLM concurrency (`LLM_MAX_CONCURRENCY`) between tenants: when
+/// permits are contended they are handed out round-robin across tenants with waiting calls,
+/// so one tenant's long video can't starve everyone else's jobs.
+pub struct FairScheduler {
+    state: std::sync::Mutex<FairState>,
+}
 
-                    // Queue first frame for LLM
-                    let img = to_rgb_image(&decoded)?;
-                    job_ctx.queue(&mut tasks, first_frame_id, 0.0, img.clone());
-                    frames_enqueued += 1;
-                    first_done = true;
-                    continue;
-                }
+struct FairState {
+    capacity: usize,
+    available: usize,
+    // Tenants with waiting calls, in round-robin order
+    waiting: VecDeque<(String, VecDeque<oneshot::Sender<FairPermit>>)>,
+}
 
-                // Sampling and streaming pairwise selection
-                if ts + 1e-6 >= next_sample {
-                    // Compute features once for this decoded frame and reuse
-                    let (img_vec, img_l2) = compute_feature_from_y(&decoded)?;
-                    let src_format = decoded.format();
-                    let w = decoded.width();
-                    let h = decoded.height();
+/// Held for the duration of one LLM call; returns its slot to the scheduler on drop.
+pub struct FairPermit {
+    scheduler: Arc<FairScheduler>,
+    armed: bool,
+}
 
-                    while ts + 1e-6 >= next_sample {
-                        // Capture frame for potential queue; clone only when needed
-                        let mut owned = FfmpegVideo::empty();
-                        unsafe {
-                            // Allocate and copy decoded into owned clone
-                            owned.alloc(src_format, w, h);
-                        }
-                        // Copy planes
-                        for plane in 0..decoded.planes() {
-                            let src = decoded.data(plane);
-                            let stride = decoded.stride(plane);
-                            let plane_h = decoded.plane_height(plane) as usize;
-                            let row_len = stride;
-                            let dst = owned.data_mut(plane);
-                            for y in 0..plane_h {
-                                let s = &src[y * stride..y * stride + row_len];
-                                let d = &mut dst[y * stride..y * stride + row_len];
-                                d.copy_from_slice(s);
-                            }
-                        }
+impl FairScheduler {
+    pub fn new(permits: usize) -> Arc<Self> {
+        Arc::new(Self {
+            state: std::sync::Mutex::new(FairState {
+                capacity: permits.max(1),
+                available: permits.max(1),
+                waiting: VecDeque::new(),
+            }),
+        })
+    }
 
-                        let feat = SampleFeature { vec: img_vec.clone(), l2: img_l2 };
-                        match pending.take() {
-                            None => {
-                                pending = Some((next_id, next_sample, owned, feat));
-                            }
-                            Some((left_id, left_ts, left_frame, left_feat)) => {
-                                let cos_left = cosine_similarity_feats(
-                                    &ref_vec, ref_l2, &left_feat.vec, left_feat.l2,
-                                );
-                                let cos_right = cosine_similarity_feats(
-                                    &ref_vec, ref_l2, &feat.vec, feat.l2,
-                                );
-                                let choose_left = cos_left <= cos_right;
-                                info!(
-                                    "Cosines vs ref: id{} -> {:.6}, id{} -> {:.6}",
-                                    left_id, cos_left, next_id, cos_right
-                                );
+    /// Process-wide scheduler sized by `LLM_MAX_CONCURRENCY`.
+    pub fn global() -> Arc<Self> {
+        static GLOBAL: std::sync::OnceLock<Arc<FairScheduler>> = std::sync::OnceLock::new();
+        GLOBAL
+            .get_or_init(|| FairScheduler::new(load_llm_max_concurrency()))
+            .clone()
+    }
 
-                                if choose_left {
-                                    // Convert left_frame to RGB and queue
-                                    let img = to_rgb_image(&left_frame)?;
-                                    job_ctx.queue(&mut tasks, left_id, left_ts, img);
-                                    frames_enqueued += 1;
-                                    // Update reference
-                                    ref_vec = left_feat.vec;
-                                    ref_l2 = left_feat.l2;
-                                    // Shift window: current becomes new pending
-                                    pending = Some((next_id, next_sample, owned, feat));
-                                } else {
-                                    // Convert current frame to RGB and queue
-                                    let img = to_rgb_image(&owned)?;
-                                    job_ctx.queue(&mut tasks, next_id, next_sample, img);
-                                    frames_enqueued += 1;
-                                    ref_vec = feat.vec;
-                                    ref_l2 = feat.l2;
-                                    // Step by 2
-                                    pending = None;
-                                }
-                            }
-                        }
+    /// (calls in flight, calls waiting, total permits)
+    pub fn usage(&self) -> (usize, usize, usize) {
+        let state = self.state.lock().unwrap();
+        let waiting = state.waiting.iter().map(|(_, queue)| queue.len()).sum();
+        (state.capacity - state.available, waiting, state.capacity)
+    }
 
-                        info!("Sampled id={} at ~{:.3}s", next_id, next_sample);
-                        next_id += 1;
-                        next_sample += 0.25;
-                    }
-                }
+    pub async fn acquire(self: &Arc<Self>, tenant: &str) -> Result<FairPermit> {
+        let rx = {
+            let mut state = self.state.lock().unwrap();
+            if state.available > 0 && state.waiting.is_empty() {
+                state.available -= 1;
+                return Ok(FairPermit {
+                    scheduler: self.clone(),
+                    armed: true,
+                });
             }
-            Ok(())
+            let (tx, rx) = oneshot::channel();
+            match state.waiting.iter_mut().find(|(t, _)| t == tenant) {
+                Some((_, queue)) => queue.push_back(tx),
+                None => state.waiting.push_back((tenant.to_string(), VecDeque::from([tx]))),
+            }
+            rx
         };
+        rx.await.context("fair scheduler dropped a waiting call")
+    }
 
-        for (stream, packet) in ictx.packets() {
-            if stream.index() != stream_index {
+    fn release(self: &Arc<Self>) {
+        let mut state = self.state.lock().unwrap();
+        while let Some((tenant, mut queue)) = state.waiting.pop_front() {
+            let Some(tx) = queue.pop_front() else {
                 continue;
+            };
+            // This tenant goes to the back of the line if it still has calls waiting
+            if !queue.is_empty() {
+                state.waiting.push_back((tenant, queue));
+            }
+            let permit = FairPermit {
+                scheduler: self.clone(),
+                armed: true,
+            };
+            match tx.send(permit) {
+                Ok(()) => return,
+                // Waiter went away (job cancelled); offer the slot to the next one
+                Err(mut permit) => permit.armed = false,
             }
-            decoder.send_packet(&packet)?;
-            receive_and_process(&mut decoder, packet.dts())?;
         }
-        decoder.send_eof()?;
-        receive_and_process(&mut decoder, None)?;
-
-        info!("Decode loop finished");
-        info!("Total frames enqueued for LLM processing: {}", frames_enqueued);
-        frames_enqueued
-    };
-
-    info!("Total frames enqueued for LLM processing: {}", frames_enqueued);
-
-    let mut records: Vec<FrameRecord> = Vec::new();
-    while let Some(result) = tasks.join_next().await {
-        let record = result.context("LLM task join error")??;
-        records.push(record);
+        state.available += 1;
     }
+}
 
-    records.sort_by(|a, b| {
-        a.timestamp
-            .partial_cmp(&b.timestamp)
-            .unwrap_or(Ordering::Equal)
-    });
-
-    info!("Processing complete: {} records", records.len());
-    
-    // Now write all frames to disk in parallel
-    info!("Writing {} frames to disk...", records.len());
-    let mut write_tasks = JoinSet::new();
-    for record in &records {
-        if let Some(bytes) = &record.jpeg_bytes {
-            let path = record.path.clone();
-            let bytes = bytes.clone();
-            write_tasks.spawn(async move {
-                fs::write(&path, &bytes)
-                    .await
-                    .with_context(|| format!("failed to write frame to {}", path))
-            });
+impl Drop for FairPermit {
+    fn drop(&mut self) {
+        if self.armed {
+            self.scheduler.release();
         }
     }
-    
-    // Wait for all writes to complete
-    while let Some(result) = write_tasks.join_next().await {
-        result.context("disk write task join error")??;
-    }
-    info!("All frames written to disk");
-    
-    println!("{}", serde_json::to_string_pretty(&records)?);
-    Ok(records)
 }
 
+static ACTIVE_JOBS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+// Counts a running `process_video`/`monitor_live` call for `load_snapshot` and the
+// `jobs_in_flight` gauge; dropped on return, error and cancellation alike
+struct ActiveJob;
+
+impl ActiveJob {
+    fn start() -> Self {
+        ACTIVE_JOBS.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        metrics::gauge!("jobs_in_flight").increment(1.0);
+        ActiveJob
+    }
+}
+
+impl Drop for ActiveJob {
+    fn drop(&mut self) {
+        ACTIVE_JOBS.fetch_sub(1, std::sync::atomic::Ordering::Relaxed);
+        metrics::gauge!("jobs_in_flight").decrement(1.0);
+    }
+}
+
+static PROMETHEUS: std::sync::OnceLock<metrics_exporter_prometheus::PrometheusHandle> =
+    std::sync::OnceLock::new();
+
+// Decode loops range from a short clip to hours of footage
+const DECODE_SECONDS_BUCKETS: &[f64] = &[0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0];
+
+/// Install the Prometheus recorder rendered by `render_metrics`. Call once at startup;
+/// anything recorded before it is lost.
+pub fn install_metrics() -> Result<()> {
+    use metrics_exporter_prometheus::{Matcher, PrometheusBuilder};
+    let handle = PrometheusBuilder::new()
+        .set_buckets_for_metric(
+            Matcher::Full("decode_duration_seconds".to_string()),
+            DECODE_SECONDS_BUCKETS,
+        )?
+        .install_recorder()?;
+    metrics::describe_counter!("videos_processed_total", "process_video runs that finished");
+    metrics::describe_counter!("videos_failed_total", "process_video runs that failed or were cancelled");
+    metrics::describe_counter!("frames_enqueued_total", "Frames queued for description");
+    metrics::describe_counter!("frames_described_total", "Frames described by the model");
+    metrics::describe_counter!("llm_errors_total", "Frame calls that failed after any fallback");
+    metrics::describe_counter!("rate_limited_total", "Requests refused by the per-client rate limit");
+    metrics::describe_histogram!(
+        "decode_duration_seconds",
+        metrics::Unit::Seconds,
+        "Decode and selection time per video"
+    );
+    metrics::describe_gauge!("jobs_in_flight", "Running process_video and monitor_live calls");
+    let _ = PROMETHEUS.set(handle);
+    Ok(())
+}
+
+/// Metrics in the Prometheus text format; empty until `install_metrics` has run.
+pub fn render_metrics() -> String {
+    PROMETHEUS.get().map(|handle| handle.render()).unwrap_or_default()
+}
+
+// Cancellation and progress of one `process_video` call
+#[derive(Default)]
+struct RunningJob {
+    token: CancellationToken,
+    // Frame calls wait while this is true
+    paused: tokio::sync::watch::Sender<bool>,
+    decoded: std::sync::atomic::AtomicU64,
+    enqueued: std::sync::atomic::AtomicU64,
+    described: std::sync::atomic::AtomicU64,
+    total_estimate: std::sync::atomic::AtomicU64,
+    // Set before `token` is cancelled by `ProcessOptions::job_timeout_secs`
+    timed_out: std::sync::atomic::AtomicBool,
+    // Set before `token` is cancelled by `JobControl::stop`
+    stopping: std::sync::atomic::AtomicBool,
+    // `ProcessOptions::tenant` of the run, the only one that may cancel or watch it
+    tenant: std::sync::Mutex<Option<String>>,
+}
+
+impl RunningJob {
+    fn set(counter: &std::sync::atomic::AtomicU64, value: u64) {
+        counter.store(value, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    fn bump(counter: &std::sync::atomic::AtomicU64) {
+        counter.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    // Cancelled by `JobControl::stop`: the run winds down but keeps what it has
+    fn is_stopping(&self) -> bool {
+        self.token.is_cancelled() && self.stopping.load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    async fn wait_while_paused(&self) {
+        // The sender lives in `self`, so this only returns once resumed
+        let _ = self.paused.subscribe().wait_for(|paused| !*paused).await;
+    }
+}
+
+/// Handle to pause, resume or cancel a `process_video` run, given to it through
+/// `ProcessOptions::control`. Pausing holds frames before their LLM call; decoding and
+/// selection carry on.
+#[derive(Clone, Default)]
+pub struct JobControl(Arc<RunningJob>);
+
+impl std::fmt::Debug for JobControl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JobControl")
+            .field("paused", &self.is_paused())
+            .field("cancelled", &self.0.token.is_cancelled())
+            .finish()
+    }
+}
+
+impl JobControl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Same effect as `cancel_job` on the run's video id.
+    pub fn cancel(&self) {
+        self.0.token.cancel();
+    }
+
+    /// End the run early but keep its results: no further frames are selected, queued
+    /// frames that haven't started their call are dropped, calls in flight complete, and
+    /// `process_video` returns the partial analysis instead of an error.
+    pub fn stop(&self) {
+        self.0.stopping.store(true, std::sync::atomic::Ordering::Relaxed);
+        self.0.token.cancel();
+        // Paused frames would otherwise wait forever to be dropped
+        self.resume();
+    }
+
+    pub fn pause(&self) {
+        self.0.paused.send_replace(true);
+    }
+
+    pub fn resume(&self) {
+        self.0.paused.send_replace(false);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.0.paused.borrow()
+    }
+}
+
+// Running `process_video` calls by video id, tagged with a run sequence number so a
+// finished run doesn't unregister a newer one of the same video
+type JobTable = HashMap<String, (u64, Arc<RunningJob>)>;
+static RUNNING_JOBS: std::sync::LazyLock<std::sync::Mutex<JobTable>> =
+    std::sync::LazyLock::new(Default::default);
+static NEXT_RUN: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+
+// Keeps a run visible to `cancel_job` and `job_progress` until dropped
+struct JobRegistration {
+    video_id: String,
+    run: u64,
+    job: Arc<RunningJob>,
+}
+
+impl JobRegistration {
+    fn register(video_id: &str, control: Option<&JobControl>, tenant: Option<&str>) -> Self {
+        let run = NEXT_RUN.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        let job: Arc<RunningJob> =
+            control.map_or_else(Default::default, |control| control.0.clone());
+        *job.tenant.lock().unwrap() = tenant.map(str::to_string);
+        RUNNING_JOBS
+            .lock()
+            .unwrap()
+            .insert(video_id.to_string(), (run, job.clone()));
+        Self {
+            video_id: video_id.to_string(),
+            run,
+            job,
+        }
+    }
+
+    fn check(&self) -> Result<()> {
+        if self.job.token.is_cancelled() {
+            return Err(self.cancelled());
+        }
+        Ok(())
+    }
+
+    fn cancelled(&self) -> anyhow::Error {
+        if self.job.timed_out.load(std::sync::atomic::Ordering::Relaxed) {
+            anyhow::anyhow!("processing of {} timed out", self.video_id)
+        } else {
+            anyhow::anyhow!("processing of {} was cancelled", self.video_id)
+        }
+    }
+
+    // Cancels the run once `limit` has passed, unless the returned guard is dropped first
+    fn deadline(&self, limit: std::time::Duration) -> tokio_util::sync::DropGuard {
+        let finished = CancellationToken::new();
+        let (job, done) = (self.job.clone(), finished.clone());
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = tokio::time::sleep(limit) => {
+                    job.timed_out.store(true, std::sync::atomic::Ordering::Relaxed);
+                    job.token.cancel();
+                }
+                _ = done.cancelled() => {}
+            }
+        });
+        finished.drop_guard()
+    }
+}
+
+impl Drop for JobRegistration {
+    fn drop(&mut self) {
+        let mut jobs = RUNNING_JOBS.lock().unwrap();
+        if jobs.get(&self.video_id).is_some_and(|(run, _)| *run == self.run) {
+            jobs.remove(&self.video_id);
+        }
+    }
+}
+
+// The running `process_video` call for `video_id`, if it belongs to `tenant`
+fn running_job(video_id: &str, tenant: Option<&str>) -> Option<Arc<RunningJob>> {
+    let jobs = RUNNING_JOBS.lock().unwrap();
+    let (_, job) = jobs.get(video_id)?;
+    let owner = job.tenant.lock().unwrap();
+    (owner.as_deref() == tenant).then(|| job.clone())
+}
+
+/// Cancel the running `process_video` call for `video_id` started by `tenant`, which then
+/// fails with a "cancelled" error after aborting its outstanding frame calls. Returns
+/// false if no run of that video is in flight, or it belongs to another tenant.
+pub fn cancel_job(video_id: &str, tenant: Option<&str>) -> bool {
+    match running_job(video_id, tenant) {
+        Some(job) => {
+            job.token.cancel();
+            true
+        }
+        None => false,
+    }
+}
+
+/// Where a running `process_video` call is.
+#[derive(Serialize, Debug, Clone)]
+pub struct JobProgress {
+    pub decoded: u64,
+    /// Frames queued for description
+    pub enqueued: u64,
+    pub described: u64,
+    /// Candidate frames expected from the duration and sampling interval; 0 until the
+    /// video is opened or when its duration is unknown
+    pub total_estimate: u64,
+}
+
+/// Progress of the running `process_video` call for `video_id` started by `tenant`,
+/// `None` if none is in flight or it belongs to another tenant.
+pub fn job_progress(video_id: &str, tenant: Option<&str>) -> Option<JobProgress> {
+    let job = running_job(video_id, tenant)?;
+    let get = |counter: &std::sync::atomic::AtomicU64| counter.load(std::sync::atomic::Ordering::Relaxed);
+    Some(JobProgress {
+        decoded: get(&job.decoded),
+        enqueued: get(&job.enqueued),
+        described: get(&job.described),
+        total_estimate: get(&job.total_estimate),
+    })
+}
+
+/// Current server load, for load balancers doing weighted routing.
+#[derive(Serialize, Debug, Clone)]
+pub struct LoadSnapshot {
+    /// Running video jobs and live monitors
+    pub active_jobs: usize,
+    pub llm_in_flight: usize,
+    /// Description calls waiting for an LLM permit
+    pub llm_queued: usize,
+    pub llm_capacity: usize,
+    /// False once every LLM permit is busy and at least as many calls again are queued
+    pub accepting: bool,
+}
+
+pub fn load_snapshot() -> LoadSnapshot {
+    let (llm_in_flight, llm_queued, llm_capacity) = FairScheduler::global().usage();
+    LoadSnapshot {
+        active_jobs: ACTIVE_JOBS.load(std::sync::atomic::Ordering::Relaxed),
+        llm_in_flight,
+        llm_queued,
+        llm_capacity,
+        accepting: llm_in_flight < llm_capacity || llm_queued < llm_capacity,
+    }
+}
+
+/// Runtime context for queuing frame-description jobs with bounded concurrency.
+#[derive(Clone)]
+struct FrameJobContext {
+    /// Makes the primary frame calls
+    describer: Arc<dyn VisionDescriber>,
+    /// Gemini-only features: prompt caching, `fallback_model` and transcription
+    gemini: Arc<GeminiConfig>,
+    model: Model,
+    semaphore: Arc<Semaphore>,
+    video_id: Arc<String>, // Unique identifier for this video
+    mode: DescriptionMode,
+    rewrites: Arc<Vec<DescriptionRewrite>>,
+    scheduler: Arc<FairScheduler>,
+    tenant: Arc<String>,
+    llm_max_dim: Option<u32>,
+    store_max_dim: Option<u32>,
+    max_frame_width: Option<u32>,
+    jpeg_quality: u8,
+    /// Name of the cached content holding the frame prompt, when prompt caching is on
+    cached_prompt: Option<Arc<String>>,
+    labels: Arc<Vec<String>>,
+    /// Classification prompt built from `labels`, replacing the description prompt
+    label_prompt: Option<Arc<String>>,
+    /// Description prompt: the caller's or the one of `mode`, followed by the language
+    /// instruction, and by `CONFIDENCE_PROMPT` unless it is the caller's
+    frame_prompt: Arc<String>,
+    fallback_model: Option<Model>,
+    frame_timeout: std::time::Duration,
+    deterministic: bool,
+    generation: GenerationParams,
+    /// Counts queued frames for `job_progress`
+    progress: Option<Arc<RunningJob>>,
+    /// Return each frame's JPEG in `FrameRecord::jpeg_bytes` after writing it
+    keep_images: bool,
+    /// Summed duration of model calls, for `RunMetrics::llm_call_ms`
+    llm_call_micros: Arc<std::sync::atomic::AtomicU64>,
+    /// Stop after writing each frame, see `ProcessOptions::dry_run`
+    dry_run: bool,
+    /// Last queued frame, sent as context with the next one; `None` slot without
+    /// `ProcessOptions::with_context_frame`
+    previous_frame: Option<Arc<std::sync::Mutex<Option<Arc<RgbFrame>>>>>,
+    /// Scope of this run's entries in `DESCRIPTION_CACHE`; `None` unless
+    /// `ProcessOptions::reuse_descriptions` applies
+    description_key: Option<Arc<String>>,
+    /// Set when faces are redacted, see `ProcessOptions::redact_faces`
+    face_detector: Option<Arc<dyn FaceDetector>>,
+    /// Set when frames share calls, see `ProcessOptions::describe_batch_size`
+    batcher: Option<Arc<FrameBatcher>>,
+}
+
+type RgbFrame = ImageBuffer<Rgb<u8>, Vec<u8>>;
+
+/// Most frames one call may describe with `ProcessOptions::describe_batch_size`.
+pub const MAX_DESCRIBE_BATCH_SIZE: usize = 16;
+
+// A batch that isn't full is sent anyway once its first frame has waited this long
+const DESCRIBE_BATCH_WAIT: std::time::Duration = std::time::Duration::from_secs(2);
+
+// A frame's JPEG waiting for a batch call, and where its reply goes
+type BatchedFrame = (Vec<u8>, oneshot::Sender<Option<String>>);
+
+// Frames collected for the next batch call of a run
+struct FrameBatcher {
+    size: usize,
+    /// Frame prompt without `CONFIDENCE_PROMPT`, which asks about a single image
+    prompt: Arc<String>,
+    /// Waiting frames, and how many batches were sent so a wait timer can tell its batch
+    /// already went
+    pending: std::sync::Mutex<(Vec<BatchedFrame>, u64)>,
+}
+
+impl FrameBatcher {
+    fn new(size: usize, prompt: String) -> Self {
+        Self {
+            size,
+            prompt: Arc::new(prompt),
+            pending: std::sync::Mutex::new((Vec::new(), 0)),
+        }
+    }
+}
+
+// Frames per call with `ProcessOptions::describe_batch_size`, `None` when frames go alone
+fn describe_batch_size(options: &ProcessOptions, max_concurrency: usize) -> Option<usize> {
+    options
+        .describe_batch_size
+        .filter(|&size| size > 1)
+        .filter(|_| options.describer.is_none() && options.labels.is_empty())
+        .filter(|_| !(options.with_context_frame || options.prompt_cache || options.dry_run))
+        .filter(|_| options.fallback_model.is_none())
+        // A batch can't hold more frames than are let in flight
+        .map(|size| size.min(MAX_DESCRIBE_BATCH_SIZE).min(max_concurrency.max(1)))
+}
+
+impl FrameJobContext {
+    fn new(
+        gemini: GeminiConfig,
+        model: Model,
+        max_concurrency: usize,
+        video_id: String,
+        options: &ProcessOptions,
+    ) -> Self {
+        let describer = options.describer.clone().unwrap_or_else(|| {
+            let provider =
+                GeminiProvider::new(gemini.clone(), model.clone(), options.deterministic);
+            Arc::new(provider.with_generation(options.generation))
+        });
+        let mut prompt = options
+            .frame_prompt
+            .as_deref()
+            .unwrap_or(frame_prompt(options.description_mode))
+            .to_string();
+        if let Some(language) = &options.language {
+            prompt = format!("{}\n\n{}", prompt, respond_in(language));
+        }
+        // A caller's own prompt is sent as written; the confidence JSON would override
+        // whatever reply it asks for
+        let described_prompt = match options.frame_prompt {
+            Some(_) => prompt.clone(),
+            None => format!("{}\n\n{}", prompt, CONFIDENCE_PROMPT),
+        };
+        let batch_size = describe_batch_size(options, max_concurrency);
+        let mut ctx = Self {
+            describer,
+            gemini: Arc::new(gemini),
+            model,
+            semaphore: Arc::new(Semaphore::new(max_concurrency.max(1))),
+            video_id: Arc::new(video_id),
+            mode: options.description_mode,
+            rewrites: Arc::new(options.description_rewrites.clone()),
+            scheduler: FairScheduler::global(),
+            tenant: Arc::new(options.tenant.clone().unwrap_or_else(|| DEFAULT_TENANT.to_string())),
+            llm_max_dim: options.llm_max_dim,
+            store_max_dim: options.store_max_dim,
+            max_frame_width: options.max_frame_width,
+            jpeg_quality: options.jpeg_quality.clamp(1, 100),
+            cached_prompt: None,
+            labels: Arc::new(options.labels.clone()),
+            label_prompt: (!options.labels.is_empty())
+                .then(|| Arc::new(label_prompt(&options.labels))),
+            frame_prompt: Arc::new(described_prompt),
+            fallback_model: options.fallback_model.as_deref().map(|name| resolve_model(Some(name))),
+            frame_timeout: options
+                .frame_timeout_secs
+                .or(options.fallback_model.as_ref().map(|_| DEFAULT_FRAME_TIMEOUT_SECS))
+                .map_or_else(gemini_call_timeout, std::time::Duration::from_secs_f64),
+            deterministic: options.deterministic,
+            generation: options.generation,
+            progress: None,
+            keep_images: options.keep_images,
+            llm_call_micros: Default::default(),
+            dry_run: options.dry_run,
+            previous_frame: options.with_context_frame.then(Default::default),
+            description_key: None,
+            face_detector: options.face_detector.clone().filter(|_| options.redact_faces),
+            batcher: batch_size.map(|size| Arc::new(FrameBatcher::new(size, prompt))),
+        };
+        if options.reuse_descriptions && !options.with_context_frame {
+            // Who may see the description, then everything that shapes it besides the image
+            let scope = match &options.description_cache_scope {
+                Some(scope) => format!("scope {}", scope),
+                None => format!("video {}", ctx.video_id),
+            };
+            let key = format!(
+                "{}\n{}\n{}\n{}\n{:?}\n{} {:?}",
+                ctx.tenant,
+                scope,
+                ctx.describer.model_name(),
+                ctx.prompt(),
+                ctx.rewrites,
+                ctx.deterministic,
+                ctx.generation
+            );
+            ctx.description_key = Some(Arc::new(key));
+        }
+        ctx
+    }
+
+    // Describe with the primary model, degrading to `fallback_model` when the call runs past
+    // `frame_timeout` or is rate limited. `context` is the previous frame's JPEG, only sent
+    // with inline prompts. Returns the answer and the name of the model that gave it.
+    async fn describe(
+        &self,
+        jpeg: Vec<u8>,
+        prompt: FramePrompt<'_>,
+        context: Option<Vec<u8>>,
+    ) -> Result<(String, String)> {
+        let context_prompt;
+        let (prompt, context) = match (prompt, context) {
+            (FramePrompt::Inline(text), Some(previous)) => {
+                context_prompt = format!("{}\n\n{}", text, CONTEXT_FRAME_PROMPT);
+                (FramePrompt::Inline(&context_prompt), Some(previous))
+            }
+            (prompt, _) => (prompt, None),
+        };
+        let primary = async {
+            match (prompt, &context) {
+                // Only set up for the built-in Gemini describer
+                (FramePrompt::Cached { .. }, _) => {
+                    let (gemini, model) = (&self.gemini, self.model.clone());
+                    let jpeg = jpeg.clone();
+                    let sampling = (self.deterministic, self.generation);
+                    describe_jpeg_bytes(gemini, model, jpeg, None, prompt, sampling).await
+                }
+                (FramePrompt::Inline(text), Some(previous)) => {
+                    self.describer.describe_with_context(previous, &jpeg, text).await
+                }
+                (FramePrompt::Inline(text), None) => self.describer.describe(&jpeg, text).await,
+            }
+        };
+        let limit = self.frame_timeout;
+        let outcome = tokio::time::timeout(limit, primary)
+            .await
+            .map_err(|_| anyhow::anyhow!("frame call timed out after {:?}", limit));
+        let error = match outcome {
+            Ok(Ok(text)) => return Ok((text, self.describer.model_name())),
+            Ok(Err(e)) if !is_rate_limited(&e) => return Err(e),
+            Ok(Err(e)) | Err(e) => e,
+        };
+        let Some(fallback) = &self.fallback_model else {
+            return Err(error);
+        };
+        warn!(
+            "{} failed for a frame ({}); retrying with {}",
+            self.describer.model_name(),
+            error,
+            fallback
+        );
+        // Cached content belongs to the primary model, so the fallback gets the prompt inline
+        let prompt = match (prompt, &context) {
+            (FramePrompt::Inline(text), Some(_)) => FramePrompt::Inline(text),
+            _ => FramePrompt::Inline(self.prompt()),
+        };
+        let call = describe_jpeg_bytes(
+            &self.gemini,
+            fallback.clone(),
+            jpeg,
+            context,
+            prompt,
+            (self.deterministic, self.generation),
+        );
+        let text = with_call_timeout(gemini_call_timeout(), call).await?;
+        Ok((text, fallback.as_str().to_string()))
+    }
+
+    fn prompt(&self) -> &str {
+        self.label_prompt.as_deref().unwrap_or(&self.frame_prompt)
+    }
+
+    // One fair-scheduled model call for an encoded frame (the decode loop's or an uploaded
+    // image), with rewrites and alt-text shortening applied. `context` is the previous
+    // frame's JPEG, see `ProcessOptions::with_context_frame`.
+    async fn describe_frame(&self, jpeg: Vec<u8>, context: Option<Vec<u8>>) -> Result<FrameDescription> {
+        let prompt = match &self.cached_prompt {
+            Some(name) => FramePrompt::Cached {
+                name,
+                json: FramePrompt::Inline(self.prompt()).asks_for_json(),
+            },
+            None => FramePrompt::Inline(self.prompt()),
+        };
+        let classifying = self.label_prompt.is_some();
+        let batched = match (&self.batcher, &context) {
+            (Some(batcher), None) if !classifying => {
+                self.describe_batched(batcher, jpeg.clone()).await
+            }
+            _ => None,
+        };
+        let answer = match batched {
+            Some(reply) => Ok((reply, Vec::new(), self.describer.model_name())),
+            None => {
+                let fair_permit = self.scheduler.acquire(&self.tenant).await?;
+                let started = Instant::now();
+                let answer = if classifying {
+                    self.classify(prompt, jpeg)
+                        .await
+                        .map(|(labels, model)| (labels.join(", "), labels, model))
+                } else {
+                    self.describe(jpeg, prompt, context)
+                        .await
+                        .map(|(description, model)| (description, Vec::new(), model))
+                };
+                self.add_llm_time(started);
+                drop(fair_permit);
+                answer
+            }
+        };
+        let (mut description, labels, model) = answer?;
+        let mut alt_text = None;
+        let mut confidence = None;
+        if !classifying {
+            (description, confidence) = parse_confidence(description);
+            for rewrite in self.rewrites.iter() {
+                description = rewrite.apply(&description);
+            }
+            if self.mode == DescriptionMode::AltText {
+                description = to_alt_text(&description);
+                alt_text = Some(description.clone());
+            }
+        }
+        Ok(FrameDescription {
+            description,
+            labels,
+            alt_text,
+            model,
+            confidence,
+        })
+    }
+
+    fn add_llm_time(&self, started: Instant) {
+        self.llm_call_micros.fetch_add(
+            started.elapsed().as_micros() as u64,
+            std::sync::atomic::Ordering::Relaxed,
+        );
+    }
+
+    // Add `jpeg` to the next batch call and wait for its reply. `None` when the call failed
+    // or its reply left this frame out, so the caller describes the frame alone.
+    async fn describe_batched(&self, batcher: &Arc<FrameBatcher>, jpeg: Vec<u8>) -> Option<String> {
+        let (sender, reply) = oneshot::channel();
+        let (full, first) = {
+            let mut pending = batcher.pending.lock().unwrap();
+            let (frames, sent) = &mut *pending;
+            frames.push((jpeg, sender));
+            if frames.len() >= batcher.size {
+                *sent += 1;
+                (Some(std::mem::take(frames)), None)
+            } else {
+                (None, (frames.len() == 1).then_some(*sent))
+            }
+        };
+        if let Some(frames) = full {
+            self.send_batch(batcher, frames);
+        } else if let Some(sent) = first {
+            // The first frame of a batch sends it after a while, full or not
+            let (ctx, batcher) = (self.clone(), batcher.clone());
+            tokio::spawn(
+                async move {
+                    tokio::time::sleep(DESCRIBE_BATCH_WAIT).await;
+                    let frames = {
+                        let mut pending = batcher.pending.lock().unwrap();
+                        let (frames, now_sent) = &mut *pending;
+                        // Already sent full
+                        if *now_sent != sent || frames.is_empty() {
+                            return;
+                        }
+                        *now_sent += 1;
+                        std::mem::take(frames)
+                    };
+                    ctx.send_batch(&batcher, frames);
+                }
+                .in_current_span(),
+            );
+        }
+        reply.await.ok().flatten()
+    }
+
+    // One call describing `frames`, under a single fair-scheduler permit
+    fn send_batch(&self, batcher: &FrameBatcher, mut frames: Vec<BatchedFrame>) {
+        // A frame left alone by the wait is better off with the single-image prompt
+        if frames.len() == 1 {
+            let (_, sender) = frames.remove(0);
+            let _ = sender.send(None);
+            return;
+        }
+        let (ctx, prompt) = (self.clone(), batcher.prompt.clone());
+        tokio::spawn(
+            async move {
+                let (jpegs, senders): (Vec<_>, Vec<_>) = frames.into_iter().unzip();
+                let count = jpegs.len();
+                let replies = async {
+                    let _fair_permit = ctx.scheduler.acquire(&ctx.tenant).await?;
+                    let started = Instant::now();
+                    let sampling = (ctx.deterministic, ctx.generation);
+                    let call = describe_jpeg_batch(
+                        &ctx.gemini,
+                        ctx.model.clone(),
+                        jpegs,
+                        &prompt,
+                        sampling,
+                    );
+                    let limit = ctx.frame_timeout;
+                    let replies = tokio::time::timeout(limit, call)
+                        .await
+                        .map_err(|_| anyhow::anyhow!("batch call timed out after {:?}", limit));
+                    ctx.add_llm_time(started);
+                    replies?
+                };
+                let replies = replies.await.unwrap_or_else(|e: anyhow::Error| {
+                    warn!("Batch of {} frames failed ({:#}); describing them one by one", count, e);
+                    vec![None; count]
+                });
+                let answered = replies.iter().flatten().count();
+                if answered > 0 && answered < count {
+                    warn!(
+                        "Batch reply described {} of {} frames; describing the rest one by one",
+                        answered, count
+                    );
+                }
+                for (sender, reply) in senders.into_iter().zip(replies) {
+                    let _ = sender.send(reply);
+                }
+            }
+            .in_current_span(),
+        );
+    }
+
+    // Ask for labels, re-prompting once when the answer strays outside the label set
+    async fn classify(
+        &self,
+        prompt: FramePrompt<'_>,
+        jpeg: Vec<u8>,
+    ) -> Result<(Vec<String>, String)> {
+        let (answer, model) = self.describe(jpeg.clone(), prompt, None).await?;
+        let (labels, valid) = match_labels(&answer, &self.labels);
+        if valid {
+            return Ok((labels, model));
+        }
+        let retry = format!(
+            "{}\nYour previous answer \"{}\" used words outside the list. Reply with labels from the list only.",
+            self.prompt(),
+            answer.trim()
+        );
+        let (answer, model) = self.describe(jpeg, FramePrompt::Inline(&retry), None).await?;
+        let (labels, valid) = match_labels(&answer, &self.labels);
+        if !valid {
+            warn!(
+                "Model answered \"{}\" outside the label set twice; keeping {:?}",
+                answer.trim(),
+                labels
+            );
+        }
+        Ok((labels, model))
+    }
+
+    /// Store the frame prompt as cached content for this run. Failures (e.g. the prompt is
+    /// below the provider's minimum cacheable size) leave the context on inline prompts.
+    async fn with_prompt_cache(mut self) -> Self {
+        let created = async {
+            let client = self.gemini.client(self.model.clone()).await?;
+            let handle = client
+                .create_cache()
+                .with_display_name(format!("frames-{}", self.video_id))?
+                .with_system_instruction(self.prompt())
+                .with_ttl(PROMPT_CACHE_TTL)
+                .execute()
+                .await?;
+            Ok::<_, anyhow::Error>(handle.name().to_string())
+        };
+        match created.await {
+            Ok(name) => {
+                info!("Using cached frame prompt {}", name);
+                self.cached_prompt = Some(Arc::new(name));
+            }
+            Err(e) => warn!("Prompt caching unavailable, sending prompts inline: {}", e),
+        }
+        self
+    }
+
+    // Best effort; an undeleted cache expires after `PROMPT_CACHE_TTL`
+    async fn release_prompt_cache(&self) {
+        let Some(name) = &self.cached_prompt else {
+            return;
+        };
+        let deleted = async {
+            let client = self.gemini.client(self.model.clone()).await?;
+            client
+                .get_cached_content(name)
+                .delete()
+                .await
+                .map_err(|(_, e)| anyhow::Error::from(e))
+        };
+        if let Err(e) = deleted.await {
+            warn!("Failed to delete cached frame prompt {}: {}", name, e);
+        }
+    }
+
+    /// Build a context from the Gemini endpoint env (unless overridden in `options`),
+    /// `GEMINI_MODEL` and `LLM_MAX_CONCURRENCY` (unless `options.max_concurrency` is set).
+    fn from_env(video_id: String, options: &ProcessOptions) -> Result<Self> {
+        let gemini = match &options.gemini {
+            Some(gemini) => gemini.clone(),
+            // Dry runs never call the model, and other describers may not need Gemini at all
+            None if options.dry_run || options.describer.is_some() => {
+                GeminiConfig::from_env().unwrap_or(GeminiConfig {
+                    base_url: None,
+                    auth: GeminiAuth::ApiKey,
+                    api_key: String::new(),
+                })
+            }
+            None => GeminiConfig::from_env()?,
+        };
+        let model_name = env::var("GEMINI_MODEL").ok();
+        let model = resolve_model(model_name.as_deref());
+        let max_concurrency = options
+            .max_concurrency
+            .unwrap_or_else(load_llm_max_concurrency);
+        let mut ctx = Self::new(gemini, model, max_concurrency, video_id, options);
+        if options.redact_faces && ctx.face_detector.is_none() {
+            // Never fall back to sending faces unblurred
+            let detector = HttpFaceDetector::from_env()?
+                .context("redact_faces needs a face detector; set FACE_DETECTOR_URL")?;
+            ctx.face_detector = Some(Arc::new(detector));
+        }
+        Ok(ctx)
+    }
+
+    /// Lower concurrency so in-flight descriptions of `width`x`height` frames fit the budget
+    /// from `LLM_MEMORY_BUDGET_MB`. Must be called before any frame is queued.
+    fn capped_for_frame(self, width: u32, height: u32) -> Self {
+        let configured = self.semaphore.available_permits();
+        let per_frame = estimate_in_flight_bytes(width, height);
+        let effective = memory_capped_concurrency(configured, per_frame, load_llm_memory_budget());
+        info!(
+            "LLM concurrency {} (configured {}, ~{} KB per in-flight {}x{} frame)",
+            effective,
+            configured,
+            per_frame / 1024,
+            width,
+            height
+        );
+        self.with_concurrency(effective)
+    }
+
+    // `effective` permits, with batches shrunk to fit since a batch can't hold more frames
+    // than are let in flight
+    fn with_concurrency(self, effective: usize) -> Self {
+        let batcher = self.batcher.and_then(|batcher| match batcher.size.min(effective) {
+            size if size == batcher.size => Some(batcher),
+            // Frames go alone
+            0 | 1 => None,
+            size => Some(Arc::new(FrameBatcher::new(size, batcher.prompt.to_string()))),
+        });
+        Self {
+            semaphore: Arc::new(Semaphore::new(effective)),
+            batcher,
+            ..self
+        }
+    }
+
+    fn queue(
+        &self,
+        tasks: &mut JoinSet<FrameRecord>,
+        frame_id: u64,
+        timestamp: f64,
+        image: ImageBuffer<Rgb<u8>, Vec<u8>>,
+        hash: Option<u64>,
+    ) {
+        if let Some(job) = &self.progress {
+            RunningJob::bump(&job.enqueued);
+        }
+        metrics::counter!("frames_enqueued_total").increment(1);
+        // Frames are queued in timestamp order, so the slot holds the one before this
+        let image = Arc::new(image);
+        let previous = self
+            .previous_frame
+            .as_ref()
+            .and_then(|slot| slot.lock().unwrap().replace(image.clone()));
+        let ctx = self.clone();
+        tasks.spawn(async move {
+            // Use video_id to create unique frame paths per video
+            let path = format!("data/{}_frame_{:03}.jpg", ctx.video_id, frame_id);
+            let error_record = |error: String, jpeg_bytes: Option<Vec<u8>>| FrameRecord {
+                frame_id,
+                timestamp,
+                description: String::new(),
+                path: path.clone(),
+                alt_text: None,
+                labels: Vec::new(),
+                model: ctx.describer.model_name(),
+                error: Some(error),
+                similarity_to_prev: None,
+                selection_score: None,
+                pts_ms: to_millis(timestamp),
+                jpeg_bytes,
+                embedding: None,
+                cache_hit: false,
+                confidence: None,
+            };
+            // A failed frame becomes an error record so the rest of the video still completes
+            let failed = |e: anyhow::Error, jpeg_bytes: Option<Vec<u8>>| {
+                warn!("Frame {} at {:.3}s failed: {:#}", frame_id, timestamp, e);
+                error_record(format!("{:#}", e), jpeg_bytes)
+            };
+
+            if let Some(job) = &ctx.progress {
+                job.wait_while_paused().await;
+            }
+            let _permit = match ctx.semaphore.clone().acquire_owned().await {
+                Ok(permit) => permit,
+                Err(e) => return failed(anyhow::Error::new(e).context("failed to acquire concurrency permit"), None),
+            };
+            // `process_video` leaves these out of the records
+            if ctx.progress.as_ref().is_some_and(|job| job.is_stopping()) {
+                return error_record(RUN_STOPPED.to_string(), None);
+            }
+
+            // Before anything is encoded, so neither `data/` nor the model sees a face
+            let (image, previous) = match &ctx.face_detector {
+                Some(detector) => {
+                    let image = match redacted(detector.as_ref(), image).await {
+                        Ok(image) => image,
+                        Err(e) => return failed(e, None),
+                    };
+                    // The slot holds the previous frame as decoded, so it is redacted again
+                    let previous = match previous.filter(|_| !ctx.dry_run) {
+                        Some(previous) => match redacted(detector.as_ref(), previous).await {
+                            Ok(previous) => Some(previous),
+                            Err(e) => return failed(e.context("in the context frame"), None),
+                        },
+                        None => None,
+                    };
+                    (image, previous)
+                }
+                None => (image, previous),
+            };
+
+            // Stored and LLM-bound JPEGs are only encoded separately when their sizes differ
+            let (llm_max_dim, store_max_dim) = (ctx.llm_max_dim, ctx.store_max_dim);
+            let max_width = ctx.max_frame_width;
+            let quality = ctx.jpeg_quality;
+            let dry_run = ctx.dry_run;
+            let encoded = tokio::task::spawn_blocking(move || {
+                let image = fit_width(&image, max_width);
+                let stored = encode_jpeg(&fit_within(&image, store_max_dim), quality)?;
+                let llm = match llm_max_dim {
+                    Some(dim)
+                        if Some(dim) != store_max_dim
+                            && image.width().max(image.height()) > dim =>
+                    {
+                        Some(encode_jpeg(&fit_within(&image, Some(dim)), quality)?)
+                    }
+                    _ => None,
+                };
+                // Encoded again here rather than shared, so no task waits on another's JPEG
+                let context = match previous.filter(|_| !dry_run) {
+                    Some(previous) => {
+                        let previous = fit_width(&previous, max_width);
+                        let fitted = fit_within(&previous, llm_max_dim.or(store_max_dim));
+                        Some(encode_jpeg(&fitted, quality)?)
+                    }
+                    None => None,
+                };
+                Ok::<_, anyhow::Error>((stored, llm, context))
+            })
+            .await
+            .context("JPEG encode task panicked");
+            let (jpeg_bytes, llm_jpeg, context_jpeg) = match encoded {
+                Ok(Ok(encoded)) => encoded,
+                Ok(Err(e)) | Err(e) => return failed(e, None),
+            };
+
+            // Written right away so frames don't pile up in memory over a long video
+            if let Err(e) = storage::storage().put(&path, &jpeg_bytes, "image/jpeg").await {
+                return failed(e.context(format!("failed to write frame to {}", path)), None);
+            }
+            let kept = ctx.keep_images.then(|| jpeg_bytes.clone());
+            if ctx.dry_run {
+                return FrameRecord {
+                    frame_id,
+                    timestamp,
+                    description: String::new(),
+                    path,
+                    alt_text: None,
+                    labels: Vec::new(),
+                    model: String::new(),
+                    error: None,
+                    similarity_to_prev: None,
+                    selection_score: None,
+                    pts_ms: to_millis(timestamp),
+                    jpeg_bytes: kept,
+                    embedding: None,
+                    cache_hit: false,
+                    confidence: None,
+                };
+            }
+            let llm_jpeg = llm_jpeg.unwrap_or(jpeg_bytes);
+            let cache_entry = ctx.description_key.clone().zip(hash);
+            let cached = cache_entry
+                .as_ref()
+                .and_then(|(key, hash)| cached_description(key, *hash));
+            if let Some(cached) = cached {
+                info!(
+                    "Frame {} at {:.3}s matches a described frame; reusing its description",
+                    frame_id, timestamp
+                );
+                return FrameRecord {
+                    frame_id,
+                    timestamp,
+                    description: cached.description,
+                    path,
+                    alt_text: cached.alt_text,
+                    labels: cached.labels,
+                    model: cached.model,
+                    error: None,
+                    similarity_to_prev: None,
+                    selection_score: None,
+                    pts_ms: to_millis(timestamp),
+                    jpeg_bytes: kept,
+                    embedding: None,
+                    cache_hit: true,
+                    confidence: cached.confidence,
+                };
+            }
+
+            let described = match ctx.describe_frame(llm_jpeg, context_jpeg).await {
+                Ok(described) => described,
+                Err(e) => {
+                    metrics::counter!("llm_errors_total").increment(1);
+                    return failed(e, kept);
+                }
+            };
+            metrics::counter!("frames_described_total").increment(1);
+            if let Some((key, hash)) = cache_entry {
+                remember_description(key, hash, &described);
+            }
+            FrameRecord {
+                frame_id,
+                timestamp,
+                description: described.description,
+                path,
+                alt_text: described.alt_text,
+                labels: described.labels,
+                model: described.model,
+                error: None,
+                similarity_to_prev: None,
+                selection_score: None,
+                pts_ms: to_millis(timestamp),
+                jpeg_bytes: kept,
+                embedding: None,
+                cache_hit: false,
+                confidence: described.confidence,
+            }
+        }
+        .in_current_span());
+    }
+}
+
+/// A described image, as `FrameRecord` carries it for video frames.
+#[derive(Serialize, Debug, Clone)]
+pub struct FrameDescription {
+    pub description: String,
+    /// Matched labels when classifying with `ProcessOptions::labels`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub labels: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alt_text: Option<String>,
+    pub model: String,
+    /// See `FrameRecord::confidence`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub confidence: Option<f32>,
+}
+
+/// Describe one still image (PNG, JPEG or anything else the `image` crate decodes) the
+/// way `process_video` describes a frame, honoring the prompt, label, rewrite, size and
+/// quality options. Nothing is written to disk.
+pub async fn describe_image(bytes: Vec<u8>, options: &ProcessOptions) -> Result<FrameDescription> {
+    let (max_dim, quality) = (options.llm_max_dim, options.jpeg_quality.clamp(1, 100));
+    let max_width = options.max_frame_width;
+    let jpeg = tokio::task::spawn_blocking(move || {
+        let image = image::load_from_memory(&bytes)
+            .context("failed to decode image")?
+            .to_rgb8();
+        encode_jpeg(&fit_within(&fit_width(&image, max_width), max_dim), quality)
+    })
+    .await
+    .context("image encode task panicked")??;
+    let ctx = FrameJobContext::from_env("image".to_string(), options)?;
+    ctx.describe_frame(jpeg, None).await
+}
+
+// (removed) FrameSelection; streaming selection uses direct enqueuing.
+
+/// Deadline of a single Gemini call unless a request sets its own (`frame_timeout_secs`).
+pub const DEFAULT_GEMINI_CALL_TIMEOUT_SECS: f64 = 60.0;
+
+// `GEMINI_CALL_TIMEOUT_SECS`, so a hung call becomes an error instead of stalling the run
+fn gemini_call_timeout() -> std::time::Duration {
+    let secs = match env::var("GEMINI_CALL_TIMEOUT_SECS") {
+        Ok(raw) => match raw.parse::<f64>() {
+            Ok(value) if value.is_finite() && value > 0.0 => value,
+            _ => {
+                warn!(
+                    "Invalid GEMINI_CALL_TIMEOUT_SECS value '{}'; using {}",
+                    raw, DEFAULT_GEMINI_CALL_TIMEOUT_SECS
+                );
+                DEFAULT_GEMINI_CALL_TIMEOUT_SECS
+            }
+        },
+        Err(_) => DEFAULT_GEMINI_CALL_TIMEOUT_SECS,
+    };
+    std::time::Duration::from_secs_f64(secs)
+}
+
+/// How long `check_gemini` waits for its ping.
+const GEMINI_PING_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Check that the Gemini configuration resolves (e.g. `GOOGLE_API_KEY` is set) and, with
+/// `ping`, that a trivial generation on the cheapest model succeeds.
+pub async fn check_gemini(ping: bool) -> Result<()> {
+    let config = GeminiConfig::from_env()?;
+    if !ping {
+        return Ok(());
+    }
+    let call = async {
+        let client = config.client(Model::Gemini25FlashLite).await?;
+        client
+            .generate_content()
+            .with_user_message("Reply with OK.")
+            .with_max_output_tokens(8)
+            .execute()
+            .await?;
+        Ok(())
+    };
+    with_call_timeout(GEMINI_PING_TIMEOUT, call).await
+}
+
+/// Whether a file can be created and removed in `data/`.
+pub async fn data_dir_writable() -> bool {
+    let probe = format!("data/.health-{}", uuid::Uuid::new_v4());
+    fs::write(&probe, b"").await.is_ok() && fs::remove_file(&probe).await.is_ok()
+}
+
+async fn with_call_timeout<T>(
+    limit: std::time::Duration,
+    call: impl std::future::Future<Output = Result<T>>,
+) -> Result<T> {
+    tokio::time::timeout(limit, call)
+        .await
+        .map_err(|_| anyhow::anyhow!("Gemini call timed out after {:?}", limit))?
+}
+
+fn load_llm_max_concurrency() -> usize {
+    const DEFAULT: usize = 100;
+    match env::var("LLM_MAX_CONCURRENCY") {
+        Ok(raw) => match raw.parse::<usize>() {
+            Ok(value) if value > 0 => value,
+            _ => {
+                warn!(
+                    "Invalid LLM_MAX_CONCURRENCY value '{}'; using {}",
+                    raw, DEFAULT
+                );
+                DEFAULT
+            }
+        },
+        Err(_) => DEFAULT,
+    }
+}
+
+fn load_llm_memory_budget() -> usize {
+    const DEFAULT_MB: usize = 2048;
+    let mb = match env::var("LLM_MEMORY_BUDGET_MB") {
+        Ok(raw) => match raw.parse::<usize>() {
+            Ok(value) if value > 0 => value,
+            _ => {
+                warn!(
+                    "Invalid LLM_MEMORY_BUDGET_MB value '{}'; using {}",
+                    raw, DEFAULT_MB
+                );
+                DEFAULT_MB
+            }
+        },
+        Err(_) => DEFAULT_MB,
+    };
+    mb * 1024 * 1024
+}
+
+// Rough peak memory for one in-flight description: the RGB frame being encoded, plus the JPEG
+// held three times over (record bytes, the copy sent to the model, and its base64 encoding).
+// Quality-85 JPEGs of camera footage land around a tenth of the raw RGB size.
+fn estimate_in_flight_bytes(width: u32, height: u32) -> usize {
+    let rgb = width as usize * height as usize * 3;
+    let jpeg = rgb / 10;
+    rgb + jpeg * 2 + jpeg * 4 / 3
+}
+
+// Cap `configured` concurrency so `per_frame_bytes * concurrency` stays within `budget_bytes`
+// (never below 1)
+fn memory_capped_concurrency(
+    configured: usize,
+    per_frame_bytes: usize,
+    budget_bytes: usize,
+) -> usize {
+    let fits = budget_bytes / per_frame_bytes.max(1);
+    configured.min(fits).max(1)
+}
+
+/// Model for a `GEMINI_MODEL`-style name ("Gemini25Flash", ...), `None` if unknown.
+pub fn parse_model(name: &str) -> Option<Model> {
+    match name {
+        "Gemini25Flash" => Some(Model::Gemini25Flash),
+        "Gemini25Pro" => Some(Model::Gemini25Pro),
+        "TextEmbedding004" => Some(Model::TextEmbedding004),
+        "Gemini25FlashLite" => Some(Model::Gemini25FlashLite),
+        _ => None,
+    }
+}
+
+// Rate limiting surfaces as HTTP 429 from the API
+fn is_rate_limited(error: &anyhow::Error) -> bool {
+    matches!(
+        error.downcast_ref::<gemini_rust::ClientError>(),
+        Some(gemini_rust::ClientError::BadResponse { code: 429, .. })
+    )
+}
+
+// Server-side or quota errors worth retrying; anything else (e.g. 400) fails fast
+fn is_transient(error: &anyhow::Error) -> bool {
+    matches!(
+        error.downcast_ref::<gemini_rust::ClientError>(),
+        Some(gemini_rust::ClientError::BadResponse {
+            code: 429 | 500 | 502 | 503 | 504,
+            ..
+        })
+    )
+}
+
+// `GEMINI_MAX_RETRIES`: retries of a transient frame-call failure (default 3)
+fn gemini_max_retries() -> u32 {
+    const DEFAULT: u32 = 3;
+    static MAX_RETRIES: std::sync::OnceLock<u32> = std::sync::OnceLock::new();
+    *MAX_RETRIES.get_or_init(|| match env::var("GEMINI_MAX_RETRIES") {
+        Ok(raw) => raw.parse().unwrap_or_else(|_| {
+            warn!("Invalid GEMINI_MAX_RETRIES value '{}'; using {}", raw, DEFAULT);
+            DEFAULT
+        }),
+        Err(_) => DEFAULT,
+    })
+}
+
+// Exponential backoff before retry `attempt` (0-based), jittered into [d/2, d] so
+// concurrent frames don't retry in lockstep
+fn retry_backoff(attempt: u32) -> std::time::Duration {
+    use std::hash::{BuildHasher, Hasher};
+    const BASE_MS: u64 = 500;
+    const MAX_MS: u64 = 30_000;
+    let full = BASE_MS.saturating_mul(1 << attempt.min(16)).min(MAX_MS);
+    // A fresh RandomState is randomly keyed, which is all the randomness jitter needs
+    let random = std::collections::hash_map::RandomState::new().build_hasher().finish();
+    std::time::Duration::from_millis(full / 2 + random % (full / 2 + 1))
+}
+
+fn resolve_model(model_name: Option<&str>) -> Model {
+    match model_name.map(|name| (name, parse_model(name))) {
+        None => Model::Gemini25FlashLite,
+        Some((_, Some(model))) => model,
+        Some((other, None)) => {
+            warn!(
+                "Unknown GEMINI_MODEL '{}'; defaulting to Gemini25FlashLite",
+                other
+            );
+            Model::Gemini25FlashLite
+        }
+    }
+}
+
+// Downscale (keeping aspect ratio) so the longest side is at most `max_dim`; borrows when
+// the image already fits
+fn fit_within(
+    image: &ImageBuffer<Rgb<u8>, Vec<u8>>,
+    max_dim: Option<u32>,
+) -> std::borrow::Cow<'_, ImageBuffer<Rgb<u8>, Vec<u8>>> {
+    let (w, h) = image.dimensions();
+    match max_dim {
+        Some(dim) if w.max(h) > dim => {
+            let scale = dim as f64 / w.max(h) as f64;
+            let nw = ((w as f64 * scale).round() as u32).max(1);
+            let nh = ((h as f64 * scale).round() as u32).max(1);
+            std::borrow::Cow::Owned(image::imageops::resize(
+                image,
+                nw,
+                nh,
+                image::imageops::FilterType::Triangle,
+            ))
+        }
+        _ => std::borrow::Cow::Borrowed(image),
+    }
+}
+
+// `image` no wider than `max_width`, keeping its aspect ratio
+fn fit_width(
+    image: &ImageBuffer<Rgb<u8>, Vec<u8>>,
+    max_width: Option<u32>,
+) -> std::borrow::Cow<'_, ImageBuffer<Rgb<u8>, Vec<u8>>> {
+    let (w, h) = image.dimensions();
+    match max_width {
+        Some(max) if w > max => {
+            let nh = ((h as f64 * max as f64 / w as f64).round() as u32).max(1);
+            std::borrow::Cow::Owned(image::imageops::resize(
+                image,
+                max,
+                nh,
+                image::imageops::FilterType::Triangle,
+            ))
+        }
+        _ => std::borrow::Cow::Borrowed(image),
+    }
+}
+
+fn encode_jpeg(image: &ImageBuffer<Rgb<u8>, Vec<u8>>, quality: u8) -> Result<Vec<u8>> {
+    let width = image.width();
+    let height = image.height();
+    let mut buf = Vec::new();
+    let encoder = JpegEncoder::new_with_quality(&mut buf, quality);
+    encoder.write_image(image.as_raw(), width, height, image::ColorType::Rgb8.into())?;
+    Ok(buf)
+}
+
+const DETAILED_FRAME_PROMPT: &str = "Please describe what you see in this video frame with extremely detailed description try to understand the context of the frames. Make speculative guesses about what might be happening based on the frame!";
+
+const ALT_TEXT_FRAME_PROMPT: &str = "Write alt text for this video frame for a screen reader user. Use exactly one concise sentence of at most 125 characters. Name the main subject and what it is doing, and say where things are in the frame (left, right, center, foreground, background). Do not start with \"Image of\" or \"This frame shows\", and do not speculate beyond what is visible.";
+
+/// Longest alt text kept; screen-reader guidance is roughly 125 characters.
+const ALT_TEXT_MAX_CHARS: usize = 150;
+
+// Normalize a model reply into a single screen-reader friendly sentence
+fn to_alt_text(raw: &str) -> String {
+    let text = raw.split_whitespace().collect::<Vec<_>>().join(" ");
+    let text = text.trim_matches(|c: char| c == '"' || c == '*');
+    // Keep the first sentence only
+    let end = text
+        .match_indices(['.', '!', '?'])
+        .map(|(i, _)| i + 1)
+        .find(|&i| i == text.len() || text[i..].starts_with(' '))
+        .unwrap_or(text.len());
+    let sentence = &text[..end];
+    if sentence.chars().count() <= ALT_TEXT_MAX_CHARS {
+        return sentence.to_string();
+    }
+    // Too long: cut at a word boundary
+    let cut: String = sentence.chars().take(ALT_TEXT_MAX_CHARS).collect();
+    let cut = cut.rsplit_once(' ').map_or(cut.as_str(), |(head, _)| head);
+    format!("{}.", cut.trim_end_matches([',', ';', ':', ' ']))
+}
+
+/// Gemini generation parameters; unset fields keep the model's defaults.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct GenerationParams {
+    /// 0..=2; lower is more repeatable
+    pub temperature: Option<f32>,
+    /// Nucleus sampling cutoff, in (0, 1]
+    pub top_p: Option<f32>,
+    /// Cap on the reply's tokens (thinking included on thinking models); a cut-off reply
+    /// loses its JSON and so its confidence
+    pub max_output_tokens: Option<u32>,
+}
+
+/// Highest `GenerationParams::temperature` Gemini accepts.
+pub const MAX_TEMPERATURE: f32 = 2.0;
+
+/// Greedy decoding when `deterministic`: temperature 0 and top-k 1. This client exposes no
+/// seed, and the provider doesn't guarantee identical output even then (batching and model
+/// updates can still change tokens), so repeated runs are usually but not always byte-identical.
+/// `generation` is applied on top.
+fn with_sampling(
+    builder: gemini_rust::ContentBuilder,
+    (deterministic, generation): (bool, GenerationParams),
+) -> gemini_rust::ContentBuilder {
+    let mut builder = if deterministic {
+        builder.with_temperature(0.0).with_top_k(1)
+    } else {
+        builder
+    };
+    if let Some(temperature) = generation.temperature {
+        builder = builder.with_temperature(temperature);
+    }
+    if let Some(top_p) = generation.top_p {
+        builder = builder.with_top_p(top_p);
+    }
+    if let Some(tokens) = generation.max_output_tokens {
+        builder = builder.with_max_output_tokens(tokens.min(i32::MAX as u32) as i32);
+    }
+    builder
+}
+
+/// How long a run's cached frame prompt may outlive the run if it isn't deleted.
+const PROMPT_CACHE_TTL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
+fn label_prompt(labels: &[String]) -> String {
+    format!(
+        "Classify this video frame. Choose the best-matching label or labels from this list: {}. Answer with only the chosen labels, comma-separated and spelled exactly as in the list, and nothing else.",
+        labels.join(", ")
+    )
+}
+
+// Labels named in a comma/line-separated answer (matched case-insensitively, returned as
+// given in `labels`), and whether the answer named at least one and nothing else
+fn match_labels(answer: &str, labels: &[String]) -> (Vec<String>, bool) {
+    let mut matched: Vec<String> = Vec::new();
+    let mut valid = true;
+    for token in answer.split([',', '\n', ';']) {
+        let token = token.trim_matches(|c: char| c.is_whitespace() || "-*\"'`.".contains(c));
+        if token.is_empty() {
+            continue;
+        }
+        match labels.iter().find(|l| l.eq_ignore_ascii_case(token)) {
+            Some(label) if !matched.contains(label) => matched.push(label.clone()),
+            Some(_) => {}
+            None => valid = false,
+        }
+    }
+    let valid = valid && !matched.is_empty();
+    (matched, valid)
+}
+
+/// Whether `tag` has the shape of a BCP-47 language tag: a 2-8 letter language subtag,
+/// then `-`-separated subtags of 1-8 letters or digits (e.g. "en", "zh-Hant", "pt-BR").
+pub fn is_language_tag(tag: &str) -> bool {
+    let mut subtags = tag.split('-');
+    let language = subtags.next().unwrap_or_default();
+    (2..=8).contains(&language.len())
+        && language.chars().all(|c| c.is_ascii_alphabetic())
+        && subtags.all(|s| {
+            (1..=8).contains(&s.len()) && s.chars().all(|c| c.is_ascii_alphanumeric())
+        })
+}
+
+// Appended to prompts for `ProcessOptions::language` and `SummaryOptions::language`
+fn respond_in(language: &str) -> String {
+    format!(
+        "Respond in the language with BCP-47 tag \"{}\", whatever the language of the input.",
+        language
+    )
+}
+
+fn frame_prompt(mode: DescriptionMode) -> &'static str {
+    match mode {
+        DescriptionMode::Detailed => DETAILED_FRAME_PROMPT,
+        DescriptionMode::AltText => ALT_TEXT_FRAME_PROMPT,
+    }
+}
+
+/// A vision model that describes one JPEG frame. `GeminiProvider` is the built-in one;
+/// others plug in through `ProcessOptions::describer` without touching the pipeline.
+#[async_trait::async_trait]
+pub trait VisionDescriber: Send + Sync + std::fmt::Debug {
+    async fn describe(&self, jpeg: &[u8], prompt: &str) -> Result<String>;
+
+    /// Describe `jpeg` with `previous`, the frame described before it, sent first for
+    /// context; `prompt` speaks of both. Describers without multi-image input keep the
+    /// default, which ignores `previous`.
+    async fn describe_with_context(&self, previous: &[u8], jpeg: &[u8], prompt: &str) -> Result<String> {
+        let _ = previous;
+        self.describe(jpeg, prompt).await
+    }
+
+    /// Recorded in `FrameRecord::model`
+    fn model_name(&self) -> String;
+}
+
+/// A text model answering one prompt, used for summaries, scenes and entities. Plugs in
+/// through `SummaryOptions::summarizer`.
+#[async_trait::async_trait]
+pub trait Summarizer: Send + Sync + std::fmt::Debug {
+    async fn summarize(&self, prompt: &str) -> Result<String>;
+}
+
+/// Gemini as a `VisionDescriber` and `Summarizer`, retrying transient errors like every
+/// other Gemini call here.
+#[derive(Debug, Clone)]
+pub struct GeminiProvider {
+    gemini: GeminiConfig,
+    model: Model,
+    deterministic: bool,
+    generation: GenerationParams,
+}
+
+impl GeminiProvider {
+    /// `deterministic` asks for greedy decoding, see `with_sampling`.
+    pub fn new(gemini: GeminiConfig, model: Model, deterministic: bool) -> Self {
+        Self {
+            gemini,
+            model,
+            deterministic,
+            generation: GenerationParams::default(),
+        }
+    }
+
+    /// Send `generation` with every call.
+    pub fn with_generation(self, generation: GenerationParams) -> Self {
+        Self { generation, ..self }
+    }
+
+    fn sampling(&self) -> (bool, GenerationParams) {
+        (self.deterministic, self.generation)
+    }
+}
+
+#[async_trait::async_trait]
+impl VisionDescriber for GeminiProvider {
+    async fn describe(&self, jpeg: &[u8], prompt: &str) -> Result<String> {
+        describe_jpeg_bytes(
+            &self.gemini,
+            self.model.clone(),
+            jpeg.to_vec(),
+            None,
+            FramePrompt::Inline(prompt),
+            self.sampling(),
+        )
+        .await
+    }
+
+    async fn describe_with_context(&self, previous: &[u8], jpeg: &[u8], prompt: &str) -> Result<String> {
+        describe_jpeg_bytes(
+            &self.gemini,
+            self.model.clone(),
+            jpeg.to_vec(),
+            Some(previous.to_vec()),
+            FramePrompt::Inline(prompt),
+            self.sampling(),
+        )
+        .await
+    }
+
+    fn model_name(&self) -> String {
+        self.model.as_str().to_string()
+    }
+}
+
+#[async_trait::async_trait]
+impl Summarizer for GeminiProvider {
+    async fn summarize(&self, prompt: &str) -> Result<String> {
+        let client = self.gemini.client(self.model.clone()).await?;
+        let call = async {
+            let response = with_sampling(client.generate_content(), self.sampling())
+                .with_user_message(prompt)
+                .execute()
+                .await?;
+            Ok(response.text())
+        };
+        with_call_timeout(gemini_call_timeout(), call).await
+    }
+}
+
+/// A face found by a `FaceDetector`, in pixels of the frame it was found in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FaceRegion {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Finds the faces `ProcessOptions::redact_faces` blurs. `HttpFaceDetector` is the built-in
+/// one; others, such as an embedded model, plug in through `ProcessOptions::face_detector`.
+#[async_trait::async_trait]
+pub trait FaceDetector: Send + Sync + std::fmt::Debug {
+    async fn detect(&self, image: &image::RgbImage) -> Result<Vec<FaceRegion>>;
+}
+
+const FACE_DETECTOR_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// A detection service that takes a frame as an `image/jpeg` POST body and answers
+/// `{"faces": [{"x": .., "y": .., "width": .., "height": ..}]}`.
+#[derive(Debug, Clone)]
+pub struct HttpFaceDetector {
+    client: reqwest::Client,
+    url: reqwest::Url,
+}
+
+impl HttpFaceDetector {
+    /// The service at `FACE_DETECTOR_URL`, `None` when it is unset.
+    pub fn from_env() -> Result<Option<Self>> {
+        let Some(raw) = env::var("FACE_DETECTOR_URL").ok().filter(|url| !url.is_empty()) else {
+            return Ok(None);
+        };
+        let url = reqwest::Url::parse(&raw)
+            .with_context(|| format!("Invalid FACE_DETECTOR_URL '{}'", raw))?;
+        if !matches!(url.scheme(), "https" | "http") {
+            anyhow::bail!("FACE_DETECTOR_URL must be http(s): '{}'", raw);
+        }
+        Ok(Some(Self {
+            client: reqwest::Client::new(),
+            url,
+        }))
+    }
+}
+
+#[async_trait::async_trait]
+impl FaceDetector for HttpFaceDetector {
+    async fn detect(&self, image: &image::RgbImage) -> Result<Vec<FaceRegion>> {
+        #[derive(Deserialize)]
+        struct Detection {
+            faces: Vec<FaceRegion>,
+        }
+        let image = image.clone();
+        let jpeg = tokio::task::spawn_blocking(move || encode_jpeg(&image, DEFAULT_JPEG_QUALITY))
+            .await
+            .context("JPEG encode task panicked")??;
+        let detection: Detection = self
+            .client
+            .post(self.url.clone())
+            .header(reqwest::header::CONTENT_TYPE, "image/jpeg")
+            .body(jpeg)
+            .timeout(FACE_DETECTOR_TIMEOUT)
+            .send()
+            .await
+            .context("failed to reach the face detector")?
+            .error_for_status()?
+            .json()
+            .await
+            .context("unexpected face detector response")?;
+        Ok(detection.faces)
+    }
+}
+
+// Share of a face box's width and height added on each side; detectors box faces tightly
+const REDACTION_MARGIN: f64 = 0.15;
+// Blurred regions are scaled down to this many pixels across and back up
+const REDACTION_CELLS: u32 = 6;
+
+/// Blur `faces` in `image` beyond recognition: each box, widened by a margin and clipped to
+/// the frame, is scaled down to a few pixels and back up.
+pub fn redact_regions(image: &mut image::RgbImage, faces: &[FaceRegion]) {
+    use image::imageops::{self, FilterType};
+
+    for face in faces {
+        let margin_x = (face.width as f64 * REDACTION_MARGIN) as u32;
+        let margin_y = (face.height as f64 * REDACTION_MARGIN) as u32;
+        let (x0, y0) = (face.x.saturating_sub(margin_x), face.y.saturating_sub(margin_y));
+        let x1 = face.x.saturating_add(face.width).saturating_add(margin_x).min(image.width());
+        let y1 = face.y.saturating_add(face.height).saturating_add(margin_y).min(image.height());
+        if x1 <= x0 || y1 <= y0 {
+            continue;
+        }
+        let (width, height) = (x1 - x0, y1 - y0);
+        let region = imageops::crop_imm(image, x0, y0, width, height).to_image();
+        let cells_x = REDACTION_CELLS.min(width);
+        let cells_y = (cells_x as f64 * height as f64 / width as f64).round() as u32;
+        let cells_y = cells_y.clamp(1, height);
+        let coarse = imageops::resize(&region, cells_x, cells_y, FilterType::Triangle);
+        let blurred = imageops::resize(&coarse, width, height, FilterType::Triangle);
+        imageops::replace(image, &blurred, x0 as i64, y0 as i64);
+    }
+}
+
+// `image` with the faces `detector` finds blurred
+async fn redacted(detector: &dyn FaceDetector, image: Arc<RgbFrame>) -> Result<Arc<RgbFrame>> {
+    let faces = detector.detect(&image).await.context("face detection failed")?;
+    if faces.is_empty() {
+        return Ok(image);
+    }
+    let mut image = Arc::unwrap_or_clone(image);
+    redact_regions(&mut image, &faces);
+    Ok(Arc::new(image))
+}
+
+// Frame prompt sent with the image, or the name of cached content that holds it and
+// whether that prompt asks for `CONFIDENCE_PROMPT`'s JSON
+#[derive(Clone, Copy)]
+enum FramePrompt<'a> {
+    Inline(&'a str),
+    Cached { name: &'a str, json: bool },
+}
+
+impl FramePrompt<'_> {
+    fn asks_for_json(&self) -> bool {
+        match *self {
+            FramePrompt::Inline(text) => text.contains(CONFIDENCE_PROMPT),
+            FramePrompt::Cached { json, .. } => json,
+        }
+    }
+}
+
+// Ends every built-in description prompt; `parse_confidence` reads the reply
+const CONFIDENCE_PROMPT: &str = "Reply with only a JSON object {\"description\": your description, \"confidence\": how sure you are of it, from 0 to 1}. Give a low confidence when the image is blurry, dark or ambiguous, or when you would otherwise hedge with words like \"might\" or \"possibly\".";
+
+// Description and confidence from a `CONFIDENCE_PROMPT` reply (code fences tolerated).
+// Anything else, including a confidence outside 0..=1, is taken as plain text.
+fn parse_confidence(reply: String) -> (String, Option<f32>) {
+    #[derive(Deserialize)]
+    struct Reply {
+        description: String,
+        confidence: f32,
+    }
+
+    let parsed = match (reply.find('{'), reply.rfind('}')) {
+        (Some(start), Some(end)) if start < end => {
+            serde_json::from_str::<Reply>(&reply[start..=end]).ok()
+        }
+        _ => None,
+    };
+    let usable = |p: &Reply| !p.description.trim().is_empty() && (0.0..=1.0).contains(&p.confidence);
+    match parsed.filter(usable) {
+        Some(parsed) => (parsed.description.trim().to_string(), Some(parsed.confidence)),
+        None => (reply, None),
+    }
+}
+
+// Appended to the frame prompt when the previous frame is sent along
+const CONTEXT_FRAME_PROMPT: &str = "The first image is the previous frame of the same video, for context only. Describe the second image, and say what changed since the previous one: movement and its direction, and what appeared or disappeared.";
+
+// `context` (the previous frame's JPEG) goes before the frame, see `CONTEXT_FRAME_PROMPT`
+async fn describe_jpeg_bytes(
+    gemini: &GeminiConfig,
+    model: Model,
+    jpeg_bytes: Vec<u8>,
+    context: Option<Vec<u8>>,
+    prompt: FramePrompt<'_>,
+    sampling: (bool, GenerationParams),
+) -> Result<String> {
+    let (b64, context_b64) = tokio::task::spawn_blocking(move || {
+        let encode = |bytes: Vec<u8>| general_purpose::STANDARD.encode(bytes);
+        (encode(jpeg_bytes), context.map(encode))
+    })
+    .await
+    .context("base64 encode task panicked")?;
+
+    let client = gemini.client(model).await?;
+
+    let max_retries = gemini_max_retries();
+    let mut attempt = 0;
+    loop {
+        let builder = match prompt {
+            FramePrompt::Inline(text) => client.generate_content().with_user_message(text),
+            FramePrompt::Cached { name, .. } => client
+                .generate_content()
+                .with_cached_content(&client.get_cached_content(name)),
+        };
+        let mut builder = with_sampling(builder, sampling);
+        if prompt.asks_for_json() {
+            builder = builder.with_response_mime_type("application/json");
+        }
+        if let Some(previous) = &context_b64 {
+            builder = builder.with_inline_data(previous.clone(), "image/jpeg");
+        }
+        let result = builder
+            .with_inline_data(b64.clone(), "image/jpeg")
+            .execute()
+            .await;
+        let error = match result {
+            Ok(response) => return Ok(response.text()),
+            Err(e) => anyhow::Error::from(e),
+        };
+        if attempt >= max_retries || !is_transient(&error) {
+            return Err(error);
+        }
+        let delay = retry_backoff(attempt);
+        warn!("Frame call failed ({}); retry {} of {} in {:?}", error, attempt + 1, max_retries, delay);
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+    }
+}
+
+// Follows the frame prompt of a batch call; `{count}` is the number of images
+const BATCH_PROMPT: &str = "Each of the following {count} images is preceded by its number, counting from 1 to {count}. Describe every image on its own. Reply with only a JSON array holding one object per image, each number from 1 to {count} exactly once: {\"index\": the image's number, \"description\": your description, \"confidence\": how sure you are of it, from 0 to 1}. Give a low confidence when the image is blurry, dark or ambiguous, or when you would otherwise hedge with words like \"might\" or \"possibly\".";
+
+// Describe `jpegs` in one call. Returns each image's reply, in order, in the form
+// `parse_confidence` reads; `None` where its description came back empty. A reply that
+// doesn't answer every image exactly once is an error.
+async fn describe_jpeg_batch(
+    gemini: &GeminiConfig,
+    model: Model,
+    jpegs: Vec<Vec<u8>>,
+    prompt: &str,
+    sampling: (bool, GenerationParams),
+) -> Result<Vec<Option<String>>> {
+    let count = jpegs.len();
+    let encoded = tokio::task::spawn_blocking(move || {
+        jpegs
+            .into_iter()
+            .map(|bytes| general_purpose::STANDARD.encode(bytes))
+            .collect::<Vec<_>>()
+    })
+    .await
+    .context("base64 encode task panicked")?;
+    let prompt = format!("{}\n\n{}", prompt, BATCH_PROMPT.replace("{count}", &count.to_string()));
+
+    let client = gemini.client(model).await?;
+
+    let max_retries = gemini_max_retries();
+    let mut attempt = 0;
+    loop {
+        let mut builder = with_sampling(client.generate_content(), sampling)
+            .with_user_message(&prompt)
+            .with_response_mime_type("application/json");
+        for (index, b64) in encoded.iter().enumerate() {
+            builder = builder
+                .with_user_message(format!("Image {}:", index + 1))
+                .with_inline_data(b64.clone(), "image/jpeg");
+        }
+        let error = match builder.execute().await {
+            Ok(response) => return split_batch_reply(&response.text(), count),
+            Err(e) => anyhow::Error::from(e),
+        };
+        if attempt >= max_retries || !is_transient(&error) {
+            return Err(error);
+        }
+        let delay = retry_backoff(attempt);
+        warn!("Batch call failed ({}); retry {} of {} in {:?}", error, attempt + 1, max_retries, delay);
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+    }
+}
+
+// Replies of the `count` images from a `BATCH_PROMPT` answer (code fences or prose around
+// the array tolerated), converted from its 1-based indices. Unless the indices are exactly
+// 1..=count the reply can't be trusted to match descriptions to frames, and it is an error.
+// An empty description leaves its image `None`.
+fn split_batch_reply(reply: &str, count: usize) -> Result<Vec<Option<String>>> {
+    #[derive(Deserialize)]
+    struct Entry {
+        index: usize,
+        description: String,
+        confidence: Option<f32>,
+    }
+
+    let entries: Vec<Entry> = match (reply.find('['), reply.rfind(']')) {
+        (Some(start), Some(end)) if start < end => serde_json::from_str(&reply[start..=end])
+            .context("batch reply is not an array of indexed descriptions")?,
+        _ => anyhow::bail!("batch reply holds no JSON array"),
+    };
+    if entries.len() != count {
+        anyhow::bail!("batch reply has {} entries for {} images", entries.len(), count);
+    }
+    let mut replies = vec![None; count];
+    let mut seen = vec![false; count];
+    for entry in entries {
+        let slot = entry.index.checked_sub(1).filter(|&slot| slot < count);
+        let Some(slot) = slot.filter(|&slot| !seen[slot]) else {
+            anyhow::bail!("batch reply indices aren't 1 to {}", count);
+        };
+        seen[slot] = true;
+        if entry.description.trim().is_empty() {
+            continue;
+        }
+        // Shaped like a single-image reply, so `describe_frame` handles both alike
+        replies[slot] = Some(match entry.confidence {
+            Some(confidence) => serde_json::json!({
+                "description": entry.description,
+                "confidence": confidence,
+            })
+            .to_string(),
+            None => entry.description,
+        });
+    }
+    Ok(replies)
+}
+
+/// Default transcript size (in characters) a single summary call may carry. Roughly 50k
+/// tokens, far below Flash Lite's context window so typical videos stay single-pass.
+pub const DEFAULT_SUMMARY_CHUNK_CHARS: usize = 200_000;
+
+/// Shape of the text `summarize_records` asks for.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SummaryStyle {
+    /// A few sentences telling the video's story (the original prompt)
+    #[default]
+    Narrative,
+    /// A bullet-point log of the actions, in order
+    Bullets,
+    /// One line per notable moment, each starting with its timestamp
+    Timeline,
+}
+
+/// Knobs for `summarize_records`. `Default` reproduces the original single-pass summary.
+#[derive(Debug, Clone, Default)]
+pub struct SummaryOptions {
+    /// Human-readable title (see `title_from_video_id`) passed along as context
+    pub title: Option<String>,
+    /// Max transcript characters per summary call; longer transcripts are map-reduced.
+    /// Falls back to `DEFAULT_SUMMARY_CHUNK_CHARS`.
+    pub chunk_chars: Option<usize>,
+    /// Max frames per summary call (speech lines don't count); videos with more are
+    /// map-reduced as for `chunk_chars`. `None` only limits characters.
+    pub chunk_frames: Option<usize>,
+    /// Endpoint for summary calls; `None` uses `GeminiConfig::from_env`
+    pub gemini: Option<GeminiConfig>,
+    /// Greedy decoding for summary calls, as `ProcessOptions::deterministic`
+    pub deterministic: bool,
+    /// Temperature, top-p and output limit of summary calls
+    pub generation: GenerationParams,
+    /// Speech interleaved with the frame descriptions by timestamp
+    pub transcript: Vec<TranscriptSegment>,
+    pub style: SummaryStyle,
+    /// Length limit: sentences for `Narrative` (default 3-5), bullets or lines otherwise
+    pub max_sentences: Option<u32>,
+    /// Text model for summary calls instead of Gemini; `gemini`, `deterministic` and
+    /// `generation` then don't apply
+    pub summarizer: Option<Arc<dyn Summarizer>>,
+    /// BCP-47 tag of the language summaries are written in, as `ProcessOptions::language`;
+    /// `None` keeps English
+    pub language: Option<String>,
+    /// Leave out frames whose `FrameRecord::confidence` is below this; frames without a
+    /// confidence are kept
+    pub min_confidence: Option<f32>,
+    /// Tenant whose share of the global LLM concurrency summary calls use, as
+    /// `ProcessOptions::tenant`
+    pub tenant: Option<String>,
+}
+
+impl SummaryOptions {
+    // Every call waits for a `FairScheduler` permit, like frame calls
+    fn summarizer(&self) -> Result<Arc<dyn Summarizer>> {
+        let inner: Arc<dyn Summarizer> = match &self.summarizer {
+            Some(summarizer) => summarizer.clone(),
+            None => {
+                let gemini = match &self.gemini {
+                    Some(gemini) => gemini.clone(),
+                    None => GeminiConfig::from_env()?,
+                };
+                let provider =
+                    GeminiProvider::new(gemini, Model::Gemini25FlashLite, self.deterministic);
+                Arc::new(provider.with_generation(self.generation))
+            }
+        };
+        Ok(Arc::new(FairSummarizer {
+            inner,
+            scheduler: FairScheduler::global(),
+            tenant: self.tenant.clone().unwrap_or_else(|| DEFAULT_TENANT.to_string()),
+        }))
+    }
+
+    // Opening of the summary prompt; the records follow its "Frames:" line
+    fn instructions(&self) -> String {
+        match (self.style, self.max_sentences) {
+            (SummaryStyle::Narrative, None) => "Summarize the video in detail description, should be 3-5 sentences.\n\nFrames:\n. Based on all the frmaes, try to keep a story line and explain what happened in the video. Describe the story not the specific details.".to_string(),
+            (SummaryStyle::Narrative, Some(n)) => format!(
+                "Summarize the video in detail description, in at most {} sentences.\n\nFrames:\n. Based on all the frames, try to keep a story line and explain what happened in the video. Describe the story not the specific details.",
+                n
+            ),
+            (SummaryStyle::Bullets, limit) => format!(
+                "Summarize the video as a bullet-point log of the actions that happen, in order, one \"- \" bullet per action{}. Reply with the bullets only.\n\nFrames:\n",
+                limit.map(|n| format!(", at most {} bullets", n)).unwrap_or_default()
+            ),
+            (SummaryStyle::Timeline, limit) => format!(
+                "Summarize the video as a timeline: one line per notable moment, starting with its timestamp as \"[12.3s]\"{}. Reply with the timeline only.\n\nFrames:\n",
+                limit.map(|n| format!(", at most {} lines", n)).unwrap_or_default()
+            ),
+        }
+    }
+}
+
+/// Default `similarity_to_prev` below which consecutive described frames count as a hard cut.
+/// Features are raw 64x64 luma, so even unrelated shots often stay above 0.8.
+pub const DEFAULT_SCENE_CUT_SIMILARITY: f32 = 0.9;
+
+/// One chapter of an edited video: consecutive described frames between two hard cuts.
+#[derive(Serialize, Debug, Clone)]
+pub struct Scene {
+    pub start: f64,
+    /// Start of the next scene, or the last frame's timestamp for the final scene
+    pub end: f64,
+    pub summary: String,
+    pub frame_ids: Vec<u64>,
+}
+
+/// Split timestamp-sorted records into scenes at hard cuts (similarity to the previous
+/// described frame below `cut_similarity`) and summarize each scene concurrently.
+pub async fn summarize_scenes(
+    records: &[FrameRecord],
+    cut_similarity: f32,
+    options: &SummaryOptions,
+) -> Result<Vec<Scene>> {
+    let mut groups: Vec<&[FrameRecord]> = Vec::new();
+    let mut start = 0;
+    for (i, record) in records.iter().enumerate().skip(1) {
+        if record.similarity_to_prev.is_some_and(|s| s < cut_similarity) {
+            groups.push(&records[start..i]);
+            start = i;
+        }
+    }
+    if start < records.len() {
+        groups.push(&records[start..]);
+    }
+    info!("Detected {} scenes across {} frames", groups.len(), records.len());
+
+    let summarizer = options.summarizer()?;
+    let transcripts = groups
+        .iter()
+        .map(|group| {
+            group
+                .iter()
+                .filter(|r| r.error.is_none())
+                .map(|r| format!("- [{:.1}s] {}\n", r.timestamp, r.description))
+                .collect::<String>()
+        })
+        .collect();
+    let language = options.language.as_deref();
+    let summaries =
+        summarize_chunks(&summarizer, transcripts, "one scene of a video", language).await?;
+
+    let mut scenes: Vec<Scene> = groups
+        .iter()
+        .zip(summaries)
+        .map(|(group, summary)| Scene {
+            start: group[0].timestamp,
+            end: group[group.len() - 1].timestamp,
+            summary,
+            frame_ids: group.iter().map(|r| r.frame_id).collect(),
+        })
+        .collect();
+    for i in 1..scenes.len() {
+        scenes[i - 1].end = scenes[i].start;
+    }
+    Ok(scenes)
+}
+
+/// A distinct object or person seen in the video, aggregated over the frames mentioning it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EntityMention {
+    pub entity: String,
+    pub first_seen_ts: f64,
+    pub last_seen_ts: f64,
+    /// Ascending
+    pub frame_ids: Vec<u64>,
+}
+
+/// Default cosine similarity of consecutive frames' description embeddings below which
+/// `detect_chapters` starts a new chapter.
+pub const DEFAULT_CHAPTER_SIMILARITY: f32 = 0.75;
+
+/// A stretch of the video about one topic, found by `detect_chapters`.
+#[derive(Serialize, Debug, Clone)]
+pub struct Chapter {
+    pub start_ts: f64,
+    /// Start of the next chapter, or the last frame's timestamp for the final chapter
+    pub end_ts: f64,
+    pub title: String,
+}
+
+const CHAPTER_TITLE_PROMPT: &str = "Below are descriptions of consecutive frames from one section of a video. \
+Give the section a short title of at most 8 words. Reply with the title only.";
+
+/// Split timestamp-sorted records into chapters where the description embeddings
+/// (`TextEmbedding004`) of consecutive described frames are less similar than
+/// `min_similarity`, and have Gemini title each chapter. Embeddings already on the records
+/// (`ProcessOptions::embed_descriptions`) are reused.
+pub async fn detect_chapters(
+    records: &[FrameRecord],
+    min_similarity: f32,
+    options: &SummaryOptions,
+) -> Result<Vec<Chapter>> {
+    let records: Vec<&FrameRecord> = records
+        .iter()
+        .filter(|r| r.error.is_none() && !r.description.is_empty())
+        .collect();
+    if records.is_empty() {
+        return Ok(Vec::new());
+    }
+    let embeddings = if records.iter().all(|r| r.embedding.is_some()) {
+        records.iter().filter_map(|r| r.embedding.clone()).collect()
+    } else {
+        let config = match &options.gemini {
+            Some(gemini) => gemini.clone(),
+            None => GeminiConfig::from_env()?,
+        };
+        let texts: Vec<String> = records.iter().map(|r| r.description.clone()).collect();
+        embed_texts(&config, &texts, TaskType::RetrievalDocument).await?
+    };
+    let norm = |v: &[f32]| dot_f32(v, v).sqrt();
+
+    let mut groups: Vec<&[&FrameRecord]> = Vec::new();
+    let mut start = 0;
+    for i in 1..records.len() {
+        let (prev, cur) = (&embeddings[i - 1], &embeddings[i]);
+        if cosine_similarity_feats(prev, norm(prev), cur, norm(cur)) < min_similarity {
+            groups.push(&records[start..i]);
+            start = i;
+        }
+    }
+    groups.push(&records[start..]);
+    info!("Detected {} chapters across {} frames", groups.len(), records.len());
+
+    let respond = options
+        .language
+        .as_deref()
+        .map(|l| format!(" {}", respond_in(l)))
+        .unwrap_or_default();
+    let prompts = groups
+        .iter()
+        .map(|group| {
+            let lines: String = group
+                .iter()
+                .map(|r| format!("- [{:.1}s] {}\n", r.timestamp, r.description))
+                .collect();
+            format!("{}{}\n\n{}", CHAPTER_TITLE_PROMPT, respond, lines)
+        })
+        .collect();
+    let titles = ask_each(&options.summarizer()?, prompts).await?;
+
+    let mut chapters: Vec<Chapter> = groups
+        .iter()
+        .zip(titles)
+        .map(|(group, title)| Chapter {
+            start_ts: group[0].timestamp,
+            end_ts: group[group.len() - 1].timestamp,
+            title: title
+                .lines()
+                .find(|line| !line.trim().is_empty())
+                .unwrap_or_default()
+                .trim()
+                .trim_matches(|c: char| c == '"' || c == '*' || c == '#')
+                .trim()
+                .to_string(),
+        })
+        .collect();
+    for i in 1..chapters.len() {
+        chapters[i - 1].end_ts = chapters[i].start_ts;
+    }
+    Ok(chapters)
+}
+
+const ENTITY_PROMPT: &str = "List the distinct objects and people that appear in this video, based on the frame descriptions below. \
+Reply with a JSON array of objects {\"entity\": short name, \"frame_ids\": ids of the frames mentioning it, \"first_seen_ts\": seconds, \"last_seen_ts\": seconds}. \
+Use one entry per real-world entity, even if frames name it differently.\n\nFrames:\n";
+
+const ENTITY_RETRY_PROMPT: &str = "List the distinct objects and people that appear in this video, based on the frame descriptions below. \
+Your reply must be ONLY a valid JSON array, with no prose and no code fences, exactly like \
+[{\"entity\": \"red car\", \"frame_ids\": [1, 4], \"first_seen_ts\": 0.5, \"last_seen_ts\": 3.0}].\n\nFrames:\n";
+
+/// Ask Gemini for the entities mentioned across the frame descriptions, merging entries
+/// with the same name. A reply that isn't valid JSON is retried once with a stricter prompt.
+pub async fn extract_entities(
+    records: &[FrameRecord],
+    options: &SummaryOptions,
+) -> Result<Vec<EntityMention>> {
+    let records: Vec<&FrameRecord> = records.iter().filter(|r| r.error.is_none()).collect();
+    if records.is_empty() {
+        return Ok(Vec::new());
+    }
+    let lines: String = records
+        .iter()
+        .map(|r| format!("- frame {} [{:.1}s] {}\n", r.frame_id, r.timestamp, r.description))
+        .collect();
+    let timestamps: HashMap<u64, f64> = records.iter().map(|r| (r.frame_id, r.timestamp)).collect();
+
+    let summarizer = options.summarizer()?;
+    let mut last_error = None;
+    for prompt in [ENTITY_PROMPT, ENTITY_RETRY_PROMPT] {
+        let reply = summarizer.summarize(&format!("{}{}", prompt, lines)).await?;
+        match parse_entities(&reply, &timestamps) {
+            Ok(entities) => return Ok(entities),
+            Err(e) => {
+                warn!("Malformed entity reply: {:#}", e);
+                last_error = Some(e);
+            }
+        }
+    }
+    Err(last_error
+        .expect("at least one attempt")
+        .context("Gemini returned malformed entity JSON twice"))
+}
+
+// Parse the model's JSON array (tolerating code fences around it) and merge duplicates.
+// Frame ids are checked against `timestamps`, which also gives the seen range when known.
+fn parse_entities(reply: &str, timestamps: &HashMap<u64, f64>) -> Result<Vec<EntityMention>> {
+    #[derive(Deserialize)]
+    struct RawEntity {
+        entity: String,
+        #[serde(default)]
+        frame_ids: Vec<u64>,
+        first_seen_ts: Option<f64>,
+        last_seen_ts: Option<f64>,
+    }
+
+    let start = reply.find('[').context("no JSON array in reply")?;
+    let end = reply.rfind(']').filter(|&end| end > start).context("no JSON array in reply")?;
+    let raw: Vec<RawEntity> = serde_json::from_str(&reply[start..=end])?;
+
+    // Keyed by lowercased name, in first-mention order
+    let mut merged: Vec<(String, EntityMention)> = Vec::new();
+    for entity in raw {
+        let name = entity.entity.trim();
+        if name.is_empty() {
+            continue;
+        }
+        let frame_ids: Vec<u64> = entity
+            .frame_ids
+            .into_iter()
+            .filter(|id| timestamps.contains_key(id))
+            .collect();
+        let seen = frame_ids.iter().map(|id| timestamps[id]);
+        let Some(first) = seen.clone().reduce(f64::min).or(entity.first_seen_ts) else {
+            continue;
+        };
+        let last = seen
+            .reduce(f64::max)
+            .or(entity.last_seen_ts)
+            .unwrap_or(first)
+            .max(first);
+
+        let key = name.to_lowercase();
+        match merged.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, mention)) => {
+                mention.first_seen_ts = mention.first_seen_ts.min(first);
+                mention.last_seen_ts = mention.last_seen_ts.max(last);
+                mention.frame_ids.extend(frame_ids);
+            }
+            None => merged.push((
+                key,
+                EntityMention {
+                    entity: name.to_string(),
+                    first_seen_ts: first,
+                    last_seen_ts: last,
+                    frame_ids,
+                },
+            )),
+        }
+    }
+    let mut entities: Vec<EntityMention> = merged
+        .into_iter()
+        .map(|(_, mut mention)| {
+            mention.frame_ids.sort_unstable();
+            mention.frame_ids.dedup();
+            mention
+        })
+        .collect();
+    entities.sort_by(|a, b| {
+        a.first_seen_ts
+            .partial_cmp(&b.first_seen_ts)
+            .unwrap_or(Ordering::Equal)
+    });
+    Ok(entities)
+}
+
+/// Summary of one window of consecutive frames, from the first map-reduce round.
+#[derive(Serialize, Debug, Clone)]
+pub struct ChunkSummary {
+    /// Timestamps of the first and last line (frame or speech) in the window
+    pub start_ts: f64,
+    pub end_ts: f64,
+    pub frame_count: usize,
+    pub summary: String,
+}
+
+/// A summary with the chunk summaries it was reduced from.
+#[derive(Serialize, Debug, Clone)]
+pub struct ChunkedSummary {
+    pub summary: String,
+    /// In timestamp order; empty when the transcript fit into a single call
+    pub chunks: Vec<ChunkSummary>,
+}
+
+/// Summarize what happens in the video based on the per-frame descriptions.
+/// Keeps it simple: sends a compact text transcript to Gemini and asks for
+/// a short summary. No images are attached here to keep calls light.
+///
+/// Transcripts larger than `chunk_chars` (or with more than `chunk_frames` frames) are
+/// summarized map-reduce style: each chunk is summarized concurrently (bounded by
+/// `LLM_MAX_CONCURRENCY`), then the chunk summaries are summarized into the final result.
+pub async fn summarize_records(records: &[FrameRecord], options: &SummaryOptions) -> Result<String> {
+    Ok(summarize_records_chunked(records, options).await?.summary)
+}
+
+/// `summarize_records`, also returning the first round's chunk summaries, e.g. to build a
+/// chapter list of a long recording.
+pub async fn summarize_records_chunked(
+    records: &[FrameRecord],
+    options: &SummaryOptions,
+) -> Result<ChunkedSummary> {
+    let single = |summary: String| ChunkedSummary {
+        summary,
+        chunks: Vec::new(),
+    };
+    // Frames that failed have nothing to tell the summary
+    let confident = |r: &&FrameRecord| {
+        options.min_confidence.is_none_or(|min| r.confidence.is_none_or(|c| c >= min))
+    };
+    let records: Vec<&FrameRecord> = records
+        .iter()
+        .filter(|r| r.error.is_none())
+        .filter(confident)
+        .collect();
+    if records.is_empty() {
+        return Ok(single("No frames processed; nothing to summarize.".to_string()));
+    }
+
+    // Build a compact transcript
+    let mut header = options.instructions();
+    if let Some(title) = &options.title {
+        use std::fmt::Write as _;
+        let _ = write!(
+            header,
+            "\nThe video's title is \"{}\"; use it as a hint about the subject, but trust the frames over the title.",
+            title
+        );
+    }
+    if !options.transcript.is_empty() {
+        header.push_str("\nLines marked \"Said:\" are speech from the audio track.");
+    }
+    if let Some(language) = &options.language {
+        header.push('\n');
+        header.push_str(&respond_in(language));
+    }
+    header.push('\n');
+    // Keep to one line per frame or speech segment, merged in timestamp order
+    let mut lines: Vec<TimedLine> = records
+        .iter()
+        .map(|r| TimedLine {
+            timestamp: r.timestamp,
+            frame: true,
+            text: format!("- [{:.1}s] {}\n", r.timestamp, r.description),
+        })
+        .chain(options.transcript.iter().map(|s| TimedLine {
+            timestamp: s.timestamp,
+            frame: false,
+            text: format!("- [{:.1}s] Said: \"{}\"\n", s.timestamp, s.text),
+        }))
+        .collect();
+    lines.sort_by(|a, b| a.timestamp.partial_cmp(&b.timestamp).unwrap_or(Ordering::Equal));
+
+    let summarizer = options.summarizer()?;
+    let chunk_chars = options.chunk_chars.unwrap_or(DEFAULT_SUMMARY_CHUNK_CHARS).max(1);
+    let chunk_frames = options.chunk_frames.map(|n| n.max(1));
+    let total: usize = lines.iter().map(|line| line.text.len()).sum();
+    if header.len() + total <= chunk_chars && chunk_frames.is_none_or(|max| records.len() <= max) {
+        let text: String = lines.iter().map(|line| line.text.as_str()).collect();
+        return Ok(single(summarizer.summarize(&(header + &text)).await?));
+    }
+
+    // Map: summarize consecutive chunks concurrently
+    let windows = chunk_timed_lines(&lines, chunk_chars, chunk_frames);
+    info!(
+        "Transcript is {} chars, {} frames; summarizing {} chunks of <= {} chars, <= {:?} frames",
+        total,
+        records.len(),
+        windows.len(),
+        chunk_chars,
+        chunk_frames
+    );
+    let language = options.language.as_deref();
+    let texts = windows.iter().map(|w| w.text.clone()).collect();
+    let mut partials =
+        summarize_chunks(&summarizer, texts, "a portion of a video", language).await?;
+    let chunk_summaries: Vec<ChunkSummary> = windows
+        .into_iter()
+        .zip(&partials)
+        .map(|(window, summary)| ChunkSummary {
+            start_ts: window.start_ts,
+            end_ts: window.end_ts,
+            frame_count: window.frames,
+            summary: summary.clone(),
+        })
+        .collect();
+
+    // Reduce: keep folding until the chunk summaries fit into one call
+    loop {
+        let partial_lines: Vec<String> = partials
+            .iter()
+            .enumerate()
+            .map(|(i, p)| format!("- Part {}: {}\n", i + 1, p.replace('\n', " ")))
+            .collect();
+        let partial_total: usize = partial_lines.iter().map(String::len).sum();
+        let chunks = chunk_lines(&partial_lines, chunk_chars);
+        // Also stop when another round wouldn't shrink anything (tiny chunk sizes)
+        if header.len() + partial_total <= chunk_chars || chunks.len() >= partials.len() {
+            let mut prompt = header.replace(
+                "Frames:\n",
+                "The video was summarized in consecutive parts; the part summaries are listed in order below.\n",
+            );
+            prompt.push_str(&partial_lines.concat());
+            let summary = summarizer.summarize(&prompt).await?;
+            return Ok(ChunkedSummary {
+                summary,
+                chunks: chunk_summaries,
+            });
+        }
+        partials = summarize_chunks(
+            &summarizer,
+            chunks,
+            "consecutive part summaries of a video",
+            language,
+        )
+        .await?;
+    }
+}
+
+// One frame or speech line of a summary transcript
+struct TimedLine {
+    timestamp: f64,
+    frame: bool,
+    text: String,
+}
+
+// Consecutive transcript lines summarized in one map call
+struct Window {
+    start_ts: f64,
+    end_ts: f64,
+    frames: usize,
+    text: String,
+}
+
+// `chunk_lines` for the transcript, also closing a window once it holds `max_frames` frames
+fn chunk_timed_lines(lines: &[TimedLine], max_chars: usize, max_frames: Option<usize>) -> Vec<Window> {
+    let mut windows: Vec<Window> = Vec::new();
+    let mut current: Option<Window> = None;
+    for line in lines {
+        if let Some(window) = current.take_if(|w| {
+            w.text.len() + line.text.len() > max_chars
+                || (line.frame && max_frames.is_some_and(|max| w.frames >= max))
+        }) {
+            windows.push(window);
+        }
+        let window = current.get_or_insert_with(|| Window {
+            start_ts: line.timestamp,
+            end_ts: line.timestamp,
+            frames: 0,
+            text: String::new(),
+        });
+        window.end_ts = line.timestamp;
+        window.frames += usize::from(line.frame);
+        window.text.push_str(&line.text);
+    }
+    windows.extend(current);
+    windows
+}
+
+// Greedily pack lines into chunks of at most `max_chars` (a single oversized line gets its own chunk)
+fn chunk_lines(lines: &[String], max_chars: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    for line in lines {
+        if !current.is_empty() && current.len() + line.len() > max_chars {
+            chunks.push(std::mem::take(&mut current));
+        }
+        current.push_str(line);
+    }
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+    chunks
+}
+
+// Summarize each chunk concurrently, returning the summaries in chunk order
+async fn summarize_chunks(
+    summarizer: &Arc<dyn Summarizer>,
+    chunks: Vec<String>,
+    what: &str,
+    language: Option<&str>,
+) -> Result<Vec<String>> {
+    let respond = language.map(|l| format!(" {}", respond_in(l))).unwrap_or_default();
+    let prompts = chunks
+        .into_iter()
+        .map(|chunk| format!(
+            "Below is {}. Summarize what happens in it in 3-5 sentences, keeping events in order and noting timestamps where useful.{}\n\n{}",
+            what, respond, chunk
+        ))
+        .collect();
+    ask_each(summarizer, prompts).await
+}
+
+// A `Summarizer` whose calls take `tenant`'s turn in `scheduler`
+struct FairSummarizer {
+    inner: Arc<dyn Summarizer>,
+    scheduler: Arc<FairScheduler>,
+    tenant: String,
+}
+
+impl std::fmt::Debug for FairSummarizer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FairSummarizer")
+            .field("inner", &self.inner)
+            .field("tenant", &self.tenant)
+            .finish()
+    }
+}
+
+#[async_trait::async_trait]
+impl Summarizer for FairSummarizer {
+    async fn summarize(&self, prompt: &str) -> Result<String> {
+        let _permit = self.scheduler.acquire(&self.tenant).await?;
+        self.inner.summarize(prompt).await
+    }
+}
+
+// Send each prompt concurrently, returning the replies in prompt order. The summarizer
+// from `SummaryOptions` bounds them by the tenant's share of `LLM_MAX_CONCURRENCY`.
+async fn ask_each(summarizer: &Arc<dyn Summarizer>, prompts: Vec<String>) -> Result<Vec<String>> {
+    let mut tasks = JoinSet::new();
+    for (i, prompt) in prompts.into_iter().enumerate() {
+        let summarizer = summarizer.clone();
+        tasks.spawn(async move {
+            Ok::<_, anyhow::Error>((i, summarizer.summarize(&prompt).await?))
+        }
+        .in_current_span());
+    }
+
+    let mut summaries = Vec::new();
+    while let Some(result) = tasks.join_next().await {
+        summaries.push(result.context("summary task join error")??);
+    }
+    summaries.sort_by_key(|(i, _)| *i);
+    Ok(summaries.into_iter().map(|(_, s)| s).collect())
+}
+
+/// Receives pipeline results as they are produced, for embedders that want streaming
+/// access or their own storage instead of waiting on the returned `Vec`.
+pub trait FrameSink: Send + Sync {
+    /// Called once per frame, in completion order (not timestamp order), as soon as its
+    /// description is ready. The frame is already written to `record.path`.
+    /// This runs inside `process_video`'s completion loop, so don't block here; spawn async
+    /// work. `STORE_PROGRESS` already upserts each record to the store.
+    fn on_frame(&self, record: &FrameRecord);
+
+    /// Called with the final summary by `analyze_video`.
+    fn on_summary(&self, _summary: &str) {}
+}
+
+/// Full pipeline for embedders: `process_video` followed by `summarize_records`, reporting
+/// every frame and the summary to `sink`. Returns the same result as `process_video`.
+pub async fn analyze_video(
+    video_path: impl Into<PathBuf>,
+    options: &ProcessOptions,
+    summary_options: &SummaryOptions,
+    sink: &dyn FrameSink,
+) -> Result<VideoAnalysis> {
+    let analysis = process_video(video_path, options, Some(sink)).await?;
+    let summary = summarize_records(&analysis.records, summary_options).await?;
+    sink.on_summary(&summary);
+    Ok(analysis)
+}
+
+/// Process a whole video at `video_path`, scheduling frame analysis on a bounded
+/// async worker pool so LLM calls and encoding happen concurrently.
+/// When `sink` is given each record is also handed to it the moment it completes.
+/// Runs inside a `process_video` tracing span that gets the run's metrics as fields.
+pub async fn process_video(
+    video_path: impl Into<PathBuf>,
+    options: &ProcessOptions,
+    sink: Option<&dyn FrameSink>,
+) -> Result<VideoAnalysis> {
+    use tracing::field::Empty;
+    let span = tracing::info_span!(
+        "process_video",
+        video_id = Empty,
+        decode_ms = Empty,
+        llm_call_ms = Empty,
+        frames_described = Empty,
+        estimated_cost_usd = Empty,
+    );
+    let result = run_process_video(video_path.into(), options, sink)
+        .instrument(span)
+        .await;
+    match &result {
+        Ok(_) => metrics::counter!("videos_processed_total").increment(1),
+        Err(_) => metrics::counter!("videos_failed_total").increment(1),
+    }
+    result
+}
+
+async fn run_process_video(
+    file_path: PathBuf,
+    options: &ProcessOptions,
+    sink: Option<&dyn FrameSink>,
+) -> Result<VideoAnalysis> {
+    ffmpeg::init().map_err(|e| anyhow::anyhow!("ffmpeg init failed: {e}"))?;
+    let _active = ActiveJob::start();
+
+    fs::create_dir_all("data")
+        .await
+        .context("failed to ensure data directory exists")?;
+    let file_path = match file_path.to_str() {
+        Some(url) if is_remote_video(url) => download_video(url).await?,
+        _ => file_path,
+    };
+
+    let video_id = video_id_from_path(&file_path);
+    tracing::Span::current().record("video_id", video_id.as_str());
+    
+    info!("Processing video with ID: {}", video_id);
+    let registration =
+        JobRegistration::register(&video_id, options.control.as_ref(), options.tenant.as_deref());
+    let _deadline = options
+        .job_timeout_secs
+        .map(|secs| registration.deadline(std::time::Duration::from_secs_f64(secs)));
+    // Frames are written as they are encoded, so the video's files are claimed for the whole run
+    let lock = VideoLock::acquire(&video_id).await?;
+
+    let mut job_ctx = FrameJobContext::from_env(video_id.clone(), options)?;
+    job_ctx.progress = Some(registration.job.clone());
+    if options.prompt_cache && !options.dry_run && options.describer.is_none() {
+        job_ctx = job_ctx.with_prompt_cache().await;
+    }
+    let prompt_cache = job_ctx.clone();
+    // Frames are saved as they complete, under an empty summary until the run is stored
+    let store_progress = store::progress_enabled();
+    if store_progress {
+        let source_path = file_path.to_string_lossy();
+        let tenant = options.tenant.as_deref();
+        if let Err(e) = store::begin_analysis(&video_id, tenant, &source_path).await {
+            warn!("Failed to start the stored analysis of {}: {:#}", video_id, e);
+        }
+    }
+    let mut tasks: JoinSet<FrameRecord> = JoinSet::new();
+    // Runs alongside frame selection; the JoinSet aborts it if processing fails
+    let mut transcription = JoinSet::new();
+    if options.transcribe && !options.dry_run {
+        let (gemini, model, path) = (job_ctx.gemini.clone(), job_ctx.model.clone(), file_path.clone());
+        let deterministic = options.deterministic;
+        transcription.spawn(
+            async move { transcribe_audio(&gemini, model, &path, deterministic).await }.in_current_span(),
+        );
+    }
+
+    // Run decode + selection in an isolated scope so ffmpeg types are dropped before awaits
+    let (stats, similarities, presented, fingerprint, video_info) = {
+        // Open input and prepare decoder
+        let OpenedVideo {
+            mut ictx,
+            stream_index,
+            time_base,
+            mut decoder,
+        } = open_video(&file_path, options.demuxer_fallback)?;
+        let duration = duration_secs(&ictx, stream_index, time_base);
+        let video_info = VideoInfo {
+            duration_secs: duration,
+            fps: ictx.stream(stream_index).and_then(|stream| {
+                [stream.avg_frame_rate(), stream.rate()]
+                    .into_iter()
+                    .find(|rate| rate.numerator() > 0 && rate.denominator() > 0)
+                    .map(|rate| f64::from(rate.numerator()) / f64::from(rate.denominator()))
+            }),
+            width: decoder.width(),
+            height: decoder.height(),
+            codec: decoder.id().name().to_string(),
+        };
+        let interval = bounded_sample_interval(options, duration);
+        if let Some(duration) = duration.filter(|_| !options.keyframes_only) {
+            let estimate = 1 + (duration / interval).floor() as u64;
+            RunningJob::set(&registration.job.total_estimate, estimate);
+        }
+        let job_ctx = job_ctx.capped_for_frame(decoder.width(), decoder.height());
+        if let Some(discard) = options.decode_skip.discard() {
+            decoder.skip_frame(discard);
+        }
+        let decode_started = Instant::now();
+
+        // Helper scaler (lazy init) for winners -> RGB24 -> JPEG
+        let mut scaler: Option<FfmpegScaler> = None;
+        // Content area below/beside black bars, settled once per resolution with `auto_crop`
+        let crop: std::cell::Cell<Option<CropRect>> = std::cell::Cell::new(None);
+        let mut geometry = FrameGeometry::new(options.auto_crop);
+        let mut crop_scaler: Option<FfmpegScaler> = None;
+        let mut to_rgb_image =
+            |frame: &FfmpegVideo| frame_to_rgb_cropped(&mut scaler, frame, crop.get());
+
+        // Comparison features for the selected `FeatureMode`
+        let mut features = FeatureExtractor::new(options.feature_mode);
+
+        // Y-plane feature reference
+        let mut frames_enqueued = 0usize;
+        let first_frame_id = 0_u64;
+        info!("Decoding first frame and dispatching to LLM queue...");
+        let mut first_done = false;
+
+        // Streaming pairwise selection state
+        let mut next_sample = interval;
+        let mut next_id = 1_u64;
+        // Previous sample point's feature, for `motion_threshold`
+        let mut motion_prev: Vec<f32> = Vec::new();
+        let mut frames_seen: u64 = 0;
+        let mut last_ts: f64 = 0.0;
+        let mut pending: Option<(u64, f64, FfmpegVideo, SampleFeature)> = None; // (id, ts, frame, feat)
+        let mut ref_vec: Vec<f32> = Vec::new();
+        let mut ref_l2: f32 = 0.0;
+        let mut similarities: HashMap<u64, f32> = HashMap::new();
+        // Decode time of each queued frame, which sampled frames don't carry as `timestamp`
+        let mut presented: HashMap<u64, f64> = HashMap::new();
+        // Most recent decoded frame, kept by swapping buffers, and whether it was queued as-is
+        let mut last_frame = FfmpegVideo::empty();
+        let mut last_frame_queued = false;
+        // Hint windows sorted by start, and the best (cos vs ref, ts, frame) in the open one
+        let mut moments: Vec<(f64, f64)> = options
+            .moments
+            .iter()
+            .map(|&t| (t - options.moment_tolerance_secs, t + options.moment_tolerance_secs))
+            .collect();
+        moments.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal));
+        let mut moment_idx = 0;
+        let mut moment_best: Option<(f32, f64, FfmpegVideo)> = None;
+        let mut moments_queued = 0_u64;
+        // Only frames picked by selection itself, so hints and options don't shift it
+        let mut fingerprint = FingerprintAccumulator::default();
+        let mut near_duplicates_skipped = 0_u64;
+        // Hard `max_frames` cap, for when the duration was unknown or wrong
+        let below_max =
+            |enqueued: usize| options.max_frames.is_none_or(|max| (enqueued as u64) < max);
+
+        let mut receive_and_process = |decoder: &mut ffmpeg::decoder::Video,
+                                       packet_ts: Option<i64>|
+         -> Result<()> {
+            let mut decoded = FfmpegVideo::empty();
+            while decoder.receive_frame(&mut decoded).is_ok() {
+                if let Some(downloaded) = download_hw_frame(&decoded)? {
+                    decoded = downloaded;
+                }
+                std::mem::swap(&mut decoded, &mut last_frame);
+                let decoded = &last_frame;
+                last_frame_queued = false;
+                // Timestamp in seconds
+                let ts_units = decoded.timestamp().or(packet_ts).unwrap_or(0);
+                let ts = (ts_units as f64)
+                    * (time_base.numerator() as f64 / time_base.denominator() as f64);
+                frames_seen += 1;
+                RunningJob::set(&registration.job.decoded, frames_seen);
+                last_ts = ts;
+
+                // Adaptive-bitrate sources can switch resolution mid-stream. Features are always
+                // 64x64 so comparisons stay valid; `Reset` restarts selection from the new size.
+                let dims = (decoded.width(), decoded.height());
+                let previous_dims = geometry.resize(dims);
+                let resized = previous_dims.is_some();
+                if let Some(previous) = previous_dims {
+                    info!("Resolution changed {:?} -> {:?} at ~{:.3}s", previous, dims, ts);
+                }
+                if !geometry.crop_settled() && crop.get().is_some() {
+                    crop.set(None);
+                    features.crop = None;
+                }
+                // Fade-ins are all black; wait for a frame that shows where the content is
+                if !geometry.crop_settled() {
+                    let gray;
+                    let luma = if has_8bit_luma_plane(decoded.format()) {
+                        decoded
+                    } else {
+                        gray = frame_to_gray(&mut crop_scaler, decoded)?;
+                        &gray
+                    };
+                    if let Some(settled) = geometry.settle_crop(detect_black_bars(luma)) {
+                        if let Some(rect) = settled {
+                            info!("Cropping black bars to {:?} at ~{:.3}s", rect, ts);
+                        }
+                        crop.set(settled);
+                        features.crop = settled;
+                    }
+                }
+                // Only keyframes reach the decoder; each is described, in decode order
+                if options.keyframes_only {
+                    if below_max(frames_enqueued) {
+                        let (v, l2) = features.compute(decoded)?;
+                        let id = if first_done {
+                            let cos = cosine_similarity_feats(&ref_vec, ref_l2, &v, l2);
+                            similarities.insert(next_id, cos);
+                            next_id += 1;
+                            next_id - 1
+                        } else {
+                            first_frame_id
+                        };
+                        fingerprint.add(&v);
+                        let hash = dhash(&v);
+                        features.recycle(std::mem::replace(&mut ref_vec, v));
+                        ref_l2 = l2;
+                        let img = to_rgb_image(decoded)?;
+                        job_ctx.queue(&mut tasks, id, ts, img, hash);
+                        presented.insert(id, ts);
+                        info!("Queued keyframe id={} at ~{:.3}s", id, ts);
+                        frames_enqueued += 1;
+                        last_frame_queued = true;
+                    } else if first_done {
+                        next_id += 1;
+                    }
+                    first_done = true;
+                    continue;
+                }
+
+                // Motion sampling: a sample point is described when it differs enough from
+                // the one before it, whatever was described last
+                if let Some(threshold) = options.motion_threshold {
+                    if first_done && ts + 1e-6 < next_sample {
+                        continue;
+                    }
+                    let (v, l2) = features.compute(decoded)?;
+                    let moved = first_done && mean_abs_diff(&motion_prev, &v) > threshold;
+                    if moved && below_max(frames_enqueued) {
+                        // Similarity to the last described frame, for scene detection
+                        if !ref_vec.is_empty() {
+                            let cos = cosine_similarity_feats(&ref_vec, ref_l2, &v, l2);
+                            similarities.insert(next_id, cos);
+                        }
+                        fingerprint.add(&v);
+                        let hash = dhash(&v);
+                        let copy = features.copy_of(&v);
+                        features.recycle(std::mem::replace(&mut ref_vec, copy));
+                        ref_l2 = l2;
+                        let img = to_rgb_image(decoded)?;
+                        job_ctx.queue(&mut tasks, next_id, ts, img, hash);
+                        presented.insert(next_id, ts);
+                        info!("Queued motion frame id={} at ~{:.3}s", next_id, ts);
+                        frames_enqueued += 1;
+                        last_frame_queued = true;
+                    }
+                    features.recycle(std::mem::replace(&mut motion_prev, v));
+                    first_done = true;
+                    while ts + 1e-6 >= next_sample {
+                        next_id += 1;
+                        next_sample += interval;
+                    }
+                    continue;
+                }
+
+                if resized && options.resolution_change == ResolutionChange::Reset && below_max(frames_enqueued) {
+                    let (v, l2) = features.compute(decoded)?;
+                    fingerprint.add(&v);
+                    let hash = dhash(&v);
+                    features.recycle(std::mem::replace(&mut ref_vec, v));
+                    ref_l2 = l2;
+                    if let Some((_, _, _, feat)) = pending.take() {
+                        features.recycle(feat.vec);
+                    }
+
+                    let img = to_rgb_image(decoded)?;
+                    job_ctx.queue(&mut tasks, next_id, ts, img, hash);
+                    presented.insert(next_id, ts);
+                    frames_enqueued += 1;
+                    last_frame_queued = true;
+                    // Skip the sample points this frame already covers
+                    while ts + 1e-6 >= next_sample {
+                        next_id += 1;
+                        next_sample += interval;
+                    }
+                    continue;
+                }
+
+                if !first_done {
+                    // Initialize reference from Y plane
+                    let (v, l2) = features.compute(decoded)?;
+                    fingerprint.add(&v);
+                    let hash = dhash(&v);
+                    ref_vec = v;
+                    ref_l2 = l2;
+
+                    // Queue first frame for LLM
+                    let img = to_rgb_image(decoded)?;
+                    job_ctx.queue(&mut tasks, first_frame_id, 0.0, img.clone(), hash);
+                    presented.insert(first_frame_id, ts);
+                    frames_enqueued += 1;
+                    last_frame_queued = true;
+                    first_done = true;
+                    continue;
+                }
+
+                // Moments of interest: the most novel frame inside each hint's window is
+                // described in addition to the automatic picks
+                while moment_idx < moments.len() && ts > moments[moment_idx].1 {
+                    if let Some((cos, best_ts, frame)) =
+                        moment_best.take().filter(|_| below_max(frames_enqueued))
+                    {
+                        // Hinted moments are always described
+                        let img = to_rgb_image(&frame)?;
+                        let id = MOMENT_FRAME_ID_BASE + moment_idx as u64;
+                        job_ctx.queue(&mut tasks, id, best_ts, img, None);
+                        similarities.insert(id, cos);
+                        presented.insert(id, best_ts);
+                        info!("Queued moment-of-interest frame id={} at ~{:.3}s", id, best_ts);
+                        frames_enqueued += 1;
+                        moments_queued += 1;
+                    }
+                    moment_idx += 1;
+                }
+                if moment_idx < moments.len() && ts >= moments[moment_idx].0 {
+                    let (v, l2) = features.compute(decoded)?;
+                    let cos = cosine_similarity_feats(&ref_vec, ref_l2, &v, l2);
+                    features.recycle(v);
+                    if moment_best.as_ref().is_none_or(|(best, _, _)| cos < *best) {
+                        moment_best = Some((cos, ts, copy_frame(decoded)));
+                    }
+                }
+
+                // Sampling and streaming pairwise selection
+                if ts + 1e-6 >= next_sample && options.selection_mode == SelectionMode::Fixed {
+                    if below_max(frames_enqueued) {
+                        // Similarity is only recorded for scene detection, it picks nothing
+                        let (v, l2) = features.compute(decoded)?;
+                        let cos = cosine_similarity_feats(&ref_vec, ref_l2, &v, l2);
+                        similarities.insert(next_id, cos);
+                        fingerprint.add(&v);
+                        let hash = dhash(&v);
+                        features.recycle(std::mem::replace(&mut ref_vec, v));
+                        ref_l2 = l2;
+                        let img = to_rgb_image(decoded)?;
+                        job_ctx.queue(&mut tasks, next_id, next_sample, img, hash);
+                        presented.insert(next_id, ts);
+                        frames_enqueued += 1;
+                        last_frame_queued = true;
+                        info!("Sampled id={} at ~{:.3}s", next_id, next_sample);
+                    }
+                    // A gap between frames longer than the interval covers several points
+                    while ts + 1e-6 >= next_sample {
+                        next_id += 1;
+                        next_sample += interval;
+                    }
+                } else if ts + 1e-6 >= next_sample {
+                    // Compute features once for this decoded frame and reuse
+                    let (img_vec, img_l2) = features.compute(decoded)?;
+
+                    while ts + 1e-6 >= next_sample {
+                        if !below_max(frames_enqueued) {
+                            next_id += 1;
+                            next_sample += interval;
+                            continue;
+                        }
+                        // Capture frame for potential queue; clone only when needed
+                        let owned = copy_frame(decoded);
+
+                        let feat = SampleFeature { vec: features.copy_of(&img_vec), l2: img_l2 };
+                        presented.insert(next_id, ts);
+                        match pending.take() {
+                            None => {
+                                pending = Some((next_id, next_sample, owned, feat));
+                            }
+                            Some((left_id, left_ts, left_frame, left_feat)) => {
+                                let cos_left = cosine_similarity_feats(
+                                    &ref_vec, ref_l2, &left_feat.vec, left_feat.l2,
+                                );
+                                let cos_right = cosine_similarity_feats(
+                                    &ref_vec, ref_l2, &feat.vec, feat.l2,
+                                );
+                                let choose_left = cos_left <= cos_right;
+                                info!(
+                                    "Cosines vs ref: id{} -> {:.6}, id{} -> {:.6}",
+                                    left_id, cos_left, next_id, cos_right
+                                );
+                                let winner_cos = cos_left.min(cos_right);
+                                let near_duplicate = options
+                                    .min_dissimilarity
+                                    .is_some_and(|d| winner_cos > 1.0 - d);
+
+                                if near_duplicate {
+                                    // Both are as good as described already; keep the reference
+                                    info!(
+                                        "Skipping near-duplicate pair id{}/id{} (cos {:.6})",
+                                        left_id, next_id, winner_cos
+                                    );
+                                    near_duplicates_skipped += 1;
+                                    features.recycle(left_feat.vec);
+                                    features.recycle(feat.vec);
+                                    pending = None;
+                                } else if choose_left {
+                                    // Convert left_frame to RGB and queue
+                                    let img = to_rgb_image(&left_frame)?;
+                                    let hash = dhash(&left_feat.vec);
+                                    job_ctx.queue(&mut tasks, left_id, left_ts, img, hash);
+                                    similarities.insert(left_id, cos_left);
+                                    frames_enqueued += 1;
+                                    // Update reference
+                                    fingerprint.add(&left_feat.vec);
+                                    features.recycle(std::mem::replace(&mut ref_vec, left_feat.vec));
+                                    ref_l2 = left_feat.l2;
+                                    // Shift window: current becomes new pending
+                                    pending = Some((next_id, next_sample, owned, feat));
+                                } else {
+                                    // Convert current frame to RGB and queue
+                                    let img = to_rgb_image(&owned)?;
+                                    let hash = dhash(&feat.vec);
+                                    job_ctx.queue(&mut tasks, next_id, next_sample, img, hash);
+                                    similarities.insert(next_id, cos_right);
+                                    frames_enqueued += 1;
+                                    last_frame_queued = true;
+                                    fingerprint.add(&feat.vec);
+                                    features.recycle(std::mem::replace(&mut ref_vec, feat.vec));
+                                    features.recycle(left_feat.vec);
+                                    ref_l2 = feat.l2;
+                                    // Step by 2
+                                    pending = None;
+                                }
+                            }
+                        }
+
+                        info!("Sampled id={} at ~{:.3}s", next_id, next_sample);
+                        next_id += 1;
+                        next_sample += interval;
+                    }
+                    features.recycle(img_vec);
+                }
+            }
+            Ok(())
+        };
+
+        for (stream, packet) in ictx.packets() {
+            if stream.index() != stream_index || (options.keyframes_only && !packet.is_key()) {
+                continue;
+            }
+            if registration.job.is_stopping() {
+                break;
+            }
+            registration.check()?;
+            decoder.send_packet(&packet)?;
+            receive_and_process(&mut decoder, packet.dts())?;
+        }
+        // A stopped run selects nothing more, not even from frames still in the decoder
+        let stopped = registration.job.is_stopping();
+        if !stopped {
+            decoder.send_eof()?;
+            receive_and_process(&mut decoder, None)?;
+        }
+
+        // The video can end inside a hint's window
+        if let Some((cos, best_ts, frame)) =
+            moment_best.take().filter(|_| below_max(frames_enqueued) && !stopped)
+        {
+            let img = frame_to_rgb_cropped(&mut scaler, &frame, crop.get())?;
+            let id = MOMENT_FRAME_ID_BASE + moment_idx as u64;
+            job_ctx.queue(&mut tasks, id, best_ts, img, None);
+            similarities.insert(id, cos);
+            presented.insert(id, best_ts);
+            info!("Queued moment-of-interest frame id={} at ~{:.3}s", id, best_ts);
+            frames_enqueued += 1;
+            moments_queued += 1;
+        }
+
+        // Sampling rarely lands on the final frame, so the ending would often go undescribed
+        if queues_last_frame(options, stopped, frames_seen, last_frame_queued)
+            && below_max(frames_enqueued)
+        {
+            let (v, l2) = features.compute(&last_frame)?;
+            let cos = cosine_similarity_feats(&ref_vec, ref_l2, &v, l2);
+            let img = frame_to_rgb_cropped(&mut scaler, &last_frame, crop.get())?;
+            job_ctx.queue(&mut tasks, next_id, last_ts, img, dhash(&v));
+            similarities.insert(next_id, cos);
+            presented.insert(next_id, last_ts);
+            info!("Queued last frame id={} at ~{:.3}s", next_id, last_ts);
+            frames_enqueued += 1;
+            next_id += 1;
+        }
+
+        let decode_secs = decode_started.elapsed().as_secs_f64();
+        metrics::histogram!("decode_duration_seconds").record(decode_secs);
+        info!(
+            "Decode loop finished: {} frames in {:.2}s ({:?})",
+            frames_seen, decode_secs, options.decode_skip
+        );
+        info!("Total frames enqueued for LLM processing: {}", frames_enqueued);
+        if options.min_dissimilarity.is_some() {
+            info!("Skipped {} near-duplicate candidates", near_duplicates_skipped);
+        }
+        let candidates = next_id + moments_queued;
+        let stats = SelectionStats {
+            frames_decoded: frames_seen,
+            candidates,
+            selected: frames_enqueued as u64,
+            skipped: candidates.saturating_sub(frames_enqueued as u64),
+            duration_secs: last_ts,
+            sample_interval_secs: interval,
+            decode_secs,
+            near_duplicates_skipped,
+        };
+        (stats, similarities, presented, fingerprint.finish(), video_info)
+    };
+    let frames_enqueued = stats.selected;
+
+    info!("Total frames enqueued for LLM processing: {}", frames_enqueued);
+
+    let llm_started = Instant::now();
+    let mut records: Vec<FrameRecord> = Vec::new();
+    let mut dropped_by_stop = 0_u64;
+    loop {
+        let result = tokio::select! {
+            result = tasks.join_next() => match result {
+                Some(result) => result,
+                None => break,
+            },
+            _ = registration.job.token.cancelled(), if !registration.job.is_stopping() => {
+                // A stop only ends the queue; the calls in flight are still awaited
+                if registration.job.is_stopping() {
+                    continue;
+                }
+                // Dropping the JoinSets on return aborts the outstanding calls
+                prompt_cache.release_prompt_cache().await;
+                return Err(registration.cancelled());
+            }
+        };
+        let mut record = result.context("LLM task join error")?;
+        if record.error.as_deref() == Some(RUN_STOPPED) {
+            dropped_by_stop += 1;
+            continue;
+        }
+        RunningJob::bump(&registration.job.described);
+        record.similarity_to_prev = similarities.get(&record.frame_id).copied();
+        record.selection_score = record.similarity_to_prev;
+        if let Some(&ts) = presented.get(&record.frame_id) {
+            record.pts_ms = to_millis(ts);
+        }
+        if let Some(sink) = sink {
+            sink.on_frame(&record);
+        }
+        if store_progress {
+            if let Err(e) = store::upsert_frame(&video_id, &record).await {
+                warn!("Failed to store frame {} of {}: {:#}", record.frame_id, video_id, e);
+            }
+        }
+        records.push(record);
+    }
+
+    records.sort_by(|a, b| {
+        a.timestamp
+            .partial_cmp(&b.timestamp)
+            .unwrap_or(Ordering::Equal)
+    });
+    prompt_cache.release_prompt_cache().await;
+    let stopped = registration.job.is_stopping();
+    if stopped {
+        // Sample points after the stop, which were never examined
+        let unexamined = video_info.duration_secs.map_or(0, |duration| {
+            ((duration - stats.duration_secs).max(0.0) / stats.sample_interval_secs) as u64
+        });
+        info!(
+            "Stopped early at ~{:.3}s: {} frames described, {} queued frames dropped before \
+             their call, ~{} sample points not examined",
+            stats.duration_secs,
+            records.len(),
+            dropped_by_stop,
+            unexamined
+        );
+        transcription.abort_all();
+    }
+    if options.embed_descriptions && !options.dry_run && !stopped {
+        // Search is optional; the descriptions are still worth returning
+        if let Err(e) = embed_records(&prompt_cache.gemini, &mut records).await {
+            warn!("Embedding descriptions failed: {:#}", e);
+        }
+    }
+
+    let llm_ms = llm_started.elapsed().as_millis() as u64;
+    let transcript = match transcription.join_next().await {
+        None => Vec::new(),
+        Some(Err(e)) if e.is_cancelled() => Vec::new(),
+        Some(Ok(Ok(transcript))) => transcript,
+        // The frames are still worth returning without the speech
+        Some(Ok(Err(e))) => {
+            warn!("Transcription failed: {:#}", e);
+            Vec::new()
+        }
+        Some(Err(e)) => {
+            warn!("Transcription task failed: {}", e);
+            Vec::new()
+        }
+    };
+
+    info!("Processing complete: {} records", records.len());
+    let write_started = Instant::now();
+    storage::storage()
+        .put(&records_path(&video_id), &serde_json::to_vec(&records)?, "application/json")
+        .await
+        .context("failed to write frame records")?;
+    drop(lock);
+    let timings = PhaseTimings {
+        decode_ms: (stats.decode_secs * 1000.0) as u64,
+        llm_ms,
+        write_ms: write_started.elapsed().as_millis() as u64,
+        summary_ms: 0,
+    };
+    let metrics = RunMetrics {
+        decode_ms: timings.decode_ms,
+        llm_call_ms: prompt_cache
+            .llm_call_micros
+            .load(std::sync::atomic::Ordering::Relaxed)
+            / 1000,
+        frames_described: records.iter().filter(|r| billed(r)).count() as u64,
+        description_cache_hits: records.iter().filter(|r| r.cache_hit).count() as u64,
+        estimated_cost_usd: estimated_cost(&records),
+    };
+    let span = tracing::Span::current();
+    span.record("decode_ms", metrics.decode_ms);
+    span.record("llm_call_ms", metrics.llm_call_ms);
+    span.record("frames_described", metrics.frames_described);
+    if let Some(cost) = metrics.estimated_cost_usd {
+        span.record("estimated_cost_usd", cost);
+    }
+    info!(
+        "Run metrics: decode {} ms, {} ms in model calls, {} frames described, ~${:.4}",
+        metrics.decode_ms,
+        metrics.llm_call_ms,
+        metrics.frames_described,
+        metrics.estimated_cost_usd.unwrap_or(0.0)
+    );
+    tracing::debug!("Returning {} records", records.len());
+    Ok(VideoAnalysis {
+        records,
+        stats,
+        timings,
+        fingerprint,
+        video_id,
+        transcript,
+        metrics,
+        video_info,
+    })
+}
+
+// Error of frames a stopped run dropped before their call; never returned in records
+const RUN_STOPPED: &str = "run stopped before this frame was described";
+
+// `sample_interval_secs` adjusted so selection lands within `min_frames..=max_frames`.
+// Pairwise selection describes one frame per two sample points (fixed selection one per
+// point), plus the first frame and (with `include_last_frame`) the last; without a known
+// duration the interval is kept.
+fn bounded_sample_interval(options: &ProcessOptions, duration: Option<f64>) -> f64 {
+    let mut interval = options.sample_interval_secs;
+    let Some(duration) = duration.filter(|d| *d > 0.0) else {
+        return interval;
+    };
+    let fixed = 1 + options.include_last_frame as u64;
+    let points_per_pick = match options.selection_mode {
+        SelectionMode::Adaptive => 2,
+        SelectionMode::Fixed => 1,
+    };
+    let described = |interval: f64| fixed + (duration / interval).floor() as u64 / points_per_pick;
+    // An interval giving `picks` picks; a hair short so float error can't lose one
+    let for_picks = |picks: u64| duration / (points_per_pick * picks) as f64 * (1.0 - 1e-9);
+
+    if let Some(min) = options.min_frames {
+        let picks = min.saturating_sub(fixed);
+        if described(interval) < min && picks > 0 {
+            interval = for_picks(picks);
+        }
+    }
+    if let Some(max) = options.max_frames {
+        if described(interval) > max {
+            interval = match max.saturating_sub(fixed) {
+                // No sample point inside the video
+                0 => duration * 2.0,
+                picks => duration / (points_per_pick * picks) as f64,
+            };
+        }
+    }
+    if interval != options.sample_interval_secs {
+        info!(
+            "Sample interval {:.3}s -> {:.3}s to describe {:?}..={:?} frames of {:.1}s",
+            options.sample_interval_secs, interval, options.min_frames, options.max_frames, duration
+        );
+    }
+    interval
+}
+
+/// Up-front cost estimate for `process_video`, computed from the container without decoding
+/// frames. Where the count depends on what the frames show (`min_dissimilarity`,
+/// `motion_threshold`) the estimate is a range.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct SelectionEstimate {
+    pub duration_secs: f64,
+    /// Sample interval after `min_frames` and `max_frames` adjusted it
+    pub sample_interval_secs: f64,
+    /// Fewest frames the run can describe
+    pub estimated_frames_min: u64,
+    /// Most frames the run can describe
+    pub estimated_frames: u64,
+    /// Model calls for `estimated_frames_min` frames: frame calls (shared with
+    /// `describe_batch_size`) plus one summary call
+    pub estimated_llm_calls_min: u64,
+    /// Model calls for `estimated_frames` frames, likewise
+    pub estimated_llm_calls: u64,
+}
+
+/// Probe the video's duration (and with `keyframes_only` count its keyframes) and apply the
+/// selection math of `options` without decoding frames or calling the model.
+pub async fn estimate_video(
+    video_path: impl Into<PathBuf>,
+    options: &ProcessOptions,
+) -> Result<SelectionEstimate> {
+    let file_path = video_path.into();
+    let count_keyframes = options.keyframes_only;
+    let (duration_secs, keyframes) =
+        tokio::task::spawn_blocking(move || probe_video(&file_path, count_keyframes))
+            .await
+            .context("probe task panicked")??;
+    let max_concurrency = options.max_concurrency.unwrap_or_else(load_llm_max_concurrency);
+    Ok(estimate_selection(duration_secs, keyframes, max_concurrency, options))
+}
+
+// The selection math of `process_video` for a `duration_secs` video with `keyframes`
+// keyframes (known with `keyframes_only`)
+fn estimate_selection(
+    duration_secs: f64,
+    keyframes: Option<u64>,
+    max_concurrency: usize,
+    options: &ProcessOptions,
+) -> SelectionEstimate {
+    let interval = if options.keyframes_only {
+        options.sample_interval_secs
+    } else {
+        bounded_sample_interval(options, Some(duration_secs))
+    };
+    // Sample points after the first frame
+    let points = (duration_secs / interval).floor() as u64;
+    let last = queues_last_frame(options, false, 2, false) as u64;
+    let (min, max) = if options.keyframes_only {
+        // Every keyframe, the first one included; the last frame unless it is one
+        let keyframes = keyframes.unwrap_or(1).max(1);
+        (keyframes, keyframes + last)
+    } else if options.motion_threshold.is_some() {
+        // Only sample points that differ from the one before: none to all but the first
+        (0, points)
+    } else {
+        let picks = match options.selection_mode {
+            SelectionMode::Adaptive => points / 2,
+            SelectionMode::Fixed => points,
+        };
+        let max = 1 + picks + options.moments.len() as u64 + last;
+        // Near-duplicate pair winners are skipped, at most every one of them
+        let skippable = options.min_dissimilarity.is_some()
+            && options.selection_mode == SelectionMode::Adaptive;
+        (if skippable { max - picks } else { max }, max)
+    };
+    let cap = |frames: u64| options.max_frames.map_or(frames, |max_frames| frames.min(max_frames));
+    let (min, max) = (cap(min), cap(max));
+    let calls = |frames: u64| {
+        if options.dry_run {
+            return 0;
+        }
+        let per_call = describe_batch_size(options, max_concurrency).unwrap_or(1) as u64;
+        frames.div_ceil(per_call) + 1
+    };
+    SelectionEstimate {
+        duration_secs,
+        sample_interval_secs: interval,
+        estimated_frames_min: min,
+        estimated_frames: max,
+        estimated_llm_calls_min: calls(min),
+        estimated_llm_calls: calls(max),
+    }
+}
+
+// Container duration, falling back to the video stream's own duration, and with
+// `count_keyframes` the number of keyframe packets of the video stream (demuxed, not decoded)
+fn probe_video(file_path: &Path, count_keyframes: bool) -> Result<(f64, Option<u64>)> {
+    ffmpeg::init().map_err(|e| anyhow::anyhow!("ffmpeg init failed: {e}"))?;
+    let OpenedVideo {
+        mut ictx,
+        stream_index,
+        time_base,
+        ..
+    } = open_video(file_path, true)?;
+    let duration =
+        duration_secs(&ictx, stream_index, time_base).context("video duration is unknown")?;
+    let keyframes = count_keyframes.then(|| {
+        ictx.packets()
+            .filter(|(stream, packet)| stream.index() == stream_index && packet.is_key())
+            .count() as u64
+    });
+    Ok((duration, keyframes))
+}
+
+fn duration_secs(ictx: &FfmpegInput, stream_index: usize, time_base: ffmpeg::Rational) -> Option<f64> {
+    if ictx.duration() > 0 {
+        return Some(ictx.duration() as f64 / f64::from(ffmpeg::ffi::AV_TIME_BASE));
+    }
+    let stream = ictx.stream(stream_index)?;
+    (stream.duration() > 0).then(|| {
+        stream.duration() as f64 * f64::from(time_base.numerator())
+            / f64::from(time_base.denominator())
+    })
+}
+
+// Demuxer + decoder for the best video stream of a file
+struct OpenedVideo {
+    ictx: FfmpegInput,
+    stream_index: usize,
+    time_base: ffmpeg::Rational,
+    decoder: ffmpeg::decoder::Video,
+}
+
+// Open `path` and its video decoder. The demuxer comes from the file's magic bytes when they
+// identify the container, since FFmpeg's own probe also weighs the extension and can pick
+// the wrong one (e.g. for WebM or Matroska under another name). When that fails and
+// `fallback` is set, retry with FFmpeg's probe, then the extension's demuxer.
+fn open_video(path: &Path, fallback: bool) -> Result<OpenedVideo> {
+    let probed = probe_demuxer(path);
+    let first = probed.map_or((None, "FFmpeg's probe"), |demuxer| (Some(demuxer), "magic bytes"));
+    let err = match open_video_with(path, first.0) {
+        Ok(opened) => return Ok(opened),
+        Err(e) if !fallback => return Err(e),
+        Err(e) => e,
+    };
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_ascii_lowercase);
+    let from_ext = ext
+        .as_deref()
+        .and_then(demuxer_from_extension)
+        .filter(|demuxer| Some(*demuxer) != probed);
+    let retries = [
+        probed.map(|_| (None, "FFmpeg's probe")),
+        from_ext.map(|demuxer| (Some(demuxer), "extension")),
+    ];
+    for (demuxer, hint) in retries.into_iter().flatten() {
+        let name = demuxer.unwrap_or("default");
+        warn!(
+            "Opening {:?} with the {} demuxer failed ({:#}); retrying with the {} demuxer (from {})",
+            path,
+            first.0.unwrap_or("default"),
+            err,
+            name,
+            hint
+        );
+        match open_video_with(path, demuxer) {
+            Ok(opened) => {
+                info!("Opened {:?} with the {} demuxer", path, name);
+                return Ok(opened);
+            }
+            Err(e) => warn!("The {} demuxer failed too: {:#}", name, e),
+        }
+    }
+    Err(err)
+}
+
+fn open_video_with(path: &Path, demuxer: Option<&str>) -> Result<OpenedVideo> {
+    let ictx = match demuxer {
+        None => ff_input(path).with_context(|| format!("failed to open video file: {:?}", path))?,
+        Some(name) => open_input_as(path, name)?,
+    };
+    let input_stream = ictx
+        .streams()
+        .best(FfmpegMediaType::Video)
+        .ok_or_else(|| no_video_stream(&ictx))?;
+    let stream_index = input_stream.index();
+    let time_base = input_stream.time_base();
+    let mut context_decoder = ffmpeg::codec::context::Context::from_parameters(input_stream.parameters())?;
+    attach_hwaccel(&mut context_decoder);
+    let decoder = context_decoder.decoder().video()?;
+    Ok(OpenedVideo {
+        ictx,
+        stream_index,
+        time_base,
+        decoder,
+    })
+}
+
+// Names the demuxer that opened the input and the streams it did find, e.g. for an
+// audio-only WebM
+fn no_video_stream(ictx: &FfmpegInput) -> anyhow::Error {
+    let streams: Vec<String> = ictx
+        .streams()
+        .map(|stream| format!("{:?}", stream.parameters().medium()).to_lowercase())
+        .collect();
+    anyhow::anyhow!(
+        "No video stream found (detected format: {}; streams: {})",
+        ictx.format().name(),
+        if streams.is_empty() { "none".to_string() } else { streams.join(", ") }
+    )
+}
+
+// `HWACCEL` names an FFmpeg hardware device type (vaapi, cuda, videotoolbox, qsv, d3d11va,
+// ...) to decode with. Any failure to open the device keeps software decoding, and so
+// does FFmpeg itself when the device can't decode this codec.
+fn attach_hwaccel(context: &mut ffmpeg::codec::context::Context) {
+    let name = match env::var("HWACCEL") {
+        Ok(name) if !name.is_empty() => name,
+        _ => return,
+    };
+    match open_hw_device(context, &name) {
+        Ok(()) => info!("Decoding with {} hardware acceleration", name),
+        Err(e) => warn!("Hardware decoding via {} unavailable ({:#}); decoding in software", name, e),
+    }
+}
+
+// ffmpeg-next has no safe hwaccel API
+fn open_hw_device(context: &mut ffmpeg::codec::context::Context, name: &str) -> Result<()> {
+    let c_name = std::ffi::CString::new(name)?;
+    // SAFETY: `c_name` is a valid NUL-terminated string for the duration of the call
+    let device_type = unsafe { ffmpeg::ffi::av_hwdevice_find_type_by_name(c_name.as_ptr()) };
+    if device_type == ffmpeg::ffi::AVHWDeviceType::AV_HWDEVICE_TYPE_NONE {
+        anyhow::bail!("unknown hardware device type '{}'", name);
+    }
+    let mut device = std::ptr::null_mut();
+    // SAFETY: on success `device` receives a new buffer reference owned by us
+    let ret = unsafe {
+        ffmpeg::ffi::av_hwdevice_ctx_create(
+            &mut device,
+            device_type,
+            std::ptr::null(),
+            std::ptr::null_mut(),
+            0,
+        )
+    };
+    if ret < 0 {
+        anyhow::bail!("failed to open the device: {}", ffmpeg::Error::from(ret));
+    }
+    // SAFETY: the not yet opened codec context takes over the reference and unrefs it
+    // when it is freed
+    unsafe { (*context.as_mut_ptr()).hw_device_ctx = device };
+    Ok(())
+}
+
+// Frames decoded on a hardware device live in device memory; copy them to system memory
+// for the Y-plane and RGB paths. `None` for frames already in system memory. 8-bit video
+// arrives as NV12, which the Y-plane path reads directly; 10-bit video arrives as P010,
+// which luma features convert to GRAY8 first.
+fn download_hw_frame(frame: &FfmpegVideo) -> Result<Option<FfmpegVideo>> {
+    // SAFETY: both pointers are valid frames for the duration of the calls
+    unsafe {
+        if (*frame.as_ptr()).hw_frames_ctx.is_null() {
+            return Ok(None);
+        }
+        let mut downloaded = FfmpegVideo::empty();
+        let ret = ffmpeg::ffi::av_hwframe_transfer_data(downloaded.as_mut_ptr(), frame.as_ptr(), 0);
+        if ret < 0 {
+            anyhow::bail!("failed to copy a hardware frame to memory: {}", ffmpeg::Error::from(ret));
+        }
+        // Timestamps and the like
+        ffmpeg::ffi::av_frame_copy_props(downloaded.as_mut_ptr(), frame.as_ptr());
+        Ok(Some(downloaded))
+    }
+}
+
+// ffmpeg-next has no safe lookup of an input format by name
+fn open_input_as(path: &Path, demuxer: &str) -> Result<FfmpegInput> {
+    let name = std::ffi::CString::new(demuxer)?;
+    let format = unsafe { ffmpeg::ffi::av_find_input_format(name.as_ptr()) };
+    if format.is_null() {
+        anyhow::bail!("demuxer '{}' is not available in this FFmpeg build", demuxer);
+    }
+    // SAFETY: non-null pointer to a static demuxer description owned by libavformat
+    let format = unsafe { ffmpeg::format::Input::wrap(format as *mut _) };
+    match ffmpeg::format::open(path, &ffmpeg::format::format::Format::Input(format)) {
+        Ok(ffmpeg::format::Context::Input(ictx)) => Ok(ictx),
+        Ok(_) => anyhow::bail!("demuxer '{}' did not produce an input context", demuxer),
+        Err(e) => Err(e).with_context(|| format!("failed to open {:?} as {}", path, demuxer)),
+    }
+}
+
+// Demuxer named by the file's magic bytes, if they identify the container
+fn probe_demuxer(path: &Path) -> Option<&'static str> {
+    let mut head = [0u8; 380];
+    let read = std::fs::File::open(path)
+        .and_then(|mut f| std::io::Read::read(&mut f, &mut head))
+        .unwrap_or(0);
+    demuxer_from_magic(&head[..read])
+}
+
+fn demuxer_from_magic(head: &[u8]) -> Option<&'static str> {
+    match head {
+        [_, _, _, _, b'f', b't', b'y', b'p', ..] | [_, _, _, _, b'm', b'o', b'o', b'v', ..] => Some("mov"),
+        [0x1A, 0x45, 0xDF, 0xA3, ..] => Some("matroska"),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'A', b'V', b'I', b' ', ..] => Some("avi"),
+        [b'F', b'L', b'V', ..] => Some("flv"),
+        [b'O', b'g', b'g', b'S', ..] => Some("ogg"),
+        [0x00, 0x00, 0x01, 0xBA, ..] => Some("mpeg"),
+        // MPEG-TS: sync byte at the start of two consecutive 188-byte packets
+        _ if head.len() > 188 && head[0] == 0x47 && head[188] == 0x47 => Some("mpegts"),
+        _ => None,
+    }
+}
+
+/// Whether `ext` (in any case) is the extension of a container we can open.
+pub fn is_video_extension(ext: &str) -> bool {
+    demuxer_from_extension(&ext.to_ascii_lowercase()).is_some()
+}
+
+fn demuxer_from_extension(ext: &str) -> Option<&'static str> {
+    match ext {
+        "mp4" | "m4v" | "mov" | "3gp" => Some("mov"),
+        "mkv" | "webm" => Some("matroska"),
+        "avi" => Some("avi"),
+        "flv" => Some("flv"),
+        "ogv" | "ogg" => Some("ogg"),
+        "mpg" | "mpeg" => Some("mpeg"),
+        "ts" | "m2ts" | "mts" => Some("mpegts"),
+        _ => None,
+    }
+}
+
+// ==========================
+// Semantic search
+// ==========================
+
+// Texts per `batchEmbedContents` call, the API's limit
+const EMBED_BATCH_SIZE: usize = 100;
+
+async fn embed_texts(config: &GeminiConfig, texts: &[String], task: TaskType) -> Result<Vec<Vec<f32>>> {
+    let gemini = config.client(Model::TextEmbedding004).await?;
+    let mut embeddings = Vec::with_capacity(texts.len());
+    for batch in texts.chunks(EMBED_BATCH_SIZE) {
+        let response = gemini
+            .embed_content()
+            .with_chunks(batch.to_vec())
+            .with_task_type(task.clone())
+            .execute_batch()
+            .await
+            .context("embedding request failed")?;
+        if response.embeddings.len() != batch.len() {
+            anyhow::bail!(
+                "expected {} embeddings, got {}",
+                batch.len(),
+                response.embeddings.len()
+            );
+        }
+        embeddings.extend(response.embeddings.into_iter().map(|e| e.values));
+    }
+    Ok(embeddings)
+}
+
+// Sets `embedding` on every described record
+async fn embed_records(config: &GeminiConfig, records: &mut [FrameRecord]) -> Result<()> {
+    let mut described: Vec<&mut FrameRecord> = records
+        .iter_mut()
+        .filter(|r| r.error.is_none() && !r.description.is_empty())
+        .collect();
+    if described.is_empty() {
+        return Ok(());
+    }
+    let texts: Vec<String> = described.iter().map(|r| r.description.clone()).collect();
+    let embeddings = embed_texts(config, &texts, TaskType::RetrievalDocument).await?;
+    for (record, embedding) in described.iter_mut().zip(embeddings) {
+        record.embedding = Some(embedding);
+    }
+    info!("Embedded {} frame descriptions", texts.len());
+    Ok(())
+}
+
+/// Summarize the stored frames of `video_id` again, e.g. in another style or language,
+/// without decoding or describing anything. `None` if no analysis of it is stored. The
+/// store keeps neither confidences nor the transcript, so `SummaryOptions::min_confidence`
+/// has no effect and speech isn't interleaved.
+pub async fn resummarize_video(video_id: &str, options: &SummaryOptions) -> Result<Option<String>> {
+    let Some(stored) = store::load_video(video_id).await? else {
+        return Ok(None);
+    };
+    let records: Vec<FrameRecord> = stored
+        .frames
+        .into_iter()
+        .map(|frame| FrameRecord {
+            frame_id: frame.frame_id,
+            timestamp: frame.timestamp,
+            description: frame.description,
+            path: frame.path,
+            alt_text: None,
+            labels: Vec::new(),
+            model: String::new(),
+            error: None,
+            similarity_to_prev: None,
+            selection_score: None,
+            pts_ms: to_millis(frame.timestamp),
+            jpeg_bytes: None,
+            embedding: None,
+            cache_hit: false,
+            confidence: None,
+        })
+        .collect();
+    summarize_records(&records, options).await.map(Some)
+}
+
+/// A stored frame matching a `search_frames` query.
+#[derive(Serialize, Debug, Clone)]
+pub struct SearchHit {
+    pub frame_id: u64,
+    pub timestamp: f64,
+    pub description: String,
+    pub path: String,
+    /// Cosine similarity of the query and description embeddings
+    pub score: f32,
+}
+
+/// The `top_k` stored frames of `video_id` whose descriptions are closest to `query`, best
+/// first. `None` when no embeddings are stored for the video, i.e. it wasn't processed
+/// with `ProcessOptions::embed_descriptions`.
+pub async fn search_frames(video_id: &str, query: &str, top_k: usize) -> Result<Option<Vec<SearchHit>>> {
+    let frames = store::load_embeddings(video_id).await?;
+    if frames.is_empty() {
+        return Ok(None);
+    }
+    let config = GeminiConfig::from_env()?;
+    let query_vec = embed_texts(&config, &[query.to_string()], TaskType::RetrievalQuery)
+        .await?
+        .pop()
+        .context("no embedding returned for the query")?;
+    let query_l2 = dot_f32(&query_vec, &query_vec).sqrt();
+    let mut hits: Vec<SearchHit> = frames
+        .into_iter()
+        .map(|(frame, embedding)| SearchHit {
+            score: cosine_similarity_feats(
+                &query_vec,
+                query_l2,
+                &embedding,
+                dot_f32(&embedding, &embedding).sqrt(),
+            ),
+            frame_id: frame.frame_id,
+            timestamp: frame.timestamp,
+            description: frame.description,
+            path: frame.path,
+        })
+        .collect();
+    hits.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(Ordering::Equal));
+    hits.truncate(top_k);
+    Ok(Some(hits))
+}
+
+// ==========================
+// Audio transcription
+// ==========================
+
+/// A stretch of speech from the video's audio track.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TranscriptSegment {
+    /// Seconds from the start of the video
+    pub timestamp: f64,
+    pub text: String,
+}
+
+const TRANSCRIBE_SAMPLE_RATE: u32 = 16_000;
+
+/// Audio per transcription call; 5 minutes of 16 kHz mono PCM is ~9.6 MB, under the
+/// inline-data request limit.
+const TRANSCRIBE_CHUNK_SECS: u32 = 300;
+
+const TRANSCRIBE_PROMPT: &str = "Transcribe the speech in this audio clip. Output one line per sentence or short phrase, formatted as [<start>s] <spoken text>, where <start> is the seconds from the start of the clip with one decimal, e.g. [12.3s] Welcome back. Output nothing else, and nothing at all if there is no speech.";
+
+// Speech in the file's audio track, empty if it has none
+async fn transcribe_audio(
+    gemini: &GeminiConfig,
+    model: Model,
+    path: &Path,
+    deterministic: bool,
+) -> Result<Vec<TranscriptSegment>> {
+    let owned = path.to_path_buf();
+    let Some(samples) = tokio::task::spawn_blocking(move || extract_audio(&owned))
+        .await
+        .context("audio extraction task panicked")??
+    else {
+        info!("No audio track in {:?}; nothing to transcribe", path);
+        return Ok(Vec::new());
+    };
+
+    let client = gemini.client(model).await?;
+    let chunk_len = (TRANSCRIBE_SAMPLE_RATE * TRANSCRIBE_CHUNK_SECS) as usize;
+    let mut segments = Vec::new();
+    for (i, chunk) in samples.chunks(chunk_len).enumerate() {
+        let offset = (i * chunk_len) as f64 / TRANSCRIBE_SAMPLE_RATE as f64;
+        let b64 = general_purpose::STANDARD.encode(wav_bytes(chunk));
+        let builder = client.generate_content().with_user_message(TRANSCRIBE_PROMPT);
+        // Only greedy decoding: an output limit would cut transcripts short
+        let response = with_sampling(builder, (deterministic, GenerationParams::default()))
+            .with_inline_data(b64, "audio/wav")
+            .execute()
+            .await
+            .with_context(|| format!("transcription of the audio from {:.0}s failed", offset))?;
+        segments.extend(parse_transcript(&response.text(), offset));
+    }
+    info!("Transcribed {} speech segments", segments.len());
+    Ok(segments)
+}
+
+// `[12.3s] text` lines of a transcription answer, shifted by the chunk's `offset`
+fn parse_transcript(answer: &str, offset: f64) -> Vec<TranscriptSegment> {
+    let line = regex::Regex::new(r"^\[(\d+(?:\.\d+)?)\s*s?\]\s*(.+)$").expect("valid regex");
+    answer
+        .lines()
+        .filter_map(|l| {
+            let captures = line.captures(l.trim())?;
+            Some(TranscriptSegment {
+                timestamp: offset + captures[1].parse::<f64>().ok()?,
+                text: captures[2].trim().to_string(),
+            })
+        })
+        .collect()
+}
+
+// The audio track decoded to 16 kHz mono PCM, `None` if the file has no audio stream
+fn extract_audio(path: &Path) -> Result<Option<Vec<i16>>> {
+    let mut ictx =
+        ff_input(path).with_context(|| format!("failed to open video file: {:?}", path))?;
+    let Some(input_stream) = ictx.streams().best(FfmpegMediaType::Audio) else {
+        return Ok(None);
+    };
+    let stream_index = input_stream.index();
+    let context_decoder = ffmpeg::codec::context::Context::from_parameters(input_stream.parameters())?;
+    let mut decoder = context_decoder.decoder().audio()?;
+
+    // Built from the first frame, whose layout is more reliable than the stream's
+    let mut resampler: Option<ffmpeg::software::resampling::Context> = None;
+    let mut samples = Vec::new();
+    let mut decoded = FfmpegAudio::empty();
+    for (stream, packet) in ictx.packets() {
+        if stream.index() != stream_index {
+            continue;
+        }
+        decoder.send_packet(&packet)?;
+        while decoder.receive_frame(&mut decoded).is_ok() {
+            resample_audio(&mut resampler, &mut decoded, &mut samples)?;
+        }
+    }
+    decoder.send_eof()?;
+    while decoder.receive_frame(&mut decoded).is_ok() {
+        resample_audio(&mut resampler, &mut decoded, &mut samples)?;
+    }
+    if let Some(resampler) = &mut resampler {
+        let mut flushed = FfmpegAudio::empty();
+        resampler.flush(&mut flushed)?;
+        push_mono_samples(&flushed, &mut samples);
+    }
+    Ok(Some(samples))
+}
+
+fn resample_audio(
+    resampler: &mut Option<ffmpeg::software::resampling::Context>,
+    frame: &mut FfmpegAudio,
+    samples: &mut Vec<i16>,
+) -> Result<()> {
+    // Some decoders leave the layout unset; resampling needs one
+    if frame.channel_layout().is_empty() {
+        frame.set_channel_layout(ffmpeg::ChannelLayout::default(frame.channels() as i32));
+    }
+    let resampler = match resampler {
+        Some(resampler) => resampler,
+        None => resampler.insert(ffmpeg::software::resampling::Context::get(
+            frame.format(),
+            frame.channel_layout(),
+            frame.rate(),
+            ffmpeg::format::Sample::I16(ffmpeg::format::sample::Type::Packed),
+            ffmpeg::ChannelLayout::MONO,
+            TRANSCRIBE_SAMPLE_RATE,
+        )?),
+    };
+    let mut resampled = FfmpegAudio::empty();
+    resampler.run(frame, &mut resampled)?;
+    push_mono_samples(&resampled, samples);
+    Ok(())
+}
+
+fn push_mono_samples(frame: &FfmpegAudio, samples: &mut Vec<i16>) {
+    // A resampler that is still buffering hands back a frame without planes
+    if frame.samples() > 0 && frame.planes() > 0 {
+        samples.extend_from_slice(frame.plane::<i16>(0));
+    }
+}
+
+// 16-bit mono PCM WAV file
+fn wav_bytes(samples: &[i16]) -> Vec<u8> {
+    let data_len = (samples.len() * 2) as u32;
+    let mut wav = Vec::with_capacity(44 + data_len as usize);
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes()); // fmt chunk size
+    wav.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    wav.extend_from_slice(&1u16.to_le_bytes()); // mono
+    wav.extend_from_slice(&TRANSCRIBE_SAMPLE_RATE.to_le_bytes());
+    wav.extend_from_slice(&(TRANSCRIBE_SAMPLE_RATE * 2).to_le_bytes()); // byte rate
+    wav.extend_from_slice(&2u16.to_le_bytes()); // block align
+    wav.extend_from_slice(&16u16.to_le_bytes()); // bits per sample
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_len.to_le_bytes());
+    for sample in samples {
+        wav.extend_from_slice(&sample.to_le_bytes());
+    }
+    wav
+}
+
+// ==========================
+// Gemini endpoint configuration
+// ==========================
+
+/// How calls to the Gemini endpoint are authenticated.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GeminiAuth {
+    /// `GOOGLE_API_KEY` sent as `x-goog-api-key` (public Gemini API, Vertex AI express mode)
+    #[default]
+    ApiKey,
+    /// OAuth bearer token of a service account: `GOOGLE_ACCESS_TOKEN` when set, otherwise
+    /// fetched from the metadata server of the GCE/GKE/Cloud Run instance we run on
+    ServiceAccount,
+}
+
+/// Endpoint settings as given in env or a request; unset fields fall back to the env values.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GeminiEndpoint {
+    /// Full base URL; takes precedence over `project` + `region`
+    pub base_url: Option<String>,
+    /// Vertex AI project, used with `region` to build the regional endpoint
+    pub project: Option<String>,
+    pub region: Option<String>,
+    pub auth: Option<GeminiAuth>,
+}
+
+impl GeminiEndpoint {
+    // `GEMINI_BASE_URL`, `GEMINI_PROJECT`, `GEMINI_REGION` and `GEMINI_AUTH`
+    fn from_env() -> Result<Self> {
+        let auth = match env::var("GEMINI_AUTH").ok().as_deref() {
+            None | Some("") | Some("api_key") => None,
+            Some("service_account") => Some(GeminiAuth::ServiceAccount),
+            Some(other) => anyhow::bail!(
+                "Invalid GEMINI_AUTH value '{}'; expected api_key or service_account",
+                other
+            ),
+        };
+        let var = |name| env::var(name).ok().filter(|v: &String| !v.is_empty());
+        Ok(Self {
+            base_url: var("GEMINI_BASE_URL"),
+            project: var("GEMINI_PROJECT"),
+            region: var("GEMINI_REGION"),
+            auth,
+        })
+    }
+
+    fn or(&self, fallback: &GeminiEndpoint) -> GeminiEndpoint {
+        // An explicit location replaces the env one as a whole
+        let located = self.base_url.is_some() || self.project.is_some() || self.region.is_some();
+        let location = if located { self } else { fallback };
+        GeminiEndpoint {
+            base_url: location.base_url.clone(),
+            project: location.project.clone(),
+            region: location.region.clone(),
+            auth: self.auth.or(fallback.auth),
+        }
+    }
+}
+
+/// Resolved, validated endpoint and credentials for Gemini calls.
+#[derive(Clone)]
+pub struct GeminiConfig {
+    /// `None` keeps gemini-rust's public API default
+    base_url: Option<reqwest::Url>,
+    auth: GeminiAuth,
+    api_key: String,
+}
+
+impl std::fmt::Debug for GeminiConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Never print the key
+        f.debug_struct("GeminiConfig")
+            .field("base_url", &self.base_url.as_ref().map(reqwest::Url::as_str))
+            .field("auth", &self.auth)
+            .finish()
+    }
+}
+
+impl GeminiConfig {
+    /// Configuration from the environment; checked at startup so mistakes fail fast.
+    pub fn from_env() -> Result<Self> {
+        Self::resolve(&GeminiEndpoint::from_env()?)
+    }
+
+    /// Environment configuration with a per-request override applied. A request may pick
+    /// a Vertex AI `project` and `region`, but a `base_url` only when it is listed in
+    /// `GEMINI_ALLOWED_BASE_URLS`, since our credentials are sent to it.
+    pub fn with_override(endpoint: &GeminiEndpoint) -> Result<Self> {
+        if let Some(url) = &endpoint.base_url {
+            let allowlist = env::var("GEMINI_ALLOWED_BASE_URLS").unwrap_or_default();
+            if !base_url_allowed(url, &allowlist) {
+                anyhow::bail!(
+                    "base_url '{}' is not in GEMINI_ALLOWED_BASE_URLS; set project and region \
+                     instead",
+                    url
+                );
+            }
+        }
+        Self::resolve(&endpoint.or(&GeminiEndpoint::from_env()?))
+    }
+
+    fn resolve(endpoint: &GeminiEndpoint) -> Result<Self> {
+        let raw_url = match (&endpoint.base_url, &endpoint.project, &endpoint.region) {
+            (Some(url), _, _) => Some(url.clone()),
+            (None, Some(project), Some(region)) => {
+                for (name, value) in [("project", project), ("region", region)] {
+                    if value.is_empty()
+                        || !value.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+                    {
+                        anyhow::bail!("Invalid Gemini {} '{}'", name, value);
+                    }
+                }
+                Some(format!(
+                    "https://{region}-aiplatform.googleapis.com/v1/projects/{project}/locations/{region}/publishers/google/"
+                ))
+            }
+            (None, None, None) => None,
+            _ => anyhow::bail!("Gemini project and region must be set together"),
+        };
+        let base_url = match raw_url {
+            Some(mut raw) => {
+                // Endpoints are joined onto the base, which drops a last segment without '/'
+                if !raw.ends_with('/') {
+                    raw.push('/');
+                }
+                let url = reqwest::Url::parse(&raw)
+                    .with_context(|| format!("Invalid Gemini base URL '{}'", raw))?;
+                if !matches!(url.scheme(), "https" | "http") {
+                    anyhow::bail!("Gemini base URL must be http(s): '{}'", raw);
+                }
+                Some(url)
+            }
+            None => None,
+        };
+
+        let auth = endpoint.auth.unwrap_or_default();
+        let api_key = match auth {
+            GeminiAuth::ApiKey => env::var("GOOGLE_API_KEY")
+                .ok()
+                .filter(|k| !k.is_empty())
+                .context("GOOGLE_API_KEY must be set for api_key auth")?,
+            // gemini-rust always sends `x-goog-api-key`; it stays empty next to the bearer token
+            GeminiAuth::ServiceAccount => String::new(),
+        };
+        Ok(Self {
+            base_url,
+            auth,
+            api_key,
+        })
+    }
+
+    /// Short description for logs, e.g. "https://europe-west4-aiplatform.googleapis.com/... (service_account)"
+    pub fn describe(&self) -> String {
+        let url = self
+            .base_url
+            .as_ref()
+            .map_or("default Gemini API endpoint", reqwest::Url::as_str);
+        let auth = match self.auth {
+            GeminiAuth::ApiKey => "api_key",
+            GeminiAuth::ServiceAccount => "service_account",
+        };
+        format!("{} ({})", url, auth)
+    }
+
+    async fn client(&self, model: Model) -> Result<Gemini> {
+        let mut builder = GeminiBuilder::new(self.api_key.clone()).with_model(model);
+        if let Some(url) = &self.base_url {
+            builder = builder.with_base_url(url.clone());
+        }
+        if self.auth == GeminiAuth::ServiceAccount {
+            let token = service_account_token().await?;
+            let mut headers = reqwest::header::HeaderMap::new();
+            headers.insert(
+                reqwest::header::AUTHORIZATION,
+                reqwest::header::HeaderValue::from_str(&format!("Bearer {}", token))
+                    .context("access token is not a valid header value")?,
+            );
+            builder = builder.with_http_client(reqwest::Client::builder().default_headers(headers));
+        }
+        Ok(builder.build()?)
+    }
+}
+
+// Whether `url` is one of the comma-separated `allowlist`, ignoring a trailing '/'
+fn base_url_allowed(url: &str, allowlist: &str) -> bool {
+    let url = url.trim_end_matches('/');
+    allowlist
+        .split(',')
+        .map(|allowed| allowed.trim().trim_end_matches('/'))
+        .any(|allowed| !allowed.is_empty() && allowed == url)
+}
+
+const METADATA_TOKEN_URL: &str =
+    "http://metadata.google.internal/computeMetadata/v1/instance/service-accounts/default/token";
+
+// Metadata-server tokens live about an hour; reuse one until shortly before it expires
+static SERVICE_ACCOUNT_TOKEN: std::sync::Mutex<Option<(String, Instant)>> =
+    std::sync::Mutex::new(None);
+
+async fn service_account_token() -> Result<String> {
+    if let Ok(token) = env::var("GOOGLE_ACCESS_TOKEN") {
+        return Ok(token);
+    }
+    if let Some((token, expires)) = SERVICE_ACCOUNT_TOKEN.lock().unwrap().as_ref() {
+        if Instant::now() < *expires {
+            return Ok(token.clone());
+        }
+    }
+
+    #[derive(Deserialize)]
+    struct TokenResponse {
+        access_token: String,
+        expires_in: u64,
+    }
+    let response: TokenResponse = reqwest::Client::new()
+        .get(METADATA_TOKEN_URL)
+        .header("Metadata-Flavor", "Google")
+        .send()
+        .await
+        .context("failed to reach the metadata server for a service account token")?
+        .error_for_status()?
+        .json()
+        .await?;
+    let expires = Instant::now()
+        + std::time::Duration::from_secs(response.expires_in.saturating_sub(60));
+    *SERVICE_ACCOUNT_TOKEN.lock().unwrap() = Some((response.access_token.clone(), expires));
+    Ok(response.access_token)
+}
+
+// ==========================
+// Live monitoring (RTSP)
+// ==========================
+
+/// Seconds between candidate frames examined by the live monitor.
+const LIVE_SAMPLE_INTERVAL_SECS: f64 = 0.25;
+
+/// Default novelty threshold: a candidate is described once its cosine similarity to the
+/// last described frame drops below `1.0 - threshold`.
+pub const DEFAULT_NOVELTY_THRESHOLD: f32 = 0.1;
+
+// (id, ts, image) handed from the live decode thread to the LLM queue
+type LiveCandidate = (u64, f64, ImageBuffer<Rgb<u8>, Vec<u8>>);
+
+/// Continuously decode a live source (e.g. `rtsp://...`) and forward a described
+/// `FrameRecord` every time the scene changes. Runs until the source ends, an error
+/// occurs, or the receiving side of `tx` is dropped (client went away).
+pub async fn monitor_live(
+    url: String,
+    novelty_threshold: f32,
+    tx: tokio::sync::mpsc::Sender<FrameRecord>,
+) -> Result<()> {
+    ffmpeg::init().map_err(|e| anyhow::anyhow!("ffmpeg init failed: {e}"))?;
+    fs::create_dir_all("data")
+        .await
+        .context("failed to ensure data directory exists")?;
+
+    let _active = ActiveJob::start();
+    let video_id = format!("live_{}", chrono::Utc::now().timestamp_millis());
+    info!("Starting live monitor {} for {}", video_id, url);
+    let job_ctx = FrameJobContext::from_env(video_id, &ProcessOptions::default())?;
+
+    // Decoding blocks indefinitely, so it lives on its own thread and hands scene changes over
+    let (cand_tx, mut cand_rx) = tokio::sync::mpsc::channel(8);
+    let decode = tokio::task::spawn_blocking(move || decode_live(&url, novelty_threshold, cand_tx));
+
+    let mut tasks: JoinSet<FrameRecord> = JoinSet::new();
+    let mut decoding = true;
+    while decoding || !tasks.is_empty() {
+        tokio::select! {
+            candidate = cand_rx.recv(), if decoding => match candidate {
+                Some((frame_id, timestamp, image)) => {
+                    job_ctx.queue(&mut tasks, frame_id, timestamp, image, None)
+                }
+                None => decoding = false,
+            },
+            Some(result) = tasks.join_next(), if !tasks.is_empty() => {
+                let record = result.context("LLM task join error")?;
+                if tx.send(record).await.is_err() {
+                    break;
+                }
+            },
+            _ = tx.closed() => break,
+        }
+    }
+
+    // Dropping the receiver makes the decode thread's next send fail, which stops it
+    drop(cand_rx);
+    tasks.abort_all();
+    info!("Live monitor stopped");
+    decode.await.context("live decode task panicked")?
+}
+
+// Blocking decode loop for live sources: sample every LIVE_SAMPLE_INTERVAL_SECS and emit
+// frames whose similarity to the last emitted one falls below the novelty threshold.
+fn decode_live(
+    url: &str,
+    novelty_threshold: f32,
+    tx: tokio::sync::mpsc::Sender<LiveCandidate>,
+) -> Result<()> {
+    let mut options = ffmpeg::Dictionary::new();
+    // TCP interleaving avoids the packet loss UDP RTP sees on busy networks
+    options.set("rtsp_transport", "tcp");
+    let mut ictx = ffmpeg::format::input_with_dictionary(&url, options)
+        .with_context(|| format!("failed to open live source: {}", url))?;
+    let input_stream = ictx
+        .streams()
+        .best(FfmpegMediaType::Video)
+        .ok_or_else(|| no_video_stream(&ictx))?;
+    let stream_index = input_stream.index();
+    let time_base = input_stream.time_base();
+    let context_decoder = ffmpeg::codec::context::Context::from_parameters(input_stream.parameters())?;
+    let mut decoder = context_decoder.decoder().video()?;
+
+    let mut scaler: Option<FfmpegScaler> = None;
+    let mut features = FeatureExtractor::new(FeatureMode::Luma);
+    let mut ref_feat: Option<SampleFeature> = None;
+    let mut next_sample = 0.0_f64;
+    let mut next_id = 0_u64;
+
+    let mut receive_and_emit = |decoder: &mut ffmpeg::decoder::Video,
+                                packet_ts: Option<i64>|
+     -> Result<bool> {
+        let mut decoded = FfmpegVideo::empty();
+        while decoder.receive_frame(&mut decoded).is_ok() {
+            let ts_units = decoded.timestamp().or(packet_ts).unwrap_or(0);
+            let ts = (ts_units as f64)
+                * (time_base.numerator() as f64 / time_base.denominator() as f64);
+            if ts + 1e-6 < next_sample {
+                continue;
+            }
+            next_sample = ts + LIVE_SAMPLE_INTERVAL_SECS;
+
+            let (vec, l2) = features.compute(&decoded)?;
+            let changed = match &ref_feat {
+                None => true,
+                Some(r) => {
+                    cosine_similarity_feats(&r.vec, r.l2, &vec, l2) < 1.0 - novelty_threshold
+                }
+            };
+            if !changed {
+                features.recycle(vec);
+                continue;
+            }
+
+            let img = frame_to_rgb(&mut scaler, &decoded)?;
+            info!("Scene change at ~{:.3}s (id={})", ts, next_id);
+            if tx.blocking_send((next_id, ts, img)).is_err() {
+                // Consumer is gone; stop decoding
+                return Ok(false);
+            }
+            if let Some(old) = ref_feat.replace(SampleFeature { vec, l2 }) {
+                features.recycle(old.vec);
+            }
+            next_id += 1;
+        }
+        Ok(true)
+    };
+
+    for (stream, packet) in ictx.packets() {
+        if stream.index() != stream_index {
+            continue;
+        }
+        decoder.send_packet(&packet)?;
+        if !receive_and_emit(&mut decoder, packet.dts())? {
+            return Ok(());
+        }
+    }
+    decoder.send_eof()?;
+    receive_and_emit(&mut decoder, None)?;
+    info!("Live source ended");
+    Ok(())
+}
+
+// ==================
+// Small helper funcs
 // ==================
-// Small helper funcs
-// ==================
 
-// Compute 64x64 feature from Y plane only. Supports common 8-bit YUV formats (YUV420p/NV12).
-fn compute_feature_from_y(frame: &FfmpegVideo) -> Result<(Vec<f32>, f32)> {
-    // Get Y plane geometry
-    let w = frame.width() as usize;
-    let h = frame.height() as usize;
-    let stride = frame.stride(0);
-    let y_plane = frame.data(0);
+// "42.5s", "3m 07s", "1h 02m"
+fn format_duration(secs: f64) -> String {
+    let total = secs.max(0.0).round() as u64;
+    if secs < 60.0 {
+        format!("{:.1}s", secs.max(0.0))
+    } else if total < 3600 {
+        format!("{}m {:02}s", total / 60, total % 60)
+    } else {
+        format!("{}h {:02}m", total / 3600, (total % 3600) / 60)
+    }
+}
+
+/// Video ID from the filename (e.g., "1761542252139_crashDemo.mp4" -> "1761542252139_crashDemo")
+pub fn video_id_from_path(path: &std::path::Path) -> String {
+    path.file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("unknown")
+        .to_string()
+}
+
+// Records of the latest run, kept next to the frames so they can be re-exported later
+fn records_path(video_id: &str) -> String {
+    format!("data/{}_records.json", video_id)
+}
+
+fn manifest_path(video_id: &str) -> String {
+    format!("data/{}_manifest.json", video_id)
+}
+
+// The video a file in `data/` belongs to: its frames, records, manifest, or the video itself
+fn artifact_video_id(name: &str) -> Option<&str> {
+    if let Some(id) = name
+        .strip_suffix("_records.json")
+        .or_else(|| name.strip_suffix("_manifest.json"))
+    {
+        return Some(id);
+    }
+    if let Some((id, number)) = name.strip_suffix(".jpg").and_then(|rest| rest.rsplit_once("_frame_")) {
+        if !number.is_empty() && number.bytes().all(|b| b.is_ascii_digit()) {
+            return Some(id);
+        }
+    }
+    let (stem, ext) = name.rsplit_once('.')?;
+    (!stem.is_empty() && is_video_extension(ext)).then_some(stem)
+}
+
+fn is_running(video_id: &str) -> bool {
+    RUNNING_JOBS.lock().unwrap().contains_key(video_id)
+}
+
+/// Delete every file of `video_id` in `data/` or storage (video, frames, records, manifest)
+/// and its stored analysis, if `tenant` ran that analysis. Returns false if there was
+/// nothing to delete (no files in `data/`, records or stored analysis, or the id can't
+/// name a video) or the video belongs to another tenant; fails while the video is being
+/// processed.
+pub async fn delete_video(video_id: &str, tenant: Option<&str>) -> Result<bool> {
+    if !is_safe_video_id(video_id) {
+        return Ok(false);
+    }
+    // Without a stored analysis the owner is unknown, so only a deployment without API
+    // keys, where everyone is the same tenant, may delete such files
+    match store::video_tenant(video_id).await? {
+        Some(owner) if owner.as_deref() != tenant => return Ok(false),
+        None if tenant.is_some() => return Ok(false),
+        _ => {}
+    }
+    if is_running(video_id) {
+        anyhow::bail!("video {} is being processed", video_id);
+    }
+    let _lock = VideoLock::acquire(video_id).await?;
+    let mut removed = 0;
+    // Frames are found through the records, since a bucket can't be listed like `data/`
+    let storage = storage::storage();
+    // A bucket can't say whether a DELETE removed anything, so count what the records list
+    let records = load_records(video_id).await?;
+    if let Some(records) = &records {
+        removed += records.len() + 1;
+    }
+    let keys = records.iter().flatten().map(|record| record.path.clone());
+    for key in keys.chain([manifest_path(video_id), records_path(video_id)]) {
+        storage.delete(&key).await?;
+    }
+    let mut entries = fs::read_dir("data").await.context("failed to read data directory")?;
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name();
+        if name.to_str().and_then(artifact_video_id) == Some(video_id) {
+            fs::remove_file(entry.path())
+                .await
+                .with_context(|| format!("failed to delete {}", entry.path().display()))?;
+            removed += 1;
+        }
+    }
+    let stored = store::delete_video(video_id).await?;
+    info!("Deleted {} files of {}", removed, video_id);
+    Ok(removed > 0 || stored)
+}
+
+/// Delete frame JPEGs and videos in `data/` last modified more than `max_age` ago,
+/// skipping videos with a run in flight. Returns how many files were removed.
+pub async fn sweep_data(max_age: std::time::Duration) -> Result<usize> {
+    let mut removed = 0;
+    let mut entries = fs::read_dir("data").await.context("failed to read data directory")?;
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name();
+        let Some(name) = name.to_str() else {
+            continue;
+        };
+        // Records and manifests are small and keep exports working
+        if name.ends_with(".json") {
+            continue;
+        }
+        let Some(video_id) = artifact_video_id(name) else {
+            continue;
+        };
+        if is_running(video_id) {
+            continue;
+        }
+        let age = entry
+            .metadata()
+            .await
+            .ok()
+            .and_then(|m| m.modified().ok())
+            .and_then(|modified| modified.elapsed().ok());
+        if age.is_some_and(|age| age > max_age) {
+            match fs::remove_file(entry.path()).await {
+                Ok(()) => removed += 1,
+                Err(e) => warn!("Failed to delete {}: {}", entry.path().display(), e),
+            }
+        }
+    }
+    Ok(removed)
+}
+
+/// Run `sweep_data` hourly with `DATA_RETENTION_HOURS` as the maximum age; returns at
+/// once when the variable is unset, keeping files forever.
+pub async fn run_data_retention() {
+    let hours = match env::var("DATA_RETENTION_HOURS") {
+        Err(_) => return,
+        Ok(raw) => match raw.parse::<f64>() {
+            Ok(hours) if hours > 0.0 && hours.is_finite() => hours,
+            _ => {
+                warn!("Invalid DATA_RETENTION_HOURS value '{}'; keeping files forever", raw);
+                return;
+            }
+        },
+    };
+    let max_age = std::time::Duration::from_secs_f64(hours * 3600.0);
+    let every = max_age.min(std::time::Duration::from_secs(3600));
+    info!("Deleting frames and videos in data/ older than {} hours", hours);
+    loop {
+        match sweep_data(max_age).await {
+            Ok(0) => {}
+            Ok(removed) => info!("Retention sweep deleted {} files", removed),
+            Err(e) => warn!("Retention sweep failed: {:#}", e),
+        }
+        tokio::time::sleep(every).await;
+    }
+}
+
+/// Exclusive claim on one video's files in `data/`, shared by every server instance that
+/// mounts the directory. Take it around writing or deleting a video's frames, records or
+/// manifest so instances don't remove files another is still writing. Released on drop.
+///
+/// The claim is an OS advisory lock on a `data/{video_id}.lock` file, so it ends with the
+/// process that holds it and a crashed instance never leaves a live lock behind.
+/// `DATA_LOCKING=false` turns locking off for single-instance deployments.
+pub struct VideoLock {
+    // The locked file, removed before the lock is released; `None` with locking off
+    held: Option<(PathBuf, std::fs::File)>,
+}
+
+impl VideoLock {
+    /// How long to wait for another instance to finish with the video.
+    const TIMEOUT: std::time::Duration = std::time::Duration::from_secs(120);
+    const POLL: std::time::Duration = std::time::Duration::from_millis(200);
+
+    pub async fn acquire(video_id: &str) -> Result<Self> {
+        if !is_safe_video_id(video_id) {
+            anyhow::bail!("invalid video id '{}'", video_id);
+        }
+        if matches!(env::var("DATA_LOCKING").as_deref(), Ok("false" | "0" | "no")) {
+            return Ok(Self { held: None });
+        }
+        let path = PathBuf::from(format!("data/{}.lock", video_id));
+        let started = Instant::now();
+        loop {
+            if let Some(file) = Self::try_lock(&path)? {
+                return Ok(Self {
+                    held: Some((path, file)),
+                });
+            }
+            if started.elapsed() > Self::TIMEOUT {
+                anyhow::bail!("timed out waiting for {} held by another instance", path.display());
+            }
+            tokio::time::sleep(Self::POLL).await;
+        }
+    }
+
+    // The lock file at `path`, locked, or `None` while another instance holds it
+    fn try_lock(path: &Path) -> Result<Option<std::fs::File>> {
+        use std::io::Write;
+        use std::os::unix::fs::MetadataExt;
+
+        let mut file = std::fs::File::options()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .with_context(|| format!("failed to open {}", path.display()))?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(std::fs::TryLockError::WouldBlock) => return Ok(None),
+            Err(std::fs::TryLockError::Error(e)) => {
+                return Err(e).with_context(|| format!("failed to lock {}", path.display()))
+            }
+        }
+        // The last holder removes the file before unlocking it, so a lock taken on a file
+        // opened before that excludes no one; only the file still at `path` counts
+        let opened = file.metadata()?;
+        let current = std::fs::metadata(path).ok();
+        if !current.is_some_and(|m| m.dev() == opened.dev() && m.ino() == opened.ino()) {
+            return Ok(None);
+        }
+        // Owner info only helps whoever inspects a lock file
+        let _ = file.set_len(0);
+        let _ = writeln!(file, "pid {}", std::process::id());
+        Ok(Some(file))
+    }
+}
+
+impl Drop for VideoLock {
+    fn drop(&mut self) {
+        // Removed while still locked, then unlocked when the file closes
+        if let Some((path, _file)) = &self.held {
+            if let Err(e) = std::fs::remove_file(path) {
+                warn!("Failed to release lock {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+// IDs from URLs must name a file directly inside `data/`
+fn is_safe_video_id(video_id: &str) -> bool {
+    !video_id.is_empty() && !video_id.starts_with('.') && !video_id.contains(['/', '\\'])
+}
+
+// Parsed JSON file from storage, `None` if it doesn't exist
+async fn read_json_file<T: serde::de::DeserializeOwned>(path: &str) -> Result<Option<T>> {
+    match storage::storage().get(path).await? {
+        Some(bytes) => Ok(Some(
+            serde_json::from_slice(&bytes).with_context(|| format!("failed to parse {}", path))?,
+        )),
+        None => Ok(None),
+    }
+}
+
+/// Records saved by the last `process_video` run of `video_id`, or `None` if it was never
+/// processed (or the ID could escape `data/`).
+pub async fn load_records(video_id: &str) -> Result<Option<Vec<FrameRecord>>> {
+    if !is_safe_video_id(video_id) {
+        return Ok(None);
+    }
+    read_json_file(&records_path(video_id)).await
+}
+
+/// Save the run manifest (options, metadata, records, summary, usage) for `video_id`.
+pub async fn write_manifest(video_id: &str, manifest: &serde_json::Value) -> Result<()> {
+    let _lock = VideoLock::acquire(video_id).await?;
+    storage::storage()
+        .put(&manifest_path(video_id), &serde_json::to_vec_pretty(manifest)?, "application/json")
+        .await
+        .context("failed to write run manifest")
+}
+
+/// Manifest written by the last run of `video_id`, or `None` if there is none.
+pub async fn load_manifest(video_id: &str) -> Result<Option<serde_json::Value>> {
+    if !is_safe_video_id(video_id) {
+        return Ok(None);
+    }
+    read_json_file(&manifest_path(video_id)).await
+}
+
+/// URL of a record's frame image: relative to the server root where `data/` is served, or
+/// a presigned or public bucket URL with S3 storage.
+pub async fn thumbnail_url(record: &FrameRecord) -> Result<String> {
+    storage::storage().url(&record.path).await
+}
+
+/// RFC 4180 CSV of `records` with `frame_id,timestamp,description,thumbnail_url` columns.
+pub async fn records_to_csv(records: &[FrameRecord]) -> Result<String> {
+    use std::fmt::Write as _;
+
+    fn field(value: &str) -> String {
+        if value.contains([',', '"', '\r', '\n']) {
+            format!("\"{}\"", value.replace('"', "\"\""))
+        } else {
+            value.to_string()
+        }
+    }
+    let mut csv = String::from("frame_id,timestamp,description,thumbnail_url\r\n");
+    for record in records {
+        let _ = write!(
+            csv,
+            "{},{:.3},{},{}\r\n",
+            record.frame_id,
+            record.timestamp,
+            field(&record.description),
+            field(&thumbnail_url(record).await?)
+        );
+    }
+    Ok(csv)
+}
+
+/// WebVTT captions of `records` (sorted by timestamp): each description is shown from its
+/// frame's timestamp until the next frame's, the last one for one sampling interval.
+pub fn to_webvtt(records: &[FrameRecord]) -> String {
+    use std::fmt::Write as _;
+
+    // Cue text can't contain "-->" or raw markup characters
+    fn cue_text(description: &str) -> String {
+        single_line(description)
+            .replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+    }
+    let mut vtt = String::from("WEBVTT\n");
+    for (i, record) in records.iter().enumerate() {
+        let (start, end) = cue_span(records, i);
+        let _ = write!(
+            vtt,
+            "\n{}\n{} --> {}\n{}\n",
+            record.frame_id,
+            cue_time(start, '.'),
+            cue_time(end, '.'),
+            cue_text(&record.description)
+        );
+    }
+    vtt
+}
+
+/// SRT subtitles of `records` (sorted by timestamp), with the cue timing of `to_webvtt`.
+/// Cues are numbered from 1 as SRT requires.
+pub fn to_srt(records: &[FrameRecord]) -> String {
+    use std::fmt::Write as _;
+
+    // SRT has no escapes, and players read a line with "-->" as a cue's timing
+    fn cue_text(description: &str) -> String {
+        single_line(description).replace("-->", "->")
+    }
+    let mut srt = String::new();
+    for (i, record) in records.iter().enumerate() {
+        let (start, end) = cue_span(records, i);
+        let _ = write!(
+            srt,
+            "{}\n{} --> {}\n{}\n\n",
+            i + 1,
+            cue_time(start, ','),
+            cue_time(end, ','),
+            cue_text(&record.description)
+        );
+    }
+    srt
+}
+
+fn to_millis(secs: f64) -> u64 {
+    (secs.max(0.0) * 1000.0).round() as u64
+}
+
+// HH:MM:SS plus milliseconds after `separator` ('.' for WebVTT, ',' for SRT)
+fn cue_time(secs: f64, separator: char) -> String {
+    let millis = (secs.max(0.0) * 1000.0).round() as u64;
+    format!(
+        "{:02}:{:02}:{:02}{}{:03}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        separator,
+        millis % 1000
+    )
+}
+
+// Shown until the next record's timestamp, the last one for one sampling interval
+fn cue_span(records: &[FrameRecord], i: usize) -> (f64, f64) {
+    let start = records[i].timestamp;
+    let end = match records.get(i + 1) {
+        Some(next) if next.timestamp > start => next.timestamp,
+        _ => start + DEFAULT_SAMPLE_INTERVAL_SECS,
+    };
+    (start, end)
+}
+
+// A blank line ends a cue, so descriptions are collapsed onto one line
+fn single_line(description: &str) -> String {
+    description.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Tiles per contact sheet row unless the request asks otherwise.
+pub const DEFAULT_CONTACT_SHEET_COLUMNS: u32 = 6;
+/// Widest tile; tiles shrink as needed to keep the sheet within the limits below.
+const CONTACT_SHEET_TILE_WIDTH: u32 = 320;
+const CONTACT_SHEET_MAX_WIDTH: u32 = 4096;
+// JPEG dimensions are 16-bit
+const CONTACT_SHEET_MAX_HEIGHT: u32 = 65_000;
+
+// Frame images fetched at once from S3 storage
+const FRAME_FETCH_CONCURRENCY: usize = 16;
+
+/// A frame's `frame_id` and stored JPEG.
+pub type FrameImage = (u64, Vec<u8>);
+
+/// The stored frame image of each record, in record order. Images that are missing or can't
+/// be read are left out with a warning.
+pub async fn load_frame_images(records: &[FrameRecord]) -> Vec<FrameImage> {
+    let permits = Arc::new(Semaphore::new(FRAME_FETCH_CONCURRENCY));
+    let mut fetches = JoinSet::new();
+    for (index, record) in records.iter().enumerate() {
+        let (frame_id, path, permits) = (record.frame_id, record.path.clone(), permits.clone());
+        fetches.spawn(async move {
+            let _permit = permits.acquire_owned().await;
+            (index, frame_id, storage::storage().get(&path).await)
+        });
+    }
+    let mut images = Vec::with_capacity(records.len());
+    while let Some(fetched) = fetches.join_next().await {
+        match fetched {
+            Ok((index, frame_id, Ok(Some(jpeg)))) => images.push((index, frame_id, jpeg)),
+            Ok((_, frame_id, Ok(None))) => warn!("Frame {} has no stored image", frame_id),
+            Ok((_, frame_id, Err(e))) => warn!("Failed to load frame {}: {:#}", frame_id, e),
+            Err(e) => warn!("Frame fetch task failed: {}", e),
+        }
+    }
+    images.sort_by_key(|&(index, _, _)| index);
+    images.into_iter().map(|(_, frame_id, jpeg)| (frame_id, jpeg)).collect()
+}
+
+/// One JPEG tiling `images` (from `load_frame_images`) in order, `columns` to a row. Tiles
+/// take the median aspect ratio of the frames, and frames of another shape are letterboxed
+/// on black. Images that can't be decoded are left out.
+pub fn build_contact_sheet(images: &[FrameImage], columns: u32) -> Result<Vec<u8>> {
+    // Sizes come from the headers first, so only one full frame is decoded at a time
+    let frames: Vec<(&FrameImage, u32, u32)> = images
+        .iter()
+        .filter_map(|image| {
+            let dimensions = image::ImageReader::new(std::io::Cursor::new(&image.1))
+                .with_guessed_format()
+                .map_err(image::ImageError::IoError)
+                .and_then(|reader| reader.into_dimensions());
+            match dimensions {
+                Ok((width, height)) if width > 0 && height > 0 => Some((image, width, height)),
+                Ok(_) => None,
+                Err(e) => {
+                    warn!("Leaving frame {} off the contact sheet: {}", image.0, e);
+                    None
+                }
+            }
+        })
+        .collect();
+    if frames.is_empty() {
+        anyhow::bail!("no frame images to tile");
+    }
+
+    let count = frames.len() as u32;
+    let columns = columns.clamp(1, count);
+    let rows = count.div_ceil(columns);
+    let mut aspects: Vec<f64> = frames.iter().map(|&(_, w, h)| h as f64 / w as f64).collect();
+    aspects.sort_by(f64::total_cmp);
+    let aspect = aspects[aspects.len() / 2];
+    let widest = CONTACT_SHEET_TILE_WIDTH
+        .min(CONTACT_SHEET_MAX_WIDTH / columns)
+        .min((CONTACT_SHEET_MAX_HEIGHT as f64 / rows as f64 / aspect) as u32);
+    let tile_width = widest.max(1);
+    let tile_height = ((tile_width as f64 * aspect).round() as u32).max(1);
+
+    let mut sheet =
+        ImageBuffer::from_pixel(columns * tile_width, rows * tile_height, Rgb([0u8, 0, 0]));
+    let mut placed = 0;
+    for ((frame_id, jpeg), width, height) in frames {
+        let frame = match image::load_from_memory(jpeg) {
+            Ok(frame) => frame.to_rgb8(),
+            Err(e) => {
+                warn!("Leaving frame {} off the contact sheet: {}", frame_id, e);
+                continue;
+            }
+        };
+        let scale = (tile_width as f64 / width as f64).min(tile_height as f64 / height as f64);
+        let w = ((width as f64 * scale).round() as u32).clamp(1, tile_width);
+        let h = ((height as f64 * scale).round() as u32).clamp(1, tile_height);
+        let thumb = image::imageops::resize(&frame, w, h, image::imageops::FilterType::Triangle);
+        let x = (placed % columns) * tile_width + (tile_width - w) / 2;
+        let y = (placed / columns) * tile_height + (tile_height - h) / 2;
+        image::imageops::replace(&mut sheet, &thumb, x as i64, y as i64);
+        placed += 1;
+    }
+    encode_jpeg(&sheet, DEFAULT_JPEG_QUALITY)
+}
+
+/// `frame_id` of the frame picked for the first moment of interest (in time order); the
+/// next moments follow it. Far above any sample point's index.
+pub const MOMENT_FRAME_ID_BASE: u64 = 1_000_000;
+
+/// Largest video accepted, by upload or download (matches the router's body limit).
+pub const MAX_VIDEO_BYTES: u64 = 500 * 1024 * 1024;
+
+/// Whether `process_video` downloads `video_path` instead of opening it locally.
+pub fn is_remote_video(video_path: &str) -> bool {
+    video_path.starts_with("http://") || video_path.starts_with("https://")
+}
+
+/// Why `check_video_path` turned down a local video path.
+#[derive(Debug)]
+pub enum VideoPathError {
+    /// Nothing exists at the path
+    NotFound,
+    /// The path, or what its symlinks point to, lies outside `data/`
+    OutsideDataDir,
+    /// Not a file whose first bytes or extension belong to a container we can open
+    NotAVideo,
+    /// It exists but can't be read
+    Unreadable(std::io::Error),
+}
+
+impl std::fmt::Display for VideoPathError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VideoPathError::NotFound => write!(f, "file not found"),
+            VideoPathError::OutsideDataDir => write!(f, "path is outside the data directory"),
+            VideoPathError::NotAVideo => write!(f, "not a valid video file"),
+            VideoPathError::Unreadable(e) => write!(f, "file is not readable: {}", e),
+        }
+    }
+}
+
+/// Check a local `video_path` from an API request before processing: it must be a
+/// readable file inside `data/` (after resolving `..` and symlinks) that looks like a
+/// video. Embedders calling `process_video` directly aren't confined.
+pub async fn check_video_path(video_path: &str) -> Result<(), VideoPathError> {
+    let cwd = std::env::current_dir().map_err(VideoPathError::Unreadable)?;
+    // Lexically first, so paths outside don't even reveal whether they exist
+    let mut lexical = PathBuf::new();
+    for component in cwd.join(video_path).components() {
+        match component {
+            std::path::Component::ParentDir => {
+                lexical.pop();
+            }
+            std::path::Component::CurDir => {}
+            other => lexical.push(other),
+        }
+    }
+    if !lexical.starts_with(cwd.join("data")) {
+        return Err(VideoPathError::OutsideDataDir);
+    }
+    let not_found = |e: std::io::Error| match e.kind() {
+        std::io::ErrorKind::NotFound => VideoPathError::NotFound,
+        _ => VideoPathError::Unreadable(e),
+    };
+    let data_dir = fs::canonicalize("data").await.map_err(not_found)?;
+    let path = fs::canonicalize(video_path).await.map_err(not_found)?;
+    if !path.starts_with(&data_dir) {
+        return Err(VideoPathError::OutsideDataDir);
+    }
+    if !fs::metadata(&path).await.map_err(not_found)?.is_file() {
+        return Err(VideoPathError::NotAVideo);
+    }
+    fs::File::open(&path).await.map_err(VideoPathError::Unreadable)?;
+    let known_extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(is_video_extension);
+    let probed = tokio::task::spawn_blocking(move || probe_demuxer(&path).is_some())
+        .await
+        .unwrap_or(false);
+    if !(probed || known_extension) {
+        return Err(VideoPathError::NotAVideo);
+    }
+    Ok(())
+}
+
+/// Whether a video may be downloaded from `url`. With `VIDEO_URL_ALLOWED_HOSTS` set only
+/// its hosts are, otherwise any host but a private, loopback or link-local IP literal;
+/// names resolving to such addresses are refused by `PublicResolver`.
+fn video_url_allowed(url: &reqwest::Url, allowlist: &str) -> bool {
+    let Some(host) = url.host_str() else {
+        return false;
+    };
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    if !allowlist.trim().is_empty() {
+        return allowlist
+            .split(',')
+            .map(str::trim)
+            .any(|allowed| !allowed.is_empty() && allowed.eq_ignore_ascii_case(host));
+    }
+    host.parse::<std::net::IpAddr>().ok().is_none_or(is_public_ip)
+}
+
+fn is_public_ip(ip: std::net::IpAddr) -> bool {
+    use std::net::IpAddr;
+
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                // Carrier-grade NAT, 100.64.0.0/10
+                || (a == 100 && (64..128).contains(&b))
+                || a == 0)
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(v4) => is_public_ip(v4.into()),
+            None => {
+                let first = ip.segments()[0];
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    // Unique local fc00::/7 and link-local fe80::/10
+                    || (first & 0xfe00) == 0xfc00
+                    || (first & 0xffc0) == 0xfe80)
+            }
+        },
+    }
+}
+
+// Resolves hosts for video downloads to their public addresses only, so a name can't
+// point a download at the server's own network
+struct PublicResolver;
+
+impl reqwest::dns::Resolve for PublicResolver {
+    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        Box::pin(async move {
+            let addrs: Vec<_> = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|addr| is_public_ip(addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                return Err(format!("{} has no public address", name.as_str()).into());
+            }
+            Ok(Box::new(addrs.into_iter()) as reqwest::dns::Addrs)
+        })
+    }
+}
+
+// Client for video downloads, checking every redirect like the first URL
+fn download_client() -> Result<reqwest::Client> {
+    const MAX_REDIRECTS: usize = 5;
+
+    let allowlist = env::var("VIDEO_URL_ALLOWED_HOSTS").unwrap_or_default();
+    let restricted = !allowlist.trim().is_empty();
+    let redirects = {
+        let allowlist = allowlist.clone();
+        reqwest::redirect::Policy::custom(move |attempt| {
+            if attempt.previous().len() >= MAX_REDIRECTS {
+                attempt.error("too many redirects")
+            } else if !video_url_allowed(attempt.url(), &allowlist) {
+                let message = format!("redirect to {} is not allowed", attempt.url());
+                attempt.error(message)
+            } else {
+                attempt.follow()
+            }
+        })
+    };
+    let mut builder = reqwest::Client::builder().redirect(redirects);
+    // Allowlisted hosts are trusted wherever they resolve
+    if !restricted {
+        builder = builder.dns_resolver(Arc::new(PublicResolver));
+    }
+    builder.build().context("failed to build the download client")
+}
+
+// Stream a remote video into `data/` under the upload handler's `{millis}_{name}` scheme
+async fn download_video(url: &str) -> Result<PathBuf> {
+    use tokio::io::AsyncWriteExt;
+
+    let parsed = reqwest::Url::parse(url).with_context(|| format!("invalid video URL {}", url))?;
+    let allowlist = env::var("VIDEO_URL_ALLOWED_HOSTS").unwrap_or_default();
+    if !video_url_allowed(&parsed, &allowlist) {
+        anyhow::bail!("downloading videos from {} is not allowed", url);
+    }
+    let mut response = download_client()?
+        .get(parsed)
+        .send()
+        .await
+        .with_context(|| format!("failed to download {}", url))?
+        .error_for_status()
+        .with_context(|| format!("failed to download {}", url))?;
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("")
+        .to_string();
+    if !content_type.starts_with("video/") {
+        anyhow::bail!(
+            "{} is not a video (content-type '{}')",
+            url,
+            if content_type.is_empty() { "none" } else { &content_type }
+        );
+    }
+    if response.content_length().is_some_and(|len| len > MAX_VIDEO_BYTES) {
+        anyhow::bail!("{} is larger than {} MB", url, MAX_VIDEO_BYTES / (1024 * 1024));
+    }
+
+    let name = response
+        .url()
+        .path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .map(|segment| {
+            segment
+                .chars()
+                .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+                .collect::<String>()
+        })
+        .filter(|name| !name.is_empty() && !name.starts_with('.'))
+        .unwrap_or_else(|| "video.mp4".to_string());
+    let path = PathBuf::from("data").join(format!(
+        "{}_{}",
+        chrono::Utc::now().timestamp_millis(),
+        name
+    ));
+    info!("Downloading {} to {}", url, path.display());
+
+    let written = async {
+        let mut file = fs::File::create(&path).await?;
+        let mut total: u64 = 0;
+        while let Some(chunk) = response.chunk().await? {
+            total += chunk.len() as u64;
+            if total > MAX_VIDEO_BYTES {
+                anyhow::bail!("{} is larger than {} MB", url, MAX_VIDEO_BYTES / (1024 * 1024));
+            }
+            file.write_all(&chunk).await?;
+        }
+        file.flush().await?;
+        Ok(())
+    };
+    if let Err(e) = written.await {
+        let _ = fs::remove_file(&path).await;
+        return Err(e.context(format!("failed to download {}", url)));
+    }
+    Ok(path)
+}
+
+/// File extension for a `video/*` content type, `None` for other types. Unknown video
+/// subtypes are left to FFmpeg's probe under a generic extension.
+pub fn extension_for_content_type(content_type: &str) -> Option<&'static str> {
+    let subtype = content_type
+        .split(';')
+        .next()?
+        .trim()
+        .to_ascii_lowercase()
+        .strip_prefix("video/")?
+        .to_string();
+    Some(match subtype.as_str() {
+        "mp4" => "mp4",
+        "quicktime" => "mov",
+        "webm" => "webm",
+        "x-matroska" | "matroska" => "mkv",
+        "x-msvideo" | "avi" => "avi",
+        "mp2t" => "ts",
+        "mpeg" => "mpg",
+        "3gpp" => "3gp",
+        _ => "video",
+    })
+}
+
+// Removes a video written only for one run, however the run ends (dropped futures included)
+struct TempVideo(PathBuf);
+
+impl Drop for TempVideo {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.0) {
+            warn!("Failed to remove temporary video {}: {}", self.0.display(), e);
+        }
+    }
+}
+
+/// `process_video` for a video held in memory; see `process_video_chunks`.
+pub async fn process_video_bytes(
+    bytes: Vec<u8>,
+    extension: &str,
+    options: &ProcessOptions,
+    sink: Option<&dyn FrameSink>,
+) -> Result<VideoAnalysis> {
+    let chunks = tokio_stream::once(Ok::<_, std::io::Error>(bytes));
+    process_video_chunks(chunks, extension, options, sink).await
+}
+
+/// `process_video` for a video arriving in chunks, e.g. piped into a request body. The
+/// chunks are written to `data/{millis}_stream.{extension}` (`{millis}_stream-2...` when
+/// another body got that name first) as they arrive, so the video never sits in memory,
+/// and the decode loop reads it from there (the video ID follows that name). The file is
+/// removed again once the run is over.
+pub async fn process_video_chunks<S, B, E>(
+    mut chunks: S,
+    extension: &str,
+    options: &ProcessOptions,
+    sink: Option<&dyn FrameSink>,
+) -> Result<VideoAnalysis>
+where
+    S: tokio_stream::Stream<Item = std::result::Result<B, E>> + Unpin,
+    B: AsRef<[u8]>,
+    E: std::error::Error + Send + Sync + 'static,
+{
+    use tokio::io::AsyncWriteExt;
+    use tokio_stream::StreamExt;
+
+    fs::create_dir_all("data")
+        .await
+        .context("failed to ensure data directory exists")?;
+    let millis = chrono::Utc::now().timestamp_millis();
+    let mut attempt = 1;
+    // Created exclusively, so bodies arriving in the same millisecond get their own files
+    let (path, mut file) = loop {
+        let name = match attempt {
+            1 => format!("{}_stream.{}", millis, extension),
+            n => format!("{}_stream-{}.{}", millis, n, extension),
+        };
+        let path = PathBuf::from("data").join(name);
+        match fs::OpenOptions::new().write(true).create_new(true).open(&path).await {
+            Ok(file) => break (path, file),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists && attempt < 100 => {
+                attempt += 1;
+            }
+            Err(e) => {
+                return Err(e).with_context(|| format!("failed to create {}", path.display()))
+            }
+        }
+    };
+    // From here on a failed write leaves nothing behind either
+    let temp = TempVideo(path);
+    let mut written = 0u64;
+    while let Some(chunk) = chunks.next().await {
+        let chunk = chunk.context("failed to read video data")?;
+        let chunk = chunk.as_ref();
+        written += chunk.len() as u64;
+        if written > MAX_VIDEO_BYTES {
+            anyhow::bail!("video is larger than {} MB", MAX_VIDEO_BYTES / (1024 * 1024));
+        }
+        file.write_all(chunk)
+            .await
+            .with_context(|| format!("failed to write {}", temp.0.display()))?;
+    }
+    file.flush()
+        .await
+        .with_context(|| format!("failed to write {}", temp.0.display()))?;
+    drop(file);
+    process_video(temp.0.clone(), options, sink).await
+}
+
+/// Human-readable title from a video ID: drops the upload handler's timestamp-millis prefix
+/// and turns separators into spaces ("1761542252139_crash_demo" -> "crash demo").
+/// Returns `None` when nothing meaningful is left.
+pub fn title_from_video_id(video_id: &str) -> Option<String> {
+    let name = match video_id.split_once('_') {
+        Some((prefix, rest)) if !prefix.is_empty() && prefix.bytes().all(|b| b.is_ascii_digit()) => rest,
+        _ => video_id,
+    };
+    let title = name
+        .split(['_', '-', '.'])
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ");
+    if title.is_empty() || title.bytes().all(|b| b.is_ascii_digit()) {
+        None
+    } else {
+        Some(title)
+    }
+}
+
+// Deep copy of a decoded frame that outlives the decoder's buffer
+fn copy_frame(decoded: &FfmpegVideo) -> FfmpegVideo {
+    let mut owned = FfmpegVideo::empty();
+    unsafe {
+        // Allocate and copy decoded into owned clone
+        owned.alloc(decoded.format(), decoded.width(), decoded.height());
+    }
+    // Copy planes; the fresh buffer's stride can differ from the decoder's
+    // (alignment, resolution changes), so index each side by its own stride
+    for plane in 0..decoded.planes() {
+        let src = decoded.data(plane);
+        let src_stride = decoded.stride(plane);
+        let dst_stride = owned.stride(plane);
+        let plane_h = decoded.plane_height(plane) as usize;
+        let row_len = src_stride.min(dst_stride);
+        let dst = owned.data_mut(plane);
+        for y in 0..plane_h {
+            let s = &src[y * src_stride..y * src_stride + row_len];
+            let d = &mut dst[y * dst_stride..y * dst_stride + row_len];
+            d.copy_from_slice(s);
+        }
+    }
+    owned
+}
+
+// (Re)build the RGB24 scaler, scaling to `out_w`x`out_h`, when the source format or size
+// changes mid-stream
+fn ensure_scaler_to(
+    scaler: &mut Option<FfmpegScaler>,
+    src_format: FfmpegPixel,
+    w: u32,
+    h: u32,
+    out_w: u32,
+    out_h: u32,
+) -> Result<()> {
+    ensure_scaler(scaler, src_format, w, h, FfmpegPixel::RGB24, out_w, out_h)
+}
+
+fn ensure_scaler(
+    scaler: &mut Option<FfmpegScaler>,
+    src_format: FfmpegPixel,
+    w: u32,
+    h: u32,
+    out_format: FfmpegPixel,
+    out_w: u32,
+    out_h: u32,
+) -> Result<()> {
+    let need_new = match scaler {
+        Some(s) => {
+            let inp = s.input();
+            let out = s.output();
+            inp.format != src_format
+                || inp.width != w
+                || inp.height != h
+                || out.format != out_format
+                || out.width != out_w
+                || out.height != out_h
+        }
+        None => true,
+    };
+    if need_new {
+        *scaler = Some(
+            FfmpegScaler::get(
+                src_format,
+                w,
+                h,
+                out_format,
+                out_w,
+                out_h,
+                FfmpegScaleFlags::BILINEAR,
+            )
+            .map_err(anyhow::Error::from)?,
+        );
+    }
+    Ok(())
+}
+
+// Whether plane 0 of `format` is 8-bit luma at full resolution, as `compute_feature_from_y`
+// and `detect_black_bars` read it. VP9/AV1 profile 0 decodes to YUV420P; their 10-bit
+// profiles (YUV420P10, and P010 from hardware decoders) store 16-bit samples.
+fn has_8bit_luma_plane(format: FfmpegPixel) -> bool {
+    matches!(
+        format,
+        FfmpegPixel::YUV420P
+            | FfmpegPixel::YUVJ420P
+            | FfmpegPixel::YUV422P
+            | FfmpegPixel::YUVJ422P
+            | FfmpegPixel::YUV444P
+            | FfmpegPixel::YUVJ444P
+            | FfmpegPixel::YUV410P
+            | FfmpegPixel::YUV411P
+            | FfmpegPixel::NV12
+            | FfmpegPixel::NV21
+            | FfmpegPixel::GRAY8
+    )
+}
+
+// Same-size GRAY8 copy of a frame without a directly readable 8-bit luma plane
+fn frame_to_gray(scaler: &mut Option<FfmpegScaler>, frame: &FfmpegVideo) -> Result<FfmpegVideo> {
+    let (w, h) = (frame.width(), frame.height());
+    ensure_scaler(scaler, frame.format(), w, h, FfmpegPixel::GRAY8, w, h)?;
+    let mut gray = FfmpegVideo::empty();
+    scaler.as_mut().unwrap().run(frame, &mut gray)?;
+    Ok(gray)
+}
+
+// Convert a decoded frame to an RGB ImageBuffer for JPEG/LLM
+fn frame_to_rgb(
+    scaler: &mut Option<FfmpegScaler>,
+    frame: &FfmpegVideo,
+) -> Result<ImageBuffer<Rgb<u8>, Vec<u8>>> {
+    let (w, h) = (frame.width(), frame.height());
+    ensure_scaler_to(scaler, frame.format(), w, h, w, h)?;
+    let mut rgb = FfmpegVideo::empty();
+    scaler.as_mut().unwrap().run(frame, &mut rgb)?;
+    let w = rgb.width();
+    let h = rgb.height();
+    let stride = rgb.stride(0);
+    let src = rgb.data(0);
+    let row_len = (w as usize) * 3;
+    let mut out = vec![0u8; row_len * (h as usize)];
+    for y in 0..(h as usize) {
+        let src_off = y * stride;
+        let dst_off = y * row_len;
+        out[dst_off..dst_off + row_len].copy_from_slice(&src[src_off..src_off + row_len]);
+    }
+    ImageBuffer::from_raw(w, h, out)
+        .ok_or_else(|| anyhow::anyhow!("failed to build RGB image"))
+}
+
+/// Region of a frame in pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct CropRect {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+// Decoded frame size and, with `auto_crop`, whether the black-bar crop is known for it.
+// Adaptive-bitrate sources can switch resolution mid-stream; the crop is then detected
+// again at the new size.
+struct FrameGeometry {
+    dims: Option<(u32, u32)>,
+    auto_crop: bool,
+    crop_settled: bool,
+}
+
+impl FrameGeometry {
+    fn new(auto_crop: bool) -> Self {
+        Self { dims: None, auto_crop, crop_settled: !auto_crop }
+    }
+
+    // Record the size of the next decoded frame; the previous size when it changed
+    fn resize(&mut self, dims: (u32, u32)) -> Option<(u32, u32)> {
+        let previous = self.dims.replace(dims).filter(|&previous| previous != dims)?;
+        self.crop_settled = !self.auto_crop;
+        Some(previous)
+    }
+
+    fn crop_settled(&self) -> bool {
+        self.crop_settled
+    }
+
+    // Settle the crop from `bars`, the `detect_black_bars` result of the current frame:
+    // `None` while frames are all dark, otherwise the crop (`None` when there are no bars)
+    fn settle_crop(&mut self, bars: Option<CropRect>) -> Option<Option<CropRect>> {
+        let (rect, dims) = (bars?, self.dims?);
+        self.crop_settled = true;
+        Some(((rect.width, rect.height) != dims).then_some(rect))
+    }
+}
+
+// Whether `include_last_frame` adds the final decoded frame once decoding ends: not for a
+// stopped run, motion sampling, a single-frame video or a final frame already queued
+fn queues_last_frame(
+    options: &ProcessOptions,
+    stopped: bool,
+    frames_seen: u64,
+    last_frame_queued: bool,
+) -> bool {
+    options.include_last_frame
+        && !stopped
+        && options.motion_threshold.is_none()
+        && frames_seen > 1
+        && !last_frame_queued
+}
+
+// Rows/columns whose luma never exceeds this count as bars (video black is 16)
+const BAR_MAX_LUMA: u8 = 32;
+
+// Bounding box of the non-black area of an 8-bit YUV frame, sampling every 4th pixel.
+// `None` when the frame is entirely dark; the full frame when the box would drop more
+// than half of either side, which is a dark scene rather than bars.
+fn detect_black_bars(frame: &FfmpegVideo) -> Option<CropRect> {
+    black_bars(frame.data(0), frame.stride(0), frame.width(), frame.height())
+}
+
+// `detect_black_bars` on a luma plane of `w`x`h` pixels, `stride` bytes to a row
+fn black_bars(y_plane: &[u8], stride: usize, w: u32, h: u32) -> Option<CropRect> {
+    const STEP: usize = 4;
+    let (w, h) = (w as usize, h as usize);
+    let lit = |x: usize, y: usize| y_plane[y * stride + x] > BAR_MAX_LUMA;
+
+    let row_lit = |y: usize| (0..w).step_by(STEP).any(|x| lit(x, y));
+    let top = (0..h).find(|&y| row_lit(y))?;
+    let bottom = (top..h).rev().find(|&y| row_lit(y)).unwrap_or(top);
+    let col_lit = |x: usize| (top..=bottom).step_by(STEP).any(|y| lit(x, y));
+    let left = (0..w).find(|&x| col_lit(x)).unwrap_or(0);
+    let right = (left..w).rev().find(|&x| col_lit(x)).unwrap_or(w - 1);
+
+    // Even offsets keep 4:2:0 chroma aligned with the cropped luma
+    let (x, y) = (left & !1, top & !1);
+    let (width, height) = (right + 1 - x, bottom + 1 - y);
+    if width * 2 < w || height * 2 < h {
+        return Some(CropRect { x: 0, y: 0, width: w as u32, height: h as u32 });
+    }
+    Some(CropRect {
+        x: x as u32,
+        y: y as u32,
+        width: width as u32,
+        height: height as u32,
+    })
+}
+
+// `frame_to_rgb` then `crop_rgb`
+fn frame_to_rgb_cropped(
+    scaler: &mut Option<FfmpegScaler>,
+    frame: &FfmpegVideo,
+    crop: Option<CropRect>,
+) -> Result<ImageBuffer<Rgb<u8>, Vec<u8>>> {
+    Ok(crop_rgb(frame_to_rgb(scaler, frame)?, crop))
+}
+
+fn crop_rgb(
+    image: ImageBuffer<Rgb<u8>, Vec<u8>>,
+    crop: Option<CropRect>,
+) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
+    match crop {
+        Some(r) => image::imageops::crop_imm(&image, r.x, r.y, r.width, r.height).to_image(),
+        None => image,
+    }
+}
+
+// Compute 64x64 feature from the Y plane (within `crop`, if given) into `feat`, returning its
+// L2 norm. Requires an 8-bit luma plane (see `has_8bit_luma_plane`); `FeatureExtractor`
+// converts other formats to GRAY8 first.
+fn compute_feature_from_y(
+    frame: &FfmpegVideo,
+    crop: Option<CropRect>,
+    feat: &mut Vec<f32>,
+) -> Result<f32> {
+    // Get Y plane geometry
+    let full = CropRect { x: 0, y: 0, width: frame.width(), height: frame.height() };
+    let region = crop.unwrap_or(full);
+    let (cx, cy) = (region.x as usize, region.y as usize);
+    let stride = frame.stride(0);
+    let y_plane = &frame.data(0)[cy * stride + cx..];
+    Ok(downscale_luma(y_plane, stride, region.width as usize, region.height as usize, feat))
+}
+
+// Bilinear downscale of a `w`x`h` luma plane, `stride` bytes to a row, to the 64x64 feature
+// in `feat`. Returns its L2 norm.
+fn downscale_luma(y_plane: &[u8], stride: usize, w: usize, h: usize, feat: &mut Vec<f32>) -> f32 {
+    const OUT: usize = 64;
+    const LANES: usize = 8;
+    let scale_x = (w as f32) / (OUT as f32);
+    let scale_y = (h as f32) / (OUT as f32);
+
+    // Horizontal taps are identical for every output row, so compute them once
+    let mut x0s = [0usize; OUT];
+    let mut x1s = [0usize; OUT];
+    let mut wx0s = [0f32; OUT];
+    let mut wx1s = [0f32; OUT];
+    for ox in 0..OUT {
+        let src_x = (ox as f32 + 0.5) * scale_x - 0.5;
+        let x0 = src_x.floor().max(0.0) as isize;
+        let x1 = (x0 + 1).min((w as isize) - 1);
+        let wx1 = (src_x - x0 as f32).clamp(0.0, 1.0);
+        x0s[ox] = x0 as usize;
+        x1s[ox] = x1 as usize;
+        wx1s[ox] = wx1;
+        wx0s[ox] = 1.0 - wx1;
+    }
+
+    feat.clear();
+    feat.reserve(OUT * OUT);
+    let mut sumsq: f32 = 0.0;
+    for oy in 0..OUT {
+        let src_y = (oy as f32 + 0.5) * scale_y - 0.5;
+        let y0 = src_y.floor().max(0.0) as isize;
+        let y1 = (y0 + 1).min((h as isize) - 1);
+        let wy1 = (src_y - y0 as f32).clamp(0.0, 1.0);
+        let wy0 = 1.0 - wy1;
+        let row0 = &y_plane[(y0 as usize) * stride..];
+        let row1 = &y_plane[(y1 as usize) * stride..];
+        let wy0v = f32x8::splat(wy0);
+        let wy1v = f32x8::splat(wy1);
+        let inv255 = f32x8::splat(255.0);
+
+        // 8 output pixels per step; the gathers stay scalar, the lerp math is vectorized.
+        // Ops are the same mul/add sequence as the scalar version, so values are bit-identical.
+        for ox in (0..OUT).step_by(LANES) {
+            let mut y00 = [0f32; LANES];
+            let mut y01 = [0f32; LANES];
+            let mut y10 = [0f32; LANES];
+            let mut y11 = [0f32; LANES];
+            for lane in 0..LANES {
+                let (x0, x1) = (x0s[ox + lane], x1s[ox + lane]);
+                y00[lane] = row0[x0] as f32;
+                y01[lane] = row0[x1] as f32;
+                y10[lane] = row1[x0] as f32;
+                y11[lane] = row1[x1] as f32;
+            }
+            let wx0 = f32x8::new(wx0s[ox..ox + LANES].try_into().unwrap());
+            let wx1 = f32x8::new(wx1s[ox..ox + LANES].try_into().unwrap());
+
+            let y0i = f32x8::new(y00) * wx0 + f32x8::new(y01) * wx1;
+            let y1i = f32x8::new(y10) * wx0 + f32x8::new(y11) * wx1;
+            let yv = (y0i * wy0v + y1i * wy1v) / inv255;
+            for v in yv.to_array() {
+                feat.push(v);
+                sumsq += v * v;
+            }
+        }
+    }
+    sumsq.sqrt()
+}
 
-    // Bilinear downscale to 64x64
+// `downscale_luma` one pixel at a time, which the SIMD version must match bit for bit
+fn downscale_luma_scalar(
+    y_plane: &[u8],
+    stride: usize,
+    w: usize,
+    h: usize,
+    feat: &mut Vec<f32>,
+) -> f32 {
     const OUT: usize = 64;
     let scale_x = (w as f32) / (OUT as f32);
     let scale_y = (h as f32) / (OUT as f32);
 
-    let mut feat = Vec::with_capacity(OUT * OUT);
+    feat.clear();
+    feat.reserve(OUT * OUT);
     let mut sumsq: f32 = 0.0;
     for oy in 0..OUT {
         let src_y = (oy as f32 + 0.5) * scale_y - 0.5;
@@ -496,15 +6104,10 @@ fn compute_feature_from_y(frame: &FfmpegVideo) -> Result<(Vec<f32>, f32)> {
             let wx1 = (src_x - x0 as f32).clamp(0.0, 1.0);
             let wx0 = 1.0 - wx1;
 
-            let idx00 = (y0 as usize) * stride + (x0 as usize);
-            let idx01 = (y0 as usize) * stride + (x1 as usize);
-            let idx10 = (y1 as usize) * stride + (x0 as usize);
-            let idx11 = (y1 as usize) * stride + (x1 as usize);
-
-            let y00 = y_plane[idx00] as f32;
-            let y01 = y_plane[idx01] as f32;
-            let y10 = y_plane[idx10] as f32;
-            let y11 = y_plane[idx11] as f32;
+            let y00 = y_plane[(y0 as usize) * stride + (x0 as usize)] as f32;
+            let y01 = y_plane[(y0 as usize) * stride + (x1 as usize)] as f32;
+            let y10 = y_plane[(y1 as usize) * stride + (x0 as usize)] as f32;
+            let y11 = y_plane[(y1 as usize) * stride + (x1 as usize)] as f32;
 
             let y0i = y00 * wx0 + y01 * wx1;
             let y1i = y10 * wx0 + y11 * wx1;
@@ -513,8 +6116,204 @@ fn compute_feature_from_y(frame: &FfmpegVideo) -> Result<(Vec<f32>, f32)> {
             sumsq += yv * yv;
         }
     }
-    let l2 = sumsq.sqrt();
-    Ok((feat, l2))
+    sumsq.sqrt()
+}
+
+// 32x32x3 RGB feature (3072D): swscale converts and downsamples in one pass, so the
+// full-resolution RGB frame is never materialized
+fn compute_feature_rgb(
+    scaler: &mut Option<FfmpegScaler>,
+    frame: &FfmpegVideo,
+    feat: &mut Vec<f32>,
+) -> Result<f32> {
+    const OUT: u32 = 32;
+    ensure_scaler_to(scaler, frame.format(), frame.width(), frame.height(), OUT, OUT)?;
+    let mut rgb = FfmpegVideo::empty();
+    scaler.as_mut().unwrap().run(frame, &mut rgb)?;
+    let stride = rgb.stride(0);
+    let data = rgb.data(0);
+    let row_len = OUT as usize * 3;
+
+    feat.clear();
+    feat.reserve(row_len * OUT as usize);
+    let mut sumsq: f32 = 0.0;
+    for y in 0..OUT as usize {
+        for &b in &data[y * stride..y * stride + row_len] {
+            let v = b as f32 / 255.0;
+            feat.push(v);
+            sumsq += v * v;
+        }
+    }
+    Ok(sumsq.sqrt())
+}
+
+// 512D joint RGB histogram (8 bins per channel) of a 64x64 downscale, normalized to sum 1
+fn compute_histogram_feature(
+    scaler: &mut Option<FfmpegScaler>,
+    frame: &FfmpegVideo,
+    feat: &mut Vec<f32>,
+) -> Result<f32> {
+    const OUT: u32 = 64;
+    const BINS: usize = 8;
+    ensure_scaler_to(scaler, frame.format(), frame.width(), frame.height(), OUT, OUT)?;
+    let mut rgb = FfmpegVideo::empty();
+    scaler.as_mut().unwrap().run(frame, &mut rgb)?;
+    let stride = rgb.stride(0);
+    let data = rgb.data(0);
+    let row_len = OUT as usize * 3;
+
+    feat.clear();
+    feat.resize(BINS * BINS * BINS, 0.0);
+    let bin = |b: u8| b as usize * BINS / 256;
+    for y in 0..OUT as usize {
+        for px in data[y * stride..y * stride + row_len].chunks_exact(3) {
+            feat[(bin(px[0]) * BINS + bin(px[1])) * BINS + bin(px[2])] += 1.0;
+        }
+    }
+    let pixels = (OUT * OUT) as f32;
+    let mut sumsq: f32 = 0.0;
+    for v in feat.iter_mut() {
+        *v /= pixels;
+        sumsq += *v * *v;
+    }
+    Ok(sumsq.sqrt())
+}
+
+/// Cells per side of the fingerprint grid; one bit per cell.
+const FINGERPRINT_GRID: usize = 16;
+
+// Brightness layout of the selected frames, summed on a coarse grid. Each frame is
+// normalized by its total so bright frames don't dominate.
+#[derive(Default)]
+struct FingerprintAccumulator {
+    cells: Vec<f64>,
+    frames: u64,
+}
+
+impl FingerprintAccumulator {
+    fn add(&mut self, feat: &[f32]) {
+        // 64x64 luma or 32x32 interleaved RGB (channels averaged)
+        let (side, channels) = match feat.len() {
+            4096 => (64, 1),
+            3072 => (32, 3),
+            _ => return,
+        };
+        let per_cell = side / FINGERPRINT_GRID;
+        let mut cells = [0f64; FINGERPRINT_GRID * FINGERPRINT_GRID];
+        for (i, pixel) in feat.chunks(channels).enumerate() {
+            let (x, y) = (i % side, i / side);
+            let value: f32 = pixel.iter().sum::<f32>() / channels as f32;
+            cells[(y / per_cell) * FINGERPRINT_GRID + x / per_cell] += value as f64;
+        }
+        let total: f64 = cells.iter().sum();
+        if total <= 0.0 {
+            return;
+        }
+        self.cells.resize(cells.len(), 0.0);
+        for (sum, cell) in self.cells.iter_mut().zip(cells) {
+            *sum += cell / total;
+        }
+        self.frames += 1;
+    }
+
+    // One bit per cell: brighter than the median cell. Re-encodes shift cell values a
+    // little but rarely move them across the median.
+    fn finish(&self) -> Option<String> {
+        if self.frames == 0 {
+            return None;
+        }
+        let mut sorted = self.cells.clone();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+        let median = sorted[sorted.len() / 2];
+        let bytes: Vec<u8> = self
+            .cells
+            .chunks(8)
+            .map(|bits| {
+                bits.iter()
+                    .fold(0u8, |byte, &cell| (byte << 1) | u8::from(cell > median))
+            })
+            .collect();
+        Some(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+    }
+}
+
+// 64-bit difference hash of a 64x64 luma or 32x32 RGB feature: the feature averaged down
+// to 9x8 cells, one bit per horizontal neighbour pair that gets brighter. `None` for
+// histogram features, which have no layout.
+fn dhash(feat: &[f32]) -> Option<u64> {
+    let (side, channels) = match feat.len() {
+        4096 => (64, 1),
+        3072 => (32, 3),
+        _ => return None,
+    };
+    let mut sums = [[0f32; 9]; 8];
+    let mut counts = [[0u32; 9]; 8];
+    for (i, pixel) in feat.chunks(channels).enumerate() {
+        let (x, y) = (i % side * 9 / side, i / side * 8 / side);
+        sums[y][x] += pixel.iter().sum::<f32>();
+        counts[y][x] += 1;
+    }
+    let mut hash = 0u64;
+    for (row, count) in sums.iter().zip(&counts) {
+        for x in 0..8 {
+            let (left, right) = (row[x] / count[x] as f32, row[x + 1] / count[x + 1] as f32);
+            hash = (hash << 1) | u64::from(right > left);
+        }
+    }
+    Some(hash)
+}
+
+/// dHashes at most this many bits apart are taken for the same image.
+const DHASH_MAX_DISTANCE: u32 = 4;
+
+/// Most descriptions kept for `ProcessOptions::reuse_descriptions`; the oldest go first.
+const DESCRIPTION_CACHE_CAPACITY: usize = 4096;
+
+// Described frames by perceptual hash, scoped by `FrameJobContext::description_key`
+static DESCRIPTION_CACHE: std::sync::Mutex<VecDeque<(Arc<String>, u64, FrameDescription)>> =
+    std::sync::Mutex::new(VecDeque::new());
+
+// Description of the closest cached frame within `DHASH_MAX_DISTANCE` of `hash`
+fn cached_description(key: &str, hash: u64) -> Option<FrameDescription> {
+    let cache = DESCRIPTION_CACHE.lock().unwrap();
+    cache
+        .iter()
+        .filter(|(entry_key, _, _)| entry_key.as_str() == key)
+        .map(|(_, entry_hash, described)| ((entry_hash ^ hash).count_ones(), described))
+        .filter(|(distance, _)| *distance <= DHASH_MAX_DISTANCE)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, described)| described.clone())
+}
+
+fn remember_description(key: Arc<String>, hash: u64, described: &FrameDescription) {
+    let mut cache = DESCRIPTION_CACHE.lock().unwrap();
+    if cache.len() >= DESCRIPTION_CACHE_CAPACITY {
+        cache.pop_front();
+    }
+    cache.push_back((key, hash, described.clone()));
+}
+
+/// Hamming distance between two `VideoAnalysis::fingerprint`s (0..=256); re-encodes of one
+/// video typically differ in a few bits, unrelated videos in about half. `None` if either
+/// isn't a fingerprint. Fingerprints from different `FeatureMode`s aren't comparable.
+pub fn fingerprint_distance(a: &str, b: &str) -> Option<u32> {
+    let expected = FINGERPRINT_GRID * FINGERPRINT_GRID / 4;
+    if a.len() != expected || b.len() != expected {
+        return None;
+    }
+    let nibbles = |s: &str| s.chars().map(|c| c.to_digit(16)).collect::<Option<Vec<u32>>>();
+    let (a, b) = (nibbles(a)?, nibbles(b)?);
+    Some(a.iter().zip(&b).map(|(x, y)| (x ^ y).count_ones()).sum())
+}
+
+// Sum of absolute differences per element, so thresholds don't depend on the feature size;
+// 0 when the features can't be compared
+fn mean_abs_diff(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() || a.is_empty() {
+        return 0.0;
+    }
+    let sad: f32 = a.iter().zip(b).map(|(x, y)| (x - y).abs()).sum();
+    sad / a.len() as f32
 }
 
 // Cosine similarity over precomputed feature vectors and norms
@@ -522,9 +6321,813 @@ fn cosine_similarity_feats(ref_vec: &[f32], ref_l2: f32, v: &[f32], l2: f32) ->
     if ref_l2 == 0.0 || l2 == 0.0 || ref_vec.len() != v.len() || ref_vec.is_empty() {
         return 0.0;
     }
+    dot_f32(ref_vec, v) / (ref_l2 * l2)
+}
+
+// 8-lane SIMD dot product with a scalar tail. `wide` itself falls back to scalar code on
+// targets without SSE/AVX/NEON. Summation order differs from a plain loop, so results can
+// differ from `dot_f32_scalar` in the last few ULPs.
+fn dot_f32(a: &[f32], b: &[f32]) -> f32 {
+    let n = a.len().min(b.len());
+    let split = n - n % 8;
+    let mut acc = f32x8::splat(0.0);
+    for (ca, cb) in a[..split].chunks_exact(8).zip(b[..split].chunks_exact(8)) {
+        acc += f32x8::new(ca.try_into().unwrap()) * f32x8::new(cb.try_into().unwrap());
+    }
+    acc.reduce_add() + dot_f32_scalar(&a[split..n], &b[split..n])
+}
+
+fn dot_f32_scalar(a: &[f32], b: &[f32]) -> f32 {
     let mut dot: f32 = 0.0;
-    for (a, b) in ref_vec.iter().zip(v.iter()) {
-        dot += a * b;
+    for (x, y) in a.iter().zip(b.iter()) {
+        dot += x * y;
+    }
+    dot
+}
+
+/// Selection kernels for `benches/`; not a stable API.
+#[doc(hidden)]
+pub mod bench_api {
+    pub fn dot_f32(a: &[f32], b: &[f32]) -> f32 {
+        super::dot_f32(a, b)
+    }
+
+    pub fn dot_f32_scalar(a: &[f32], b: &[f32]) -> f32 {
+        super::dot_f32_scalar(a, b)
+    }
+
+    /// Luma feature of a `w`x`h` plane with `stride` bytes to a row, into `feat`; returns
+    /// its norm
+    pub fn downscale_luma(
+        plane: &[u8],
+        stride: usize,
+        w: usize,
+        h: usize,
+        feat: &mut Vec<f32>,
+    ) -> f32 {
+        super::downscale_luma(plane, stride, w, h, feat)
+    }
+
+    pub fn downscale_luma_scalar(
+        plane: &[u8],
+        stride: usize,
+        w: usize,
+        h: usize,
+        feat: &mut Vec<f32>,
+    ) -> f32 {
+        super::downscale_luma_scalar(plane, stride, w, h, feat)
+    }
+
+    /// Decodes every video frame of `path` the way `process_video` does and counts them
+    pub fn decode_frames(path: &std::path::Path, skip: super::DecodeSkip) -> anyhow::Result<u64> {
+        use super::{ff_input, ffmpeg, FfmpegMediaType, FfmpegVideo};
+        ffmpeg::init()?;
+        let mut ictx = ff_input(&path)?;
+        let input_stream = ictx
+            .streams()
+            .best(FfmpegMediaType::Video)
+            .ok_or_else(|| anyhow::anyhow!("No video stream found"))?;
+        let stream_index = input_stream.index();
+        let context_decoder =
+            ffmpeg::codec::context::Context::from_parameters(input_stream.parameters())?;
+        let mut decoder = context_decoder.decoder().video()?;
+        if let Some(discard) = skip.discard() {
+            decoder.skip_frame(discard);
+        }
+        let mut decoded = FfmpegVideo::empty();
+        let mut frames = 0;
+        for (stream, packet) in ictx.packets() {
+            if stream.index() == stream_index {
+                decoder.send_packet(&packet)?;
+                while decoder.receive_frame(&mut decoded).is_ok() {
+                    frames += 1;
+                }
+            }
+        }
+        decoder.send_eof()?;
+        while decoder.receive_frame(&mut decoded).is_ok() {
+            frames += 1;
+        }
+        Ok(frames)
+    }
+
+    /// `FeatureExtractor`'s pool of feature vectors, computing luma features from planes
+    pub struct FeaturePool(super::FeatureExtractor);
+
+    impl Default for FeaturePool {
+        fn default() -> Self {
+            Self(super::FeatureExtractor::new(super::FeatureMode::Luma))
+        }
+    }
+
+    impl FeaturePool {
+        /// Feature and norm of a `w`x`h` plane, in a pooled vector
+        pub fn compute(
+            &mut self,
+            plane: &[u8],
+            stride: usize,
+            w: usize,
+            h: usize,
+        ) -> (Vec<f32>, f32) {
+            let mut feat = self.0.take();
+            let l2 = super::downscale_luma(plane, stride, w, h, &mut feat);
+            (feat, l2)
+        }
+
+        pub fn copy_of(&mut self, feat: &[f32]) -> Vec<f32> {
+            self.0.copy_of(feat)
+        }
+
+        pub fn recycle(&mut self, feat: Vec<f32>) {
+            self.0.recycle(feat)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn batch_entry(index: usize, description: &str) -> serde_json::Value {
+        serde_json::json!({ "index": index, "description": description, "confidence": 0.5 })
+    }
+
+    fn description_of(reply: &Option<String>) -> String {
+        parse_confidence(reply.clone().unwrap()).0
+    }
+
+    // Deterministic values in -1..1
+    fn pseudo_random(len: usize, seed: u32) -> Vec<f32> {
+        let mut state = seed.wrapping_mul(2_654_435_761).max(1);
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as f32 / u32::MAX as f32 * 2.0 - 1.0
+            })
+            .collect()
+    }
+
+    // Echoes prompts, recording the most calls it saw at once
+    #[derive(Debug, Default)]
+    struct CountingSummarizer {
+        in_flight: std::sync::atomic::AtomicUsize,
+        max_in_flight: std::sync::atomic::AtomicUsize,
+    }
+
+    #[async_trait::async_trait]
+    impl Summarizer for CountingSummarizer {
+        async fn summarize(&self, prompt: &str) -> Result<String> {
+            use std::sync::atomic::Ordering::SeqCst;
+            let now = self.in_flight.fetch_add(1, SeqCst) + 1;
+            self.max_in_flight.fetch_max(now, SeqCst);
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            self.in_flight.fetch_sub(1, SeqCst);
+            Ok(prompt.to_uppercase())
+        }
+    }
+
+    #[tokio::test]
+    async fn ask_each_waits_for_the_tenants_scheduler_permits() {
+        let counting = Arc::new(CountingSummarizer::default());
+        let summarizer: Arc<dyn Summarizer> = Arc::new(FairSummarizer {
+            inner: counting.clone(),
+            scheduler: FairScheduler::new(2),
+            tenant: "tenant".to_string(),
+        });
+        let prompts = ["a", "b", "c", "d", "e"].map(str::to_string).to_vec();
+        let replies = ask_each(&summarizer, prompts).await.unwrap();
+        assert_eq!(replies, ["A", "B", "C", "D", "E"]);
+        assert_eq!(counting.max_in_flight.load(std::sync::atomic::Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn non_latin_records_round_trip_through_json() {
+        let record = FrameRecord {
+            frame_id: 3,
+            timestamp: 1.5,
+            description: "一位女士打开了门。 Женщина открывает дверь. امرأة تفتح الباب.".into(),
+            alt_text: Some("ドアを開ける女性".into()),
+            labels: vec!["الباب".into()],
+            model: "gemini".into(),
+            ..Default::default()
+        };
+        let json = serde_json::to_string(&record).unwrap();
+        assert!(json.contains("一位女士打开了门") && json.contains("امرأة تفتح الباب"));
+        let parsed: FrameRecord = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.description, record.description);
+        assert_eq!(parsed.alt_text, record.alt_text);
+        assert_eq!(parsed.labels, record.labels);
+    }
+
+    #[test]
+    fn language_tags_are_checked_for_shape() {
+        for tag in ["ja", "ar", "zh-Hant", "pt-BR", "es-419"] {
+            assert!(is_language_tag(tag), "{}", tag);
+        }
+        for tag in ["", "j", "日本語", "en_US", "pt-", "english language"] {
+            assert!(!is_language_tag(tag), "{}", tag);
+        }
+    }
+
+    #[test]
+    fn simd_dot_product_agrees_with_the_scalar_loop() {
+        for len in (0..=40).chain([63, 64, 65, 4095, 4096, 4099]) {
+            let (a, b) = (pseudo_random(len, 1), pseudo_random(len, 2));
+            let (simd, scalar) = (dot_f32(&a, &b), dot_f32_scalar(&a, &b));
+            let magnitude: f32 = a.iter().zip(&b).map(|(x, y)| (x * y).abs()).sum();
+            assert!(
+                (simd - scalar).abs() <= magnitude * 1e-5,
+                "len {}: {} vs {}",
+                len,
+                simd,
+                scalar
+            );
+        }
+        // Lengths past the shorter slice are ignored, as in the scalar loop
+        let (a, b) = (pseudo_random(13, 3), pseudo_random(9, 4));
+        assert!((dot_f32(&a, &b) - dot_f32_scalar(&a, &b)).abs() < 1e-5);
+    }
+
+    #[test]
+    fn simd_downscale_matches_the_scalar_version() {
+        // Odd sizes, a padded stride and sizes below 64 exercise the edge taps
+        let sizes = [(1920, 1080, 1920), (1280, 720, 1344), (853, 479, 864), (48, 20, 64)];
+        for (w, h, stride) in sizes {
+            let noise = pseudo_random(stride * h, w as u32);
+            let plane: Vec<u8> = noise.iter().map(|v| (v.abs() * 255.0) as u8).collect();
+            let (mut simd, mut scalar) = (Vec::new(), Vec::new());
+            let simd_l2 = downscale_luma(&plane, stride, w, h, &mut simd);
+            let scalar_l2 = downscale_luma_scalar(&plane, stride, w, h, &mut scalar);
+            assert_eq!(simd.len(), 64 * 64);
+            assert_eq!(simd, scalar, "{}x{}", w, h);
+            assert_eq!(simd_l2, scalar_l2);
+        }
+    }
+
+    #[test]
+    fn resolution_changes_are_reported_once() {
+        let mut geometry = FrameGeometry::new(false);
+        assert_eq!(geometry.resize((1280, 720)), None);
+        assert_eq!(geometry.resize((1280, 720)), None);
+        assert_eq!(geometry.resize((1920, 1080)), Some((1280, 720)));
+        assert_eq!(geometry.resize((1920, 1080)), None);
+        assert_eq!(geometry.resize((1280, 720)), Some((1920, 1080)));
+    }
+
+    // Luma plane of a `w`x`h` frame, lit only inside `content`
+    fn letterboxed(w: u32, h: u32, content: CropRect) -> Vec<u8> {
+        let mut plane = vec![16; (w * h) as usize];
+        for y in content.y..content.y + content.height {
+            let row = (y * w) as usize;
+            plane[row + content.x as usize..row + (content.x + content.width) as usize].fill(200);
+        }
+        plane
+    }
+
+    #[test]
+    fn black_bars_find_the_lit_area() {
+        let content = CropRect { x: 0, y: 90, width: 1280, height: 540 };
+        let plane = letterboxed(1280, 720, content);
+        assert_eq!(black_bars(&plane, 1280, 1280, 720), Some(content));
+        assert_eq!(black_bars(&vec![16; 1280 * 720], 1280, 1280, 720), None);
+    }
+
+    #[test]
+    fn crop_is_detected_again_after_a_resolution_change() {
+        let mut geometry = FrameGeometry::new(true);
+        assert_eq!(geometry.resize((1280, 720)), None);
+        let bars = CropRect { x: 0, y: 90, width: 1280, height: 540 };
+        assert_eq!(geometry.settle_crop(Some(bars)), Some(Some(bars)));
+        assert!(geometry.crop_settled());
+        assert_eq!(geometry.resize((1280, 720)), None);
+        assert!(geometry.crop_settled());
+
+        // The 1080p segment starts with a fade-in, then fills the frame
+        assert_eq!(geometry.resize((1920, 1080)), Some((1280, 720)));
+        assert!(!geometry.crop_settled());
+        assert_eq!(geometry.settle_crop(None), None);
+        assert!(!geometry.crop_settled());
+        let full = CropRect { x: 0, y: 0, width: 1920, height: 1080 };
+        let plane = letterboxed(1920, 1080, full);
+        assert_eq!(geometry.settle_crop(black_bars(&plane, 1920, 1920, 1080)), Some(None));
+        assert!(geometry.crop_settled());
+    }
+
+    #[test]
+    fn resolution_changes_without_auto_crop_keep_the_crop_settled() {
+        let mut geometry = FrameGeometry::new(false);
+        assert!(geometry.crop_settled());
+        geometry.resize((1280, 720));
+        assert_eq!(geometry.resize((1920, 1080)), Some((1280, 720)));
+        assert!(geometry.crop_settled());
+    }
+
+    #[test]
+    fn decode_skip_only_discards_when_asked() {
+        assert_eq!(ProcessOptions::default().decode_skip, DecodeSkip::Off);
+        assert_eq!(DecodeSkip::Off.discard(), None);
+        let non_reference = DecodeSkip::NonReference.discard();
+        assert_eq!(non_reference, Some(ffmpeg::codec::Discard::NonReference));
+        let parsed: DecodeSkip = serde_json::from_str("\"non_reference\"").unwrap();
+        assert_eq!(parsed, DecodeSkip::NonReference);
+        assert!(serde_json::from_str::<DecodeSkip>("\"keyframes\"").is_err());
+    }
+
+    #[test]
+    fn last_frame_is_queued_unless_a_guard_applies() {
+        let on = ProcessOptions::default();
+        assert!(on.include_last_frame, "include_last_frame is on by default");
+        let motion = ProcessOptions { motion_threshold: Some(0.1), ..Default::default() };
+        let off = ProcessOptions { include_last_frame: false, ..Default::default() };
+        // The other selection options leave the final frame alone
+        let fixed = ProcessOptions { selection_mode: SelectionMode::Fixed, ..Default::default() };
+        let keyframes = ProcessOptions { keyframes_only: true, ..Default::default() };
+        let similar = ProcessOptions { min_dissimilarity: Some(0.2), ..Default::default() };
+        let dry = ProcessOptions { dry_run: true, ..Default::default() };
+        // (options, stopped, frames seen, last frame already queued, queued)
+        let cases = [
+            (&on, false, 120, false, true),
+            (&on, false, 2, false, true),
+            (&on, true, 120, false, false),
+            (&on, false, 120, true, false),
+            (&on, false, 1, false, false),
+            (&on, false, 0, false, false),
+            (&on, true, 120, true, false),
+            (&motion, false, 120, false, false),
+            (&off, false, 120, false, false),
+            (&fixed, false, 120, false, true),
+            (&keyframes, false, 120, false, true),
+            (&similar, false, 120, false, true),
+            (&dry, false, 120, false, true),
+        ];
+        for (i, (options, stopped, seen, queued, expected)) in cases.into_iter().enumerate() {
+            assert_eq!(queues_last_frame(options, stopped, seen, queued), expected, "case {}", i);
+        }
+    }
+
+    // Luma plane of a `w`x`h` gradient, so features of different planes differ
+    fn gradient(w: usize, h: usize, shift: usize) -> Vec<u8> {
+        (0..w * h).map(|i| ((i % w + i / w + shift) % 256) as u8).collect()
+    }
+
+    #[test]
+    fn pooled_features_match_fresh_ones() {
+        let mut pool = FeatureExtractor::new(FeatureMode::Luma);
+        let (first, second) = (gradient(320, 180, 0), gradient(640, 360, 90));
+        let mut stale = pool.take();
+        downscale_luma(&first, 320, 320, 180, &mut stale);
+        let reused = stale.as_ptr();
+        pool.recycle(stale);
+
+        // A recycled vector is handed out again, and its old contents don't leak through
+        let mut feat = pool.take();
+        assert_eq!(feat.as_ptr(), reused);
+        let l2 = downscale_luma(&second, 640, 640, 360, &mut feat);
+        let mut fresh = Vec::new();
+        assert_eq!(downscale_luma(&second, 640, 640, 360, &mut fresh), l2);
+        assert_eq!(feat, fresh);
+
+        let copy = pool.copy_of(&feat);
+        assert_eq!(copy, feat);
+        assert_ne!(copy.as_ptr(), feat.as_ptr());
+    }
+
+    #[test]
+    fn feature_pool_keeps_a_few_allocated_vectors() {
+        let mut pool = FeatureExtractor::new(FeatureMode::Luma);
+        // Nothing to reuse in a vector that never allocated
+        pool.recycle(Vec::new());
+        assert!(pool.free.is_empty());
+        for _ in 0..FeatureExtractor::MAX_FREE + 2 {
+            pool.recycle(vec![0.0; 4096]);
+        }
+        assert_eq!(pool.free.len(), FeatureExtractor::MAX_FREE);
+        for _ in 0..FeatureExtractor::MAX_FREE {
+            assert!(pool.take().capacity() >= 4096);
+        }
+        assert_eq!(pool.take().capacity(), 0);
+    }
+
+    fn cue(frame_id: u64, timestamp: f64, description: &str) -> FrameRecord {
+        FrameRecord {
+            frame_id,
+            timestamp,
+            description: description.to_string(),
+            path: format!("data/video_frame_{:03}.jpg", frame_id),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn cue_times_round_to_milliseconds() {
+        assert_eq!(cue_time(0.0, '.'), "00:00:00.000");
+        assert_eq!(cue_time(-1.0, ','), "00:00:00,000");
+        assert_eq!(cue_time(61.2345, ','), "00:01:01,235");
+        assert_eq!(cue_time(3725.5, '.'), "01:02:05.500");
+    }
+
+    #[test]
+    fn webvtt_cues_escape_markup_and_run_until_the_next_frame() {
+        let records = [cue(3, 0.0, "a <b> & c\n\nd"), cue(7, 2.5, "arrow --> here")];
+        let vtt = to_webvtt(&records);
+        let end = cue_time(2.5 + DEFAULT_SAMPLE_INTERVAL_SECS, '.');
+        assert_eq!(
+            vtt,
+            format!(
+                "WEBVTT\n\n3\n00:00:00.000 --> 00:00:02.500\na &lt;b&gt; &amp; c d\n\n\
+                 7\n00:00:02.500 --> {}\narrow --&gt; here\n",
+                end
+            )
+        );
+    }
+
+    #[test]
+    fn srt_cues_are_numbered_and_never_contain_a_timing_arrow() {
+        let records = [cue(3, 1.0, "left --> right"), cue(7, 1.0, "same\ntime")];
+        let srt = to_srt(&records);
+        let end = cue_time(1.0 + DEFAULT_SAMPLE_INTERVAL_SECS, ',');
+        assert_eq!(
+            srt,
+            format!(
+                "1\n00:00:01,000 --> {end}\nleft -> right\n\n\
+                 2\n00:00:01,000 --> {end}\nsame time\n\n"
+            )
+        );
+        let arrows = srt.lines().filter(|line| line.contains("-->")).count();
+        assert_eq!(arrows, records.len());
+    }
+
+    #[tokio::test]
+    async fn csv_fields_are_quoted_when_needed() {
+        let records = [cue(1, 0.5, "plain"), cue(2, 1.25, "a, \"quoted\"\nline")];
+        let csv = records_to_csv(&records).await.unwrap();
+        assert_eq!(
+            csv,
+            "frame_id,timestamp,description,thumbnail_url\r\n\
+             1,0.500,plain,/data/video_frame_001.jpg\r\n\
+             2,1.250,\"a, \"\"quoted\"\"\nline\",/data/video_frame_002.jpg\r\n"
+        );
+    }
+
+    // `DESCRIPTION_CACHE` is global, and the eviction test would evict other tests' entries
+    static DESCRIPTION_CACHE_TEST: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+    fn described(description: &str) -> FrameDescription {
+        FrameDescription {
+            description: description.to_string(),
+            labels: Vec::new(),
+            alt_text: None,
+            model: "model".to_string(),
+            confidence: None,
+        }
+    }
+
+    fn description_key(tenant: Option<&str>, video_id: &str, scope: Option<&str>) -> String {
+        let gemini = GeminiConfig {
+            base_url: None,
+            auth: GeminiAuth::ApiKey,
+            api_key: "key".to_string(),
+        };
+        let options = ProcessOptions {
+            reuse_descriptions: true,
+            tenant: tenant.map(str::to_string),
+            description_cache_scope: scope.map(str::to_string),
+            ..Default::default()
+        };
+        let ctx = FrameJobContext::new(gemini, resolve_model(None), 1, video_id.into(), &options);
+        ctx.description_key.unwrap().to_string()
+    }
+
+    // (min, max) frames and calls for a 60s video sampled every second
+    fn estimate(options: ProcessOptions) -> (u64, u64, u64, u64) {
+        let options = ProcessOptions { sample_interval_secs: 1.0, ..options };
+        let estimate = estimate_selection(60.0, Some(12), 8, &options);
+        (
+            estimate.estimated_frames_min,
+            estimate.estimated_frames,
+            estimate.estimated_llm_calls_min,
+            estimate.estimated_llm_calls,
+        )
+    }
+
+    #[test]
+    fn estimates_follow_the_selection_mode() {
+        // First frame, one of each pair of the 60 sample points, last frame; plus a summary
+        assert_eq!(estimate(ProcessOptions::default()), (32, 32, 33, 33));
+        let fixed = ProcessOptions { selection_mode: SelectionMode::Fixed, ..Default::default() };
+        assert_eq!(estimate(fixed), (62, 62, 63, 63));
+        let off = ProcessOptions { include_last_frame: false, ..Default::default() };
+        assert_eq!(estimate(off), (31, 31, 32, 32));
+        let moments = ProcessOptions { moments: vec![10.0, 20.0], ..Default::default() };
+        assert_eq!(estimate(moments), (34, 34, 35, 35));
+    }
+
+    #[test]
+    fn content_dependent_selection_estimates_a_range() {
+        // Every pair winner may be a near-duplicate of the last described frame
+        let similar = ProcessOptions { min_dissimilarity: Some(0.1), ..Default::default() };
+        assert_eq!(estimate(similar), (2, 32, 3, 33));
+        // Fixed selection doesn't skip near-duplicates
+        let fixed = ProcessOptions {
+            min_dissimilarity: Some(0.1),
+            selection_mode: SelectionMode::Fixed,
+            ..Default::default()
+        };
+        assert_eq!(estimate(fixed), (62, 62, 63, 63));
+        // Anything from no motion to motion at every sample point, and no last frame
+        let motion = ProcessOptions { motion_threshold: Some(0.1), ..Default::default() };
+        assert_eq!(estimate(motion), (0, 60, 1, 61));
+    }
+
+    #[test]
+    fn keyframes_only_estimates_every_keyframe() {
+        let keyframes = ProcessOptions { keyframes_only: true, ..Default::default() };
+        assert_eq!(estimate(keyframes.clone()), (12, 13, 13, 14));
+        let capped = ProcessOptions { max_frames: Some(5), ..keyframes };
+        assert_eq!(estimate(capped), (5, 5, 6, 6));
+    }
+
+    #[test]
+    fn frame_bounds_move_the_estimated_interval() {
+        let max = ProcessOptions { max_frames: Some(10), ..Default::default() };
+        assert_eq!(estimate(max), (10, 10, 11, 11));
+        let min = ProcessOptions { min_frames: Some(50), ..Default::default() };
+        assert_eq!(estimate(min), (50, 50, 51, 51));
+        let options = ProcessOptions { max_frames: Some(10), ..Default::default() };
+        let estimate = estimate_selection(60.0, None, 8, &options);
+        assert_eq!(estimate.sample_interval_secs, 60.0 / 16.0);
+    }
+
+    #[test]
+    fn batches_and_dry_runs_cut_the_estimated_calls() {
+        let batched = ProcessOptions { describe_batch_size: Some(4), ..Default::default() };
+        assert_eq!(estimate(batched), (32, 32, 9, 9));
+        let dry = ProcessOptions { dry_run: true, ..Default::default() };
+        assert_eq!(estimate(dry), (32, 32, 0, 0));
+    }
+
+    #[test]
+    fn batching_keeps_the_configured_concurrency() {
+        let gemini = GeminiConfig {
+            base_url: None,
+            auth: GeminiAuth::ApiKey,
+            api_key: "key".to_string(),
+        };
+        let batch = |size, fallback_model: Option<&str>| {
+            let options = ProcessOptions {
+                describe_batch_size: Some(size),
+                fallback_model: fallback_model.map(str::to_string),
+                ..Default::default()
+            };
+            let model = resolve_model(None);
+            let ctx = FrameJobContext::new(gemini.clone(), model, 4, "v".into(), &options);
+            (ctx.semaphore.available_permits(), ctx.batcher.map(|batcher| batcher.size))
+        };
+        assert_eq!(batch(3, None), (4, Some(3)));
+        assert_eq!(batch(8, None), (4, Some(4)));
+        // Batch calls have no fallback, so frames go one by one
+        assert_eq!(batch(8, Some("gemini-2.5-flash-lite")), (4, None));
+    }
+
+    #[test]
+    fn memory_capped_concurrency_shrinks_batches() {
+        let gemini = GeminiConfig {
+            base_url: None,
+            auth: GeminiAuth::ApiKey,
+            api_key: "key".to_string(),
+        };
+        let capped = |effective| {
+            let options = ProcessOptions { describe_batch_size: Some(4), ..Default::default() };
+            let model = resolve_model(None);
+            let ctx = FrameJobContext::new(gemini.clone(), model, 8, "v".into(), &options)
+                .with_concurrency(effective);
+            (ctx.semaphore.available_permits(), ctx.batcher.map(|batcher| batcher.size))
+        };
+        assert_eq!(capped(8), (8, Some(4)));
+        assert_eq!(capped(3), (3, Some(3)));
+        assert_eq!(capped(1), (1, None));
+    }
+
+    #[test]
+    fn only_built_in_prompts_ask_for_confidence() {
+        let gemini = GeminiConfig {
+            base_url: None,
+            auth: GeminiAuth::ApiKey,
+            api_key: "key".to_string(),
+        };
+        let prompt = |frame_prompt: Option<&str>| {
+            let options = ProcessOptions {
+                frame_prompt: frame_prompt.map(str::to_string),
+                language: Some("French".to_string()),
+                ..Default::default()
+            };
+            let model = resolve_model(None);
+            let ctx = FrameJobContext::new(gemini.clone(), model, 1, "v".into(), &options);
+            ctx.prompt().to_string()
+        };
+        let built_in = prompt(None);
+        assert!(built_in.starts_with(frame_prompt(DescriptionMode::default())));
+        assert!(built_in.ends_with(CONFIDENCE_PROMPT));
+        let custom = prompt(Some("List the colours you see, one per line."));
+        assert!(custom.starts_with("List the colours you see, one per line.\n\n"));
+        assert!(!custom.contains(CONFIDENCE_PROMPT));
+        assert!(!FramePrompt::Inline(&custom).asks_for_json());
+    }
+
+    #[test]
+    fn cached_description_allows_a_few_differing_bits() {
+        let _guard = DESCRIPTION_CACHE_TEST.lock().unwrap();
+        let key = Arc::new("threshold".to_string());
+        remember_description(key.clone(), 0, &described("slide"));
+        let at_threshold = (1u64 << DHASH_MAX_DISTANCE) - 1;
+        assert_eq!(cached_description(&key, at_threshold).unwrap().description, "slide");
+        let past_threshold = (1u64 << (DHASH_MAX_DISTANCE + 1)) - 1;
+        assert!(cached_description(&key, past_threshold).is_none());
+    }
+
+    #[test]
+    fn cached_description_prefers_the_closest_hash() {
+        let _guard = DESCRIPTION_CACHE_TEST.lock().unwrap();
+        let key = Arc::new("closest".to_string());
+        remember_description(key.clone(), 0b111, &described("far"));
+        remember_description(key.clone(), 0b001, &described("near"));
+        assert_eq!(cached_description(&key, 0).unwrap().description, "near");
+    }
+
+    #[test]
+    fn cached_description_only_matches_its_key() {
+        let _guard = DESCRIPTION_CACHE_TEST.lock().unwrap();
+        remember_description(Arc::new("tenant a".to_string()), 42, &described("a"));
+        assert!(cached_description("tenant b", 42).is_none());
+        assert_eq!(cached_description("tenant a", 42).unwrap().description, "a");
+    }
+
+    #[test]
+    fn description_cache_evicts_the_oldest_entry() {
+        let _guard = DESCRIPTION_CACHE_TEST.lock().unwrap();
+        let (old, filler) = (Arc::new("oldest".to_string()), Arc::new("filler".to_string()));
+        remember_description(old.clone(), 7, &described("old"));
+        for hash in 0..DESCRIPTION_CACHE_CAPACITY as u64 - 1 {
+            remember_description(filler.clone(), hash << 8, &described("filler"));
+        }
+        assert!(cached_description(&old, 7).is_some());
+        remember_description(filler.clone(), u64::MAX, &described("filler"));
+        assert!(cached_description(&old, 7).is_none());
+        assert_eq!(DESCRIPTION_CACHE.lock().unwrap().len(), DESCRIPTION_CACHE_CAPACITY);
+    }
+
+    #[test]
+    fn description_key_is_scoped_by_tenant_and_video() {
+        let key = description_key(Some("a"), "video", None);
+        assert_eq!(key, description_key(Some("a"), "video", None));
+        assert_ne!(key, description_key(Some("b"), "video", None));
+        assert_ne!(key, description_key(None, "video", None));
+        assert_ne!(key, description_key(Some("a"), "other", None));
+        let shared = description_key(Some("a"), "video", Some("slides"));
+        assert_eq!(shared, description_key(Some("a"), "other", Some("slides")));
+        assert_ne!(shared, description_key(Some("b"), "other", Some("slides")));
+    }
+
+    #[test]
+    fn batch_reply_is_matched_by_one_based_index() {
+        let reply = serde_json::json!([batch_entry(2, "second"), batch_entry(1, "first")]);
+        let replies = split_batch_reply(&reply.to_string(), 2).unwrap();
+        assert_eq!(description_of(&replies[0]), "first");
+        assert_eq!(description_of(&replies[1]), "second");
+    }
+
+    #[test]
+    fn zero_based_batch_reply_is_rejected() {
+        let reply = serde_json::json!([batch_entry(0, "first"), batch_entry(1, "second")]);
+        assert!(split_batch_reply(&reply.to_string(), 2).is_err());
+    }
+
+    #[test]
+    fn batch_reply_with_duplicate_index_is_rejected() {
+        let reply = serde_json::json!([batch_entry(1, "first"), batch_entry(1, "again")]);
+        assert!(split_batch_reply(&reply.to_string(), 2).is_err());
+    }
+
+    #[test]
+    fn short_batch_reply_is_rejected() {
+        let reply = serde_json::json!([batch_entry(1, "first"), batch_entry(2, "second")]);
+        assert!(split_batch_reply(&reply.to_string(), 3).is_err());
+    }
+
+    #[test]
+    fn batch_reply_in_prose_and_fences_is_found() {
+        let array = serde_json::json!([batch_entry(1, "first"), batch_entry(2, "second")]);
+        let reply = format!("Here they are:\n```json\n{}\n```\nHope this helps.", array);
+        let replies = split_batch_reply(&reply, 2).unwrap();
+        assert_eq!(description_of(&replies[1]), "second");
+        assert_eq!(parse_confidence(replies[0].clone().unwrap()).1, Some(0.5));
+    }
+
+    #[test]
+    fn per_request_base_url_must_be_allowlisted() {
+        let endpoint = GeminiEndpoint {
+            base_url: Some("https://attacker.example.com/v1beta/".to_string()),
+            ..Default::default()
+        };
+        let e = GeminiConfig::with_override(&endpoint).unwrap_err();
+        assert!(format!("{:#}", e).contains("GEMINI_ALLOWED_BASE_URLS"));
+
+        let allowlist = "https://proxy.internal/v1beta/, http://127.0.0.1:9557/v1beta";
+        assert!(base_url_allowed("https://proxy.internal/v1beta", allowlist));
+        assert!(base_url_allowed("http://127.0.0.1:9557/v1beta/", allowlist));
+        assert!(!base_url_allowed("https://proxy.internal/", allowlist));
+        assert!(!base_url_allowed("https://proxy.internal/v1beta/", ""));
+        assert!(!base_url_allowed("", " , "));
+    }
+
+    #[test]
+    fn empty_batch_description_leaves_its_frame_for_a_single_call() {
+        let reply = serde_json::json!([batch_entry(1, " "), batch_entry(2, "second")]);
+        let replies = split_batch_reply(&reply.to_string(), 2).unwrap();
+        assert!(replies[0].is_none());
+        assert_eq!(description_of(&replies[1]), "second");
+    }
+
+    #[tokio::test]
+    async fn video_lock_refuses_ids_outside_data() {
+        for id in ["../escape", "..", "nested/id", ""] {
+            assert!(VideoLock::acquire(id).await.is_err(), "{}", id);
+        }
+        assert!(!std::path::Path::new("escape.lock").exists());
+    }
+
+    #[test]
+    fn video_urls_to_private_addresses_are_refused() {
+        let allowed = |url: &str, allowlist: &str| {
+            video_url_allowed(&reqwest::Url::parse(url).unwrap(), allowlist)
+        };
+        assert!(allowed("https://videos.example.com/clip.mp4", ""));
+        assert!(allowed("http://93.184.216.34/clip.mp4", ""));
+        for url in [
+            "http://127.0.0.1:8080/clip.mp4",
+            "http://169.254.169.254/latest/meta-data",
+            "http://10.0.0.5/clip.mp4",
+            "http://192.168.1.1/clip.mp4",
+            "http://100.64.0.1/clip.mp4",
+            "http://0.0.0.0/clip.mp4",
+            "http://[::1]/clip.mp4",
+            "http://[fd00::1]/clip.mp4",
+            "http://[fe80::1]/clip.mp4",
+            "http://[::ffff:127.0.0.1]/clip.mp4",
+        ] {
+            assert!(!allowed(url, ""), "{}", url);
+        }
+
+        let allowlist = "videos.example.com, 10.0.0.5";
+        assert!(allowed("https://VIDEOS.example.com/clip.mp4", allowlist));
+        assert!(allowed("http://10.0.0.5/clip.mp4", allowlist));
+        assert!(!allowed("https://other.example.com/clip.mp4", allowlist));
+    }
+
+    #[tokio::test]
+    async fn video_lock_excludes_until_dropped() {
+        let path = Path::new("data/video_lock_test.lock");
+        let lock = VideoLock::acquire("video_lock_test").await.unwrap();
+        assert!(VideoLock::try_lock(path).unwrap().is_none());
+        drop(lock);
+        assert!(!path.exists());
+        let again = VideoLock::try_lock(path).unwrap();
+        assert!(again.is_some());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn process_video_bytes_removes_its_temporary_file() {
+        let options = ProcessOptions::default();
+        let result = process_video_bytes(b"not a video".to_vec(), "bytestest", &options, None);
+        assert!(result.await.is_err());
+        let left = std::fs::read_dir("data")
+            .unwrap()
+            .filter_map(|entry| entry.ok())
+            .any(|entry| entry.file_name().to_string_lossy().ends_with(".bytestest"));
+        assert!(!left);
+    }
+
+    #[test]
+    fn max_frame_width_bounds_the_width_of_portrait_frames() {
+        let portrait: RgbFrame = ImageBuffer::new(1080, 1920);
+        assert_eq!(fit_width(&portrait, Some(720)).dimensions(), (720, 1280));
+        assert_eq!(fit_width(&portrait, Some(1280)).dimensions(), (1080, 1920));
+        assert_eq!(fit_width(&portrait, None).dimensions(), (1080, 1920));
+    }
+
+    #[test]
+    fn only_the_runs_tenant_may_cancel_or_watch_it() {
+        let registration = JobRegistration::register("tenant_scoped_job", None, Some("a"));
+        assert!(job_progress("tenant_scoped_job", Some("b")).is_none());
+        assert!(job_progress("tenant_scoped_job", None).is_none());
+        assert!(!cancel_job("tenant_scoped_job", Some("b")));
+        assert!(!registration.job.token.is_cancelled());
+
+        assert!(job_progress("tenant_scoped_job", Some("a")).is_some());
+        assert!(cancel_job("tenant_scoped_job", Some("a")));
+        assert!(registration.job.token.is_cancelled());
     }
-    dot / (ref_l2 * l2)
 }
@@ -0,0 +1,317 @@
+//! Where frame images, records and manifests are kept.
+//!
+//! `data/` on local disk by default. `STORAGE_BACKEND=s3` puts them in an S3-compatible bucket
+//! instead, so instances don't need a shared volume. Keys are the paths the files would have
+//! locally (`data/{video_id}_frame_001.jpg`), which is what `FrameRecord::path` holds.
+
+use anyhow::{Context, Result};
+use std::env;
+use std::sync::OnceLock;
+use tokio::fs;
+use tracing::warn;
+
+/// A place to keep frame images and JSON files under string keys.
+#[async_trait::async_trait]
+pub trait StorageBackend: Send + Sync {
+    /// Store `bytes` under `key`, replacing what was there.
+    async fn put(&self, key: &str, bytes: &[u8], content_type: &str) -> Result<()>;
+
+    /// What is stored under `key`, `None` if nothing is.
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>>;
+
+    /// Remove `key`; removing a key that holds nothing is not an error.
+    async fn delete(&self, key: &str) -> Result<()>;
+
+    /// URL a client can fetch `key` from.
+    async fn url(&self, key: &str) -> Result<String>;
+
+    /// Short description for logs
+    fn describe(&self) -> String;
+}
+
+/// Files under the working directory, served by the router under `/data`.
+#[derive(Debug, Default)]
+pub struct LocalStorage;
+
+#[async_trait::async_trait]
+impl StorageBackend for LocalStorage {
+    async fn put(&self, key: &str, bytes: &[u8], _content_type: &str) -> Result<()> {
+        fs::write(key, bytes)
+            .await
+            .with_context(|| format!("failed to write {}", key))
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        match fs::read(key).await {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("failed to read {}", key)),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        match fs::remove_file(key).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e).with_context(|| format!("failed to delete {}", key)),
+        }
+    }
+
+    async fn url(&self, key: &str) -> Result<String> {
+        Ok(format!("/{}", key))
+    }
+
+    fn describe(&self) -> String {
+        "local data directory".to_string()
+    }
+}
+
+/// Presigned URLs stay valid this long unless `S3_PRESIGN_SECS` says otherwise.
+const DEFAULT_PRESIGN_SECS: u64 = 3600;
+// The longest SigV4 allows
+const MAX_PRESIGN_SECS: u64 = 7 * 24 * 3600;
+
+/// An S3 bucket (AWS, MinIO, R2, ...), through the AWS SDK.
+#[derive(Debug)]
+pub struct S3Storage {
+    client: aws_sdk_s3::Client,
+    bucket: String,
+    /// Prepended to every key
+    prefix: String,
+    /// `S3_ENDPOINT`, for `describe`; `None` is AWS itself
+    endpoint: Option<String>,
+    /// Base URL of a public bucket or CDN; `url` presigns when unset
+    public_url: Option<String>,
+    presign_secs: u64,
+}
+
+impl S3Storage {
+    /// Settings from `S3_BUCKET`, `S3_REGION`, `S3_ENDPOINT`, `S3_PREFIX`, `S3_PUBLIC_URL` and
+    /// `S3_PRESIGN_SECS`. Credentials and, without `S3_REGION`, the region come from the AWS
+    /// SDK's usual chain: `AWS_ACCESS_KEY_ID` and `AWS_REGION`, profiles, instance roles, ...
+    pub async fn from_env() -> Result<Self> {
+        use aws_sdk_s3::config::{
+            BehaviorVersion, Region, RequestChecksumCalculation, ResponseChecksumValidation,
+        };
+
+        let var = |name| env::var(name).ok().filter(|v: &String| !v.is_empty());
+        let bucket = var("S3_BUCKET").context("S3_BUCKET must be set for the s3 backend")?;
+        if bucket.contains('/') {
+            anyhow::bail!("Invalid S3_BUCKET '{}'", bucket);
+        }
+        let presign_secs = match var("S3_PRESIGN_SECS") {
+            None => DEFAULT_PRESIGN_SECS,
+            Some(raw) => match raw.parse::<u64>() {
+                Ok(secs) if (1..=MAX_PRESIGN_SECS).contains(&secs) => secs,
+                _ => {
+                    let default = DEFAULT_PRESIGN_SECS;
+                    warn!("Invalid S3_PRESIGN_SECS value '{}'; using {}", raw, default);
+                    DEFAULT_PRESIGN_SECS
+                }
+            },
+        };
+
+        let sdk = aws_config::load_defaults(BehaviorVersion::latest()).await;
+        let region = var("S3_REGION")
+            .map(Region::new)
+            .or_else(|| sdk.region().cloned())
+            .unwrap_or_else(|| Region::new("us-east-1"));
+        let mut config = aws_sdk_s3::config::Builder::from(&sdk).region(region);
+        let endpoint = var("S3_ENDPOINT").map(|raw| raw.trim_end_matches('/').to_string());
+        if let Some(raw) = &endpoint {
+            let url = reqwest::Url::parse(raw)
+                .with_context(|| format!("Invalid S3_ENDPOINT '{}'", raw))?;
+            if !matches!(url.scheme(), "https" | "http") || url.host_str().is_none() {
+                anyhow::bail!("S3_ENDPOINT must be an http(s) URL: '{}'", raw);
+            }
+            // S3-compatible stores are addressed path-style, and not all of them accept the
+            // checksums the SDK adds to every request for AWS
+            config = config
+                .endpoint_url(raw)
+                .force_path_style(true)
+                .request_checksum_calculation(RequestChecksumCalculation::WhenRequired)
+                .response_checksum_validation(ResponseChecksumValidation::WhenRequired);
+        }
+        // Resolve credentials now, so a missing key fails at startup rather than on a frame
+        let credentials = sdk
+            .credentials_provider()
+            .context("no AWS credentials provider for the s3 backend")?;
+        aws_sdk_s3::config::ProvideCredentials::provide_credentials(&credentials)
+            .await
+            .context("no AWS credentials for the s3 backend")?;
+
+        Ok(Self {
+            client: aws_sdk_s3::Client::from_conf(config.build()),
+            bucket,
+            prefix: var("S3_PREFIX").unwrap_or_default(),
+            endpoint,
+            public_url: var("S3_PUBLIC_URL").map(|url| url.trim_end_matches('/').to_string()),
+            presign_secs,
+        })
+    }
+
+    fn object_key(&self, key: &str) -> String {
+        format!("{}{}", self.prefix, key)
+    }
+}
+
+#[async_trait::async_trait]
+impl StorageBackend for S3Storage {
+    async fn put(&self, key: &str, bytes: &[u8], content_type: &str) -> Result<()> {
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(self.object_key(key))
+            .content_type(content_type)
+            .body(aws_sdk_s3::primitives::ByteStream::from(bytes.to_vec()))
+            .send()
+            .await
+            .with_context(|| format!("failed to upload {} to S3", key))?;
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let result = self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(self.object_key(key))
+            .send()
+            .await;
+        let output = match result {
+            Ok(output) => output,
+            Err(e) if e.raw_response().is_some_and(|r| r.status().as_u16() == 404) => {
+                return Ok(None)
+            }
+            Err(e) => return Err(e).with_context(|| format!("failed to download {} from S3", key)),
+        };
+        let body = output
+            .body
+            .collect()
+            .await
+            .with_context(|| format!("failed to download {} from S3", key))?;
+        Ok(Some(body.into_bytes().to_vec()))
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        self.client
+            .delete_object()
+            .bucket(&self.bucket)
+            .key(self.object_key(key))
+            .send()
+            .await
+            .with_context(|| format!("failed to delete {} from S3", key))?;
+        Ok(())
+    }
+
+    async fn url(&self, key: &str) -> Result<String> {
+        if let Some(base) = &self.public_url {
+            return Ok(format!("{}/{}", base, uri_encode(&self.object_key(key))));
+        }
+        let expires = std::time::Duration::from_secs(self.presign_secs);
+        let presigned = self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(self.object_key(key))
+            .presigned(aws_sdk_s3::presigning::PresigningConfig::expires_in(expires)?)
+            .await
+            .with_context(|| format!("failed to presign {}", key))?;
+        Ok(presigned.uri().to_string())
+    }
+
+    fn describe(&self) -> String {
+        let endpoint = self.endpoint.as_deref().unwrap_or("AWS");
+        format!("s3://{}/{} at {}", self.bucket, self.prefix, endpoint)
+    }
+}
+
+// Percent-encoding of a key for a URL path: everything but unreserved characters and '/'
+fn uri_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+static STORAGE: OnceLock<Box<dyn StorageBackend>> = OnceLock::new();
+
+/// The backend chosen by `STORAGE_BACKEND`: `local` (the default) or `s3`.
+pub async fn from_env() -> Result<Box<dyn StorageBackend>> {
+    match env::var("STORAGE_BACKEND").ok().as_deref() {
+        None | Some("") | Some("local") => Ok(Box::new(LocalStorage)),
+        Some("s3") => Ok(Box::new(S3Storage::from_env().await?)),
+        Some(other) => anyhow::bail!(
+            "Invalid STORAGE_BACKEND value '{}'; expected local or s3",
+            other
+        ),
+    }
+}
+
+/// Set up the backend from the environment; called at startup so mistakes fail fast.
+pub async fn init() -> Result<&'static dyn StorageBackend> {
+    let backend = from_env().await?;
+    Ok(STORAGE.get_or_init(|| backend).as_ref())
+}
+
+/// The backend set up by `init`; local storage when `init` wasn't called first.
+pub fn storage() -> &'static dyn StorageBackend {
+    STORAGE
+        .get_or_init(|| {
+            let backend = env::var("STORAGE_BACKEND").unwrap_or_default();
+            if !matches!(&*backend, "" | "local") {
+                warn!("storage::init wasn't called; ignoring STORAGE_BACKEND={}", backend);
+            }
+            Box::new(LocalStorage)
+        })
+        .as_ref()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bucket(public_url: Option<&str>) -> S3Storage {
+        use aws_sdk_s3::config::{BehaviorVersion, Credentials, Region};
+
+        let config = aws_sdk_s3::Config::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .region(Region::new("us-east-1"))
+            .credentials_provider(Credentials::for_tests())
+            .endpoint_url("http://127.0.0.1:9000")
+            .force_path_style(true)
+            .build();
+        S3Storage {
+            client: aws_sdk_s3::Client::from_conf(config),
+            bucket: "frames".to_string(),
+            prefix: "runs/".to_string(),
+            endpoint: Some("http://127.0.0.1:9000".to_string()),
+            public_url: public_url.map(str::to_string),
+            presign_secs: 600,
+        }
+    }
+
+    #[tokio::test]
+    async fn keys_are_percent_encoded_in_public_urls() {
+        assert_eq!(uri_encode("data/a b+c_~.jpg"), "data/a%20b%2Bc_~.jpg");
+        assert_eq!(uri_encode("data/é.jpg"), "data/%C3%A9.jpg");
+        let storage = bucket(Some("https://cdn.example.com"));
+        let url = storage.url("data/video 1_frame_001.jpg").await.unwrap();
+        assert_eq!(url, "https://cdn.example.com/runs/data/video%201_frame_001.jpg");
+    }
+
+    #[tokio::test]
+    async fn private_buckets_get_presigned_urls() {
+        let storage = bucket(None);
+        let url = storage.url("data/video_frame_001.jpg").await.unwrap();
+        assert!(url.starts_with("http://127.0.0.1:9000/frames/runs/data/video_frame_001.jpg?"));
+        assert!(url.contains("X-Amz-Expires=600"));
+        assert!(url.contains("X-Amz-Signature="));
+    }
+}
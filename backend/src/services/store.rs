@@ -0,0 +1,375 @@
+//! SQLite store of analyses, so results survive restarts.
+//!
+//! The database is `DATABASE_URL` (default `sqlite://videos.db`, outside
+//! the `data/` directory served under `/data`), created on first use.
+
+use super::FrameRecord;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePool};
+use sqlx::Row;
+use std::str::FromStr;
+use tokio::sync::OnceCell;
+
+const DEFAULT_DATABASE_URL: &str = "sqlite://videos.db";
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS videos (
+    video_id TEXT PRIMARY KEY,
+    source_path TEXT NOT NULL,
+    summary TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    -- API key tenant that ran the analysis, NULL without API keys
+    tenant TEXT
+);
+CREATE TABLE IF NOT EXISTS frames (
+    video_id TEXT NOT NULL REFERENCES videos(video_id) ON DELETE CASCADE,
+    frame_id INTEGER NOT NULL,
+    timestamp REAL NOT NULL,
+    description TEXT NOT NULL,
+    path TEXT NOT NULL,
+    PRIMARY KEY (video_id, frame_id)
+);
+CREATE TABLE IF NOT EXISTS frame_embeddings (
+    video_id TEXT NOT NULL REFERENCES videos(video_id) ON DELETE CASCADE,
+    frame_id INTEGER NOT NULL,
+    -- Little-endian f32 values
+    embedding BLOB NOT NULL,
+    PRIMARY KEY (video_id, frame_id)
+);
+";
+
+static POOL: OnceCell<SqlitePool> = OnceCell::const_new();
+
+async fn pool() -> Result<&'static SqlitePool> {
+    POOL.get_or_try_init(|| async {
+        let url = std::env::var("DATABASE_URL")
+            .ok()
+            .filter(|url| !url.is_empty())
+            .unwrap_or_else(|| DEFAULT_DATABASE_URL.to_string());
+        let options = SqliteConnectOptions::from_str(&url)
+            .with_context(|| format!("Invalid DATABASE_URL '{}'", url))?
+            .create_if_missing(true)
+            .foreign_keys(true);
+        let pool = SqlitePool::connect_with(options)
+            .await
+            .with_context(|| format!("failed to open {}", url))?;
+        sqlx::raw_sql(SCHEMA)
+            .execute(&pool)
+            .await
+            .context("failed to create the store schema")?;
+        // Databases from before `tenant` existed get the column, their rows no owner
+        let has_tenant: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM pragma_table_info('videos') WHERE name = 'tenant'",
+        )
+        .fetch_one(&pool)
+        .await?;
+        if has_tenant == 0 {
+            sqlx::query("ALTER TABLE videos ADD COLUMN tenant TEXT")
+                .execute(&pool)
+                .await
+                .context("failed to add videos.tenant")?;
+        }
+        Ok(pool)
+    })
+    .await
+}
+
+/// A stored analysis, as returned by `GET /videos/{video_id}`.
+#[derive(Debug, Serialize)]
+pub struct StoredVideo {
+    pub video_id: String,
+    pub source_path: String,
+    pub summary: String,
+    /// RFC 3339 time the analysis was saved
+    pub created_at: String,
+    /// In `frame_id` order
+    pub frames: Vec<StoredFrame>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StoredFrame {
+    pub frame_id: u64,
+    pub timestamp: f64,
+    pub description: String,
+    pub path: String,
+}
+
+/// Save a finished analysis of `tenant`, replacing an earlier one with the same `video_id`.
+pub async fn save_analysis(
+    video_id: &str,
+    tenant: Option<&str>,
+    source_path: &str,
+    summary: &str,
+    records: &[FrameRecord],
+) -> Result<()> {
+    let mut tx = pool().await?.begin().await?;
+    for table in ["frames", "frame_embeddings"] {
+        sqlx::query(&format!("DELETE FROM {} WHERE video_id = ?", table))
+            .bind(video_id)
+            .execute(&mut *tx)
+            .await?;
+    }
+    sqlx::query(
+        "INSERT OR REPLACE INTO videos (video_id, source_path, summary, created_at, tenant) VALUES (?, ?, ?, ?, ?)",
+    )
+    .bind(video_id)
+    .bind(source_path)
+    .bind(summary)
+    // Fixed width so `list_videos` can sort the text
+    .bind(chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Micros, true))
+    .bind(tenant)
+    .execute(&mut *tx)
+    .await?;
+    for record in records {
+        sqlx::query(
+            "INSERT INTO frames (video_id, frame_id, timestamp, description, path) VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind(video_id)
+        .bind(record.frame_id as i64)
+        .bind(record.timestamp)
+        .bind(&record.description)
+        .bind(&record.path)
+        .execute(&mut *tx)
+        .await?;
+        if let Some(embedding) = &record.embedding {
+            let blob: Vec<u8> = embedding.iter().flat_map(|v| v.to_le_bytes()).collect();
+            sqlx::query("INSERT INTO frame_embeddings (video_id, frame_id, embedding) VALUES (?, ?, ?)")
+                .bind(video_id)
+                .bind(record.frame_id as i64)
+                .bind(blob)
+                .execute(&mut *tx)
+                .await?;
+        }
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Whether `STORE_PROGRESS` asks `process_video` to save each frame as soon as it is
+/// described, so `GET /videos/{video_id}` shows a long run's progress. Off by default.
+pub fn progress_enabled() -> bool {
+    matches!(std::env::var("STORE_PROGRESS").as_deref(), Ok("true" | "1" | "yes"))
+}
+
+/// Start the stored analysis of a run: an empty summary and no frames, replacing an earlier
+/// analysis with the same `video_id`. `upsert_frame` then adds the frames as they complete,
+/// and `save_analysis` replaces everything with the finished result.
+pub async fn begin_analysis(
+    video_id: &str,
+    tenant: Option<&str>,
+    source_path: &str,
+) -> Result<()> {
+    save_analysis(video_id, tenant, source_path, "", &[]).await
+}
+
+/// Save one frame of a run started with `begin_analysis`, replacing an earlier version.
+pub async fn upsert_frame(video_id: &str, record: &FrameRecord) -> Result<()> {
+    sqlx::query(
+        "INSERT OR REPLACE INTO frames (video_id, frame_id, timestamp, description, path) VALUES (?, ?, ?, ?, ?)",
+    )
+    .bind(video_id)
+    .bind(record.frame_id as i64)
+    .bind(record.timestamp)
+    .bind(&record.description)
+    .bind(&record.path)
+    .execute(pool().await?)
+    .await?;
+    Ok(())
+}
+
+/// One entry of `list_videos`.
+#[derive(Debug, Serialize)]
+pub struct VideoListing {
+    pub video_id: String,
+    pub summary: String,
+    pub frame_count: u64,
+    pub created_at: String,
+}
+
+/// Stored analyses, newest first, skipping `offset` and returning at most `limit`.
+pub async fn list_videos(limit: u32, offset: u32) -> Result<Vec<VideoListing>> {
+    let rows = sqlx::query(
+        "SELECT v.video_id, v.summary, v.created_at, COUNT(f.frame_id) AS frame_count
+         FROM videos v LEFT JOIN frames f ON f.video_id = v.video_id
+         GROUP BY v.video_id
+         ORDER BY v.created_at DESC, v.video_id
+         LIMIT ? OFFSET ?",
+    )
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(pool().await?)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|row| VideoListing {
+            video_id: row.get("video_id"),
+            summary: row.get("summary"),
+            frame_count: row.get::<i64, _>("frame_count") as u64,
+            created_at: row.get("created_at"),
+        })
+        .collect())
+}
+
+/// The stored analysis of `video_id`, `None` if there is none.
+pub async fn load_video(video_id: &str) -> Result<Option<StoredVideo>> {
+    let pool = pool().await?;
+    let Some(row) =
+        sqlx::query("SELECT source_path, summary, created_at FROM videos WHERE video_id = ?")
+            .bind(video_id)
+            .fetch_optional(pool)
+            .await?
+    else {
+        return Ok(None);
+    };
+    let frames = sqlx::query(
+        "SELECT frame_id, timestamp, description, path FROM frames WHERE video_id = ? ORDER BY frame_id",
+    )
+    .bind(video_id)
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|frame| StoredFrame {
+        frame_id: frame.get::<i64, _>("frame_id") as u64,
+        timestamp: frame.get("timestamp"),
+        description: frame.get("description"),
+        path: frame.get("path"),
+    })
+    .collect();
+    Ok(Some(StoredVideo {
+        video_id: video_id.to_string(),
+        source_path: row.get("source_path"),
+        summary: row.get("summary"),
+        created_at: row.get("created_at"),
+        frames,
+    }))
+}
+
+/// Tenant that saved the analysis of `video_id`: `None` if there is none, `Some(None)`
+/// if it was saved without API keys (or before owners were recorded).
+pub async fn video_tenant(video_id: &str) -> Result<Option<Option<String>>> {
+    let tenant: Option<Option<String>> =
+        sqlx::query_scalar("SELECT tenant FROM videos WHERE video_id = ?")
+            .bind(video_id)
+            .fetch_optional(pool().await?)
+            .await?;
+    Ok(tenant)
+}
+
+/// Remove the stored analysis of `video_id`; false if there was none.
+pub async fn delete_video(video_id: &str) -> Result<bool> {
+    // Frames go with it through `ON DELETE CASCADE`
+    let deleted = sqlx::query("DELETE FROM videos WHERE video_id = ?")
+        .bind(video_id)
+        .execute(pool().await?)
+        .await?;
+    Ok(deleted.rows_affected() > 0)
+}
+
+/// Stored frames of `video_id` that have an embedding, with the embedding.
+pub async fn load_embeddings(video_id: &str) -> Result<Vec<(StoredFrame, Vec<f32>)>> {
+    let rows = sqlx::query(
+        "SELECT f.frame_id, f.timestamp, f.description, f.path, e.embedding
+         FROM frames f JOIN frame_embeddings e ON e.video_id = f.video_id AND e.frame_id = f.frame_id
+         WHERE f.video_id = ? ORDER BY f.frame_id",
+    )
+    .bind(video_id)
+    .fetch_all(pool().await?)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            let blob: Vec<u8> = row.get("embedding");
+            let embedding = blob
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .collect();
+            let frame = StoredFrame {
+                frame_id: row.get::<i64, _>("frame_id") as u64,
+                timestamp: row.get("timestamp"),
+                description: row.get("description"),
+                path: row.get("path"),
+            };
+            (frame, embedding)
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(frame_id: u64, description: &str) -> FrameRecord {
+        FrameRecord {
+            frame_id,
+            timestamp: frame_id as f64,
+            description: description.to_string(),
+            path: format!("data/video_frame_{:03}.jpg", frame_id),
+            ..Default::default()
+        }
+    }
+
+    // The pool is process-wide, so every store test runs on the runtime that opened it,
+    // against a database of its own
+    fn block_on<F: std::future::Future>(test: F) -> F::Output {
+        static RUNTIME: std::sync::OnceLock<tokio::runtime::Runtime> = std::sync::OnceLock::new();
+        let runtime = RUNTIME.get_or_init(|| {
+            let dir = std::env::temp_dir().join(format!("store-test-{}", std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+            std::env::set_var("DATABASE_URL", format!("sqlite://{}/videos.db", dir.display()));
+            tokio::runtime::Runtime::new().unwrap()
+        });
+        runtime.block_on(test)
+    }
+
+    #[test]
+    fn frames_are_readable_before_the_analysis_is_saved() {
+        block_on(frames_are_readable_before_saving())
+    }
+
+    async fn frames_are_readable_before_saving() {
+        save_analysis("video", None, "old.mp4", "old summary", &[record(9, "old")]).await.unwrap();
+        begin_analysis("video", None, "new.mp4").await.unwrap();
+        upsert_frame("video", &record(2, "second")).await.unwrap();
+        upsert_frame("video", &record(1, "first")).await.unwrap();
+        upsert_frame("video", &record(2, "second again")).await.unwrap();
+
+        let video = load_video("video").await.unwrap().unwrap();
+        assert_eq!(video.summary, "");
+        assert_eq!(video.source_path, "new.mp4");
+        let frames: Vec<_> = video.frames.iter().map(|f| f.description.as_str()).collect();
+        assert_eq!(frames, ["first", "second again"]);
+
+        save_analysis("video", None, "new.mp4", "summary", &[record(1, "done")]).await.unwrap();
+        let video = load_video("video").await.unwrap().unwrap();
+        assert_eq!(video.summary, "summary");
+        assert_eq!(video.frames.len(), 1);
+    }
+
+    #[test]
+    fn non_latin_text_survives_the_store() {
+        block_on(async {
+            let summary = "駐車場に車が入ってくる。 سيارة تدخل موقف السيارات.";
+            save_analysis("languages", None, "video.mp4", summary, &[record(1, "車が止まる")])
+                .await
+                .unwrap();
+            let video = load_video("languages").await.unwrap().unwrap();
+            assert_eq!(video.summary, summary);
+            assert_eq!(video.frames[0].description, "車が止まる");
+            // As `GET /videos/{video_id}` returns it: UTF-8, not `\u` escapes
+            let json = serde_json::to_string(&video).unwrap();
+            assert!(json.contains(summary));
+        })
+    }
+
+    #[test]
+    fn analyses_remember_their_tenant() {
+        block_on(async {
+            save_analysis("owned", Some("acme"), "video.mp4", "", &[]).await.unwrap();
+            assert_eq!(video_tenant("owned").await.unwrap(), Some(Some("acme".to_string())));
+            save_analysis("unowned", None, "video.mp4", "", &[]).await.unwrap();
+            assert_eq!(video_tenant("unowned").await.unwrap(), Some(None));
+            assert_eq!(video_tenant("missing").await.unwrap(), None);
+        })
+    }
+}